@@ -0,0 +1,347 @@
+//! [Authenticated Received Chain] (ARC)
+//!
+//! Parsers for the `"ARC-Seal:"`, `"ARC-Message-Signature:"` and
+//! `"ARC-Authentication-Results:"` headers and grouping of these
+//! headers into ARC sets.
+//!
+//! Chain validation is only available with the `dkim` feature.
+//!
+//! [Authenticated Received Chain]: https://tools.ietf.org/html/rfc8617
+
+use std::str;
+
+use nom::bytes::complete::{tag, take_while1};
+use nom::combinator::{map, map_res, opt};
+use nom::sequence::{pair, terminated, tuple};
+
+use crate::behaviour::Intl;
+use crate::headersection::{split_message, Field};
+use crate::rfc5234::crlf;
+use crate::rfc5322::ofws;
+use crate::rfc6376::{check_duplicates, decode_base64, get_tag, parse_number, tag_list, DKIMSignature, SigningAlgorithm};
+use crate::rfc8601::{authentication_results, AuthenticationResults};
+use crate::types::Domain;
+use crate::util::*;
+
+#[cfg(feature = "dkim")]
+use crate::dkim;
+#[cfg(feature = "dkim")]
+use crate::dns::Resolver;
+#[cfg(feature = "dkim")]
+use crate::rfc6376::Canonicalization;
+#[cfg(feature = "dkim")]
+use crate::rfc8601::{AuthResult, ResInfo};
+
+/// The maximum number of ARC sets in a chain.
+pub const MAX_INSTANCE: u32 = 50;
+
+/// The chain validation status from the `"cv="` tag of an ARC seal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChainValidation {
+    /// `"none"`, only valid for the first instance.
+    None,
+    /// `"pass"`
+    Pass,
+    /// `"fail"`
+    Fail,
+}
+
+/// The content of an `"ARC-Seal:"` header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ARCSeal {
+    /// The instance number from `"i="`.
+    pub instance: u32,
+    /// The signing algorithm from `"a="`.
+    pub algorithm: SigningAlgorithm,
+    /// The decoded signature from `"b="`.
+    pub signature: Vec<u8>,
+    /// The chain validation status from `"cv="`.
+    pub chain_validation: ChainValidation,
+    /// The signing domain from `"d="`.
+    pub domain: Domain,
+    /// The selector from `"s="`.
+    pub selector: String,
+    /// The signature timestamp from `"t="`.
+    pub timestamp: Option<u64>,
+}
+
+/// The content of an `"ARC-Message-Signature:"` header.
+///
+/// The signature uses the same tags as a DKIM signature except for
+/// the version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ARCMessageSignature {
+    /// The instance number from `"i="`.
+    pub instance: u32,
+    /// The signature. The identity is always the signing domain.
+    pub signature: DKIMSignature,
+}
+
+/// The content of an `"ARC-Authentication-Results:"` header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ARCAuthenticationResults {
+    /// The instance number from `"i="`.
+    pub instance: u32,
+    /// The authentication results.
+    pub results: AuthenticationResults,
+}
+
+fn parse_instance(value: &str) -> Result<u32, &'static str> {
+    match parse_number(value) {
+        Ok(i) if i >= 1 && i <= MAX_INSTANCE as u64 => Ok(i as u32),
+        _ => Err("Invalid instance"),
+    }
+}
+
+impl ARCSeal {
+    /// Build a seal from a parsed tag list.
+    ///
+    /// Fails if a required tag is missing, a tag is duplicated, a tag
+    /// value is invalid or if `"h="` is present.
+    pub fn from_tags(tags: &[(String, String)]) -> Result<Self, &'static str> {
+        check_duplicates(tags)?;
+        let get = |name: &str| get_tag(tags, name);
+
+        if get("h").is_some() {
+            return Err("h= is not allowed in a seal");
+        }
+        let chain_validation = match &get("cv").ok_or("Missing cv=")?.to_ascii_lowercase()[..] {
+            "none" => ChainValidation::None,
+            "pass" => ChainValidation::Pass,
+            "fail" => ChainValidation::Fail,
+            _ => return Err("Invalid cv="),
+        };
+
+        Ok(ARCSeal {
+            instance: parse_instance(get("i").ok_or("Missing i=")?)?,
+            algorithm: get("a").ok_or("Missing a=")?.into(),
+            signature: decode_base64(get("b").ok_or("Missing b=")?)?,
+            chain_validation,
+            domain: Domain::from_smtp(get("d").ok_or("Missing d=")?.as_bytes()).map_err(|_| "Invalid d=")?,
+            selector: get("s").ok_or("Missing s=")?.into(),
+            timestamp: get("t").map(parse_number).transpose()?,
+        })
+    }
+}
+
+impl ARCMessageSignature {
+    /// Build a message signature from a parsed tag list.
+    ///
+    /// Fails under the same conditions as
+    /// [`DKIMSignature::from_tags`] except that there is no version
+    /// tag and From does not need to be signed.
+    pub fn from_tags(tags: &[(String, String)]) -> Result<Self, &'static str> {
+        Ok(ARCMessageSignature {
+            instance: parse_instance(get_tag(tags, "i").ok_or("Missing i=")?)?,
+            signature: DKIMSignature::from_tags_with(tags, true)?,
+        })
+    }
+}
+
+/// Parse the content of an `"ARC-Seal:"` header.
+pub fn arc_seal(input: &[u8]) -> NomResult<'_, ARCSeal> {
+    map_res(terminated(tag_list, opt(crlf)), |tags| ARCSeal::from_tags(&tags))(input)
+}
+
+/// Parse the content of an `"ARC-Message-Signature:"` header.
+pub fn arc_message_signature(input: &[u8]) -> NomResult<'_, ARCMessageSignature> {
+    map_res(terminated(tag_list, opt(crlf)), |tags| ARCMessageSignature::from_tags(&tags))(input)
+}
+
+/// Parse the content of an `"ARC-Authentication-Results:"` header.
+/// # Examples
+/// ```
+/// use rustyknife::arc::arc_authentication_results;
+///
+/// let (_, aar) = arc_authentication_results(b" i=1; mx.example.org; spf=pass smtp.mailfrom=example.com\r\n").unwrap();
+/// assert_eq!(aar.instance, 1);
+/// assert_eq!(aar.results.authserv_id, "mx.example.org");
+/// ```
+pub fn arc_authentication_results(input: &[u8]) -> NomResult<'_, ARCAuthenticationResults> {
+    map(pair(map_res(tuple((ofws, tag("i"), ofws, tag("="), ofws, take_while1(|c: u8| c.is_ascii_digit()), ofws, tag(";"))),
+                     |(_, _, _, _, _, i, _, _)| parse_instance(str::from_utf8(i).unwrap())),
+             authentication_results::<Intl>),
+        |(instance, results)| ARCAuthenticationResults { instance, results })(input)
+}
+
+/// A complete ARC set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ARCSet {
+    /// The instance number shared by the three headers.
+    pub instance: u32,
+    /// The `"ARC-Authentication-Results:"` header.
+    pub authentication_results: ARCAuthenticationResults,
+    /// The `"ARC-Message-Signature:"` header.
+    pub message_signature: ARCMessageSignature,
+    /// The `"ARC-Seal:"` header.
+    pub seal: ARCSeal,
+}
+
+// Header indexes of the results, message signature and seal.
+type SetIndexes = [usize; 3];
+
+const HEADER_NAMES: [&str; 3] = ["ARC-Authentication-Results", "ARC-Message-Signature", "ARC-Seal"];
+
+fn group_sets(fields: &[Field<'_>]) -> Result<Vec<(ARCSet, SetIndexes)>, &'static str> {
+    let mut found: Vec<[Option<usize>; 3]> = Vec::new();
+    let mut aars = Vec::new();
+    let mut amss = Vec::new();
+    let mut seals = Vec::new();
+
+    for (index, (name, value)) in fields.iter().enumerate() {
+        let kind = match HEADER_NAMES.iter().position(|h| name.eq_ignore_ascii_case(h.as_bytes())) {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let instance = match kind {
+            0 => arc_authentication_results(value).map(|(_, p)| { let i = p.instance; aars.push(p); i }),
+            1 => arc_message_signature(value).map(|(_, p)| { let i = p.instance; amss.push(p); i }),
+            _ => arc_seal(value).map(|(_, p)| { let i = p.instance; seals.push(p); i }),
+        }.map_err(|_| "Invalid ARC header")? as usize;
+
+        if found.len() < instance {
+            found.resize(instance, [None; 3]);
+        }
+        if found[instance-1][kind].replace(index).is_some() {
+            return Err("Duplicate ARC header");
+        }
+    }
+
+    found.iter().enumerate().map(|(i, indexes)| {
+        let instance = i as u32 + 1;
+        match indexes {
+            [Some(aar), Some(ams), Some(seal)] => Ok((ARCSet {
+                instance,
+                authentication_results: aars.iter().find(|p| p.instance == instance).unwrap().clone(),
+                message_signature: amss.iter().find(|p| p.instance == instance).unwrap().clone(),
+                seal: seals.iter().find(|p| p.instance == instance).unwrap().clone(),
+            }, [*aar, *ams, *seal])),
+            _ => Err("Incomplete ARC set"),
+        }
+    }).collect()
+}
+
+/// Group the ARC headers of a message into sets, ordered by instance.
+///
+/// Fails if an ARC header is invalid, if an instance has a missing or
+/// duplicated header or if an instance is missing.
+pub fn arc_sets(message: &[u8]) -> Result<Vec<ARCSet>, &'static str> {
+    let (fields, _) = split_message(message)?;
+    Ok(group_sets(&fields)?.into_iter().map(|(set, _)| set).collect())
+}
+
+/// The result of validating an ARC chain.
+#[cfg(feature = "dkim")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainResult {
+    /// The validation result. One of [`AuthResult::None`],
+    /// [`AuthResult::Pass`], [`AuthResult::Fail`] or
+    /// [`AuthResult::TempError`].
+    pub result: AuthResult,
+    /// The reason for a result other than pass or none.
+    pub reason: Option<&'static str>,
+    /// The ARC sets, empty if they could not be grouped.
+    pub sets: Vec<ARCSet>,
+}
+
+#[cfg(feature = "dkim")]
+impl ChainResult {
+    /// Convert to a result for an `"Authentication-Results:"` header.
+    pub fn resinfo(&self) -> ResInfo {
+        ResInfo {
+            method: "arc".into(),
+            method_version: None,
+            result: self.result.clone(),
+            reason: self.reason.map(String::from),
+            properties: Vec::new(),
+            comments: Vec::new(),
+        }
+    }
+}
+
+// The data covered by the seal of `sets[last]`.
+#[cfg(feature = "dkim")]
+fn seal_data(fields: &[Field<'_>], sets: &[(ARCSet, SetIndexes)], last: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for (i, (_, indexes)) in sets[..=last].iter().enumerate() {
+        for (kind, &index) in indexes.iter().enumerate() {
+            let (name, value) = fields[index];
+            if i == last && kind == 2 {
+                out.extend(dkim::canonicalize_header(Canonicalization::Relaxed, name, &dkim::strip_signature(value)));
+            } else {
+                out.extend(dkim::canonicalize_header(Canonicalization::Relaxed, name, value));
+            }
+        }
+    }
+    out.truncate(out.len() - 2);
+
+    out
+}
+
+// ARC only has pass and fail results, temporary errors aside.
+#[cfg(feature = "dkim")]
+fn chain_failure((result, reason): dkim::Failure) -> dkim::Failure {
+    (if result == AuthResult::TempError { result } else { AuthResult::Fail }, reason)
+}
+
+#[cfg(feature = "dkim")]
+fn validate_sets<R: Resolver>(fields: &[Field<'_>], body: &[u8], sets: &[(ARCSet, SetIndexes)], resolver: &R,
+                                 now: u64) -> Result<(), dkim::Failure>
+{
+    let fail = |reason| (AuthResult::Fail, reason);
+    let (latest, latest_indexes) = sets.last().unwrap();
+
+    if latest.seal.chain_validation == ChainValidation::Fail {
+        return Err(fail("Chain already failed"));
+    }
+    for (set, _) in sets {
+        let expected = if set.instance == 1 { ChainValidation::None } else { ChainValidation::Pass };
+        if set.seal.chain_validation != expected {
+            return Err(fail("Invalid cv="));
+        }
+    }
+
+    dkim::check_signature(fields, latest_indexes[1], &latest.message_signature.signature, body, resolver, now)
+        .map_err(chain_failure)?;
+
+    for (i, (set, _)) in sets.iter().enumerate().rev() {
+        let key = dkim::fetch_key(resolver, &set.seal.selector, &set.seal.domain).map_err(chain_failure)?;
+        dkim::verify_data(&set.seal.algorithm, &key, &seal_data(fields, sets, i), &set.seal.signature)
+            .map_err(chain_failure)?;
+    }
+
+    Ok(())
+}
+
+/// Validate the ARC chain of a message.
+///
+/// Returns [`AuthResult::None`] if the message has no ARC headers.
+/// Only the most recent message signature is verified while every
+/// seal is verified.
+///
+/// `now` is the current UNIX time, used to check the expiration of
+/// the message signature.
+#[cfg(feature = "dkim")]
+pub fn validate<R: Resolver>(message: &[u8], resolver: &R, now: u64) -> ChainResult {
+    let failed = |reason| ChainResult { result: AuthResult::Fail, reason: Some(reason), sets: Vec::new() };
+
+    let (fields, body) = match split_message(message) {
+        Ok(split) => split,
+        Err(reason) => return failed(reason),
+    };
+    let sets = match group_sets(&fields) {
+        Ok(sets) => sets,
+        Err(reason) => return failed(reason),
+    };
+    if sets.is_empty() {
+        return ChainResult { result: AuthResult::None, reason: None, sets: Vec::new() };
+    }
+
+    let (result, reason) = match validate_sets(&fields, body, &sets, resolver, now) {
+        Ok(()) => (AuthResult::Pass, None),
+        Err((result, reason)) => (result, Some(reason)),
+    };
+
+    ChainResult { result, reason, sets: sets.into_iter().map(|(set, _)| set).collect() }
+}
@@ -0,0 +1,197 @@
+//! Attachment extraction
+//!
+//! Walks a [MIME entity tree] and returns the decoded content of
+//! every attachment along with a filename that is safe to use on
+//! common file systems.
+//!
+//! A leaf entity is taken as an attachment if any of the following
+//! holds:
+//! - Its `"Content-Disposition:"` is `"attachment"`.
+//! - It has a filename, either from the `"Content-Disposition:"`
+//!   `"filename"` parameter or from the `"Content-Type:"` `"name"`
+//!   parameter.
+//! - It is not a `"text/*"` entity.
+//!
+//! Inline text parts without a filename are considered message
+//! bodies and are skipped. Forwarded `"message/rfc822"` and
+//! `"message/global"` entities are returned as attachments and the
+//! attachments inside them follow.
+//!
+//! [MIME entity tree]: crate::mime
+
+use crate::mime::Entity;
+use crate::rfc2047::decode_embedded;
+use crate::rfc2231::{ContentDisposition, MediaType};
+
+/// The longest sanitized filename in bytes.
+pub const MAX_FILENAME_LEN: usize = 255;
+
+/// An attachment found by [`attachments`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment<'e, 'a> {
+    /// The entity holding the attachment.
+    pub entity: &'e Entity<'a>,
+    /// The declared media type.
+    pub content_type: &'e MediaType,
+    /// The filename as declared, with RFC 2231 and RFC 2047 encoding
+    /// undone.
+    pub raw_filename: Option<String>,
+    /// The filename made safe by [`sanitize_filename`].
+    pub filename: Option<String>,
+    /// The content with the transfer encoding undone.
+    pub data: Vec<u8>,
+    /// The first problem found while undoing the transfer encoding.
+    pub transfer_error: Option<&'static str>,
+}
+
+/// Return the filename declared for an entity.
+///
+/// The `"filename"` parameter of the `"Content-Disposition:"` header
+/// is preferred over the `"name"` parameter of the `"Content-Type:"`
+/// header. Encoded words left in the value are decoded, as some
+/// mailers use them instead of RFC 2231 encoding.
+pub fn declared_filename(entity: &Entity) -> Option<String> {
+    let disposition = entity.disposition();
+    let filename = disposition.as_ref()
+        .and_then(|(_, params)| params.iter().find(|(n, _)| n == "filename"))
+        .map(|(_, v)| v.as_str());
+
+    filename.or_else(|| entity.content_type.name())
+        .map(decode_embedded)
+        .filter(|f| !f.trim().is_empty())
+}
+
+fn is_reserved(stem: &str) -> bool {
+    const RESERVED: [&str; 4] = ["con", "prn", "aux", "nul"];
+    let stem = stem.trim_end().to_ascii_lowercase();
+
+    RESERVED.contains(&&stem[..])
+        || ((stem.starts_with("com") || stem.starts_with("lpt"))
+            && stem.len() == 4 && stem.as_bytes()[3].is_ascii_digit())
+}
+
+/// Make a filename safe to create on common file systems.
+///
+/// - Directory components are removed.
+/// - Control characters and characters reserved on Windows are
+///   replaced with `"_"`.
+/// - Leading and trailing whitespace and dots are removed.
+/// - Reserved device names such as `"CON"` or `"lpt1.txt"` are
+///   prefixed with `"_"`.
+/// - The name is truncated to [`MAX_FILENAME_LEN`] bytes, keeping the
+///   extension if it is short.
+///
+/// Returns `None` if nothing is left.
+/// # Examples
+/// ```
+/// use rustyknife::attachment::sanitize_filename;
+///
+/// assert_eq!(sanitize_filename("../../etc/passwd"), Some("passwd".into()));
+/// assert_eq!(sanitize_filename("C:\\Users\\me\\report?.pdf"), Some("report_.pdf".into()));
+/// assert_eq!(sanitize_filename("nul.txt"), Some("_nul.txt".into()));
+/// assert_eq!(sanitize_filename(" .. "), None);
+/// ```
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base.chars()
+        .map(|c| if c.is_control() || "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    let mut out = cleaned.trim_matches(|c: char| c.is_whitespace() || c == '.').to_string();
+
+    if out.is_empty() {
+        return None;
+    }
+    if is_reserved(out.split('.').next().unwrap()) {
+        out.insert(0, '_');
+    }
+
+    if out.len() > MAX_FILENAME_LEN {
+        let ext = out.rfind('.').map(|i| out[i..].to_string()).filter(|e| e.len() <= 16).unwrap_or_default();
+        let mut end = MAX_FILENAME_LEN - ext.len();
+        while !out.is_char_boundary(end) {
+            end -= 1;
+        }
+        out = format!("{}{}", out[..end].trim_end(), ext);
+    }
+
+    Some(out)
+}
+
+// Multipart entities that could not be split are neither bodies nor
+// attachments.
+fn is_attachment(entity: &Entity, filename: &Option<String>) -> bool {
+    let disposed = matches!(entity.disposition(), Some((ContentDisposition::Attachment, _)));
+
+    !entity.content_type.is_multipart() && (disposed || filename.is_some() || !entity.content_type.is_text())
+}
+
+/// Iterator over the attachments of an entity tree, see [`attachments`].
+pub struct Attachments<'e, 'a> {
+    message: &'e [u8],
+    stack: Vec<&'e Entity<'a>>,
+}
+
+impl<'e, 'a> Iterator for Attachments<'e, 'a> {
+    type Item = Attachment<'e, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entity) = self.stack.pop() {
+            if let Some(multipart) = &entity.multipart {
+                self.stack.extend(multipart.parts.iter().rev());
+                continue;
+            }
+            // The entity of an external body has no content here.
+            if let Some(nested) = &entity.message {
+                if entity.content_type.essence() != "message/external-body" {
+                    self.stack.push(nested);
+                }
+            }
+
+            let raw_filename = declared_filename(entity);
+            if !is_attachment(entity, &raw_filename) {
+                continue;
+            }
+
+            let (data, transfer_error) = entity.decode_body(self.message);
+            return Some(Attachment {
+                entity,
+                content_type: &entity.content_type,
+                filename: raw_filename.as_deref().and_then(sanitize_filename),
+                raw_filename,
+                data,
+                transfer_error,
+            });
+        }
+
+        None
+    }
+}
+
+/// Iterate over the attachments of a parsed message in order.
+///
+/// `message` is the message given to [`crate::mime::parse`]. Bodies
+/// are decoded as the iterator advances.
+/// # Examples
+/// ```
+/// use rustyknife::attachment::attachments;
+/// use rustyknife::mime::parse;
+///
+/// let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+///                 --b\r\n\r\nSee attached.\r\n\
+///                 --b\r\n\
+///                 Content-Type: application/pdf\r\n\
+///                 Content-Disposition: attachment; filename*=utf-8''%C3%A9t%C3%A9.pdf\r\n\
+///                 Content-Transfer-Encoding: base64\r\n\r\n\
+///                 JVBERi0=\r\n\
+///                 --b--\r\n";
+///
+/// let root = parse(message);
+/// let found: Vec<_> = attachments(&root, message).collect();
+/// assert_eq!(found.len(), 1);
+/// assert_eq!(found[0].filename.as_deref(), Some("été.pdf"));
+/// assert_eq!(found[0].content_type.essence(), "application/pdf");
+/// assert_eq!(found[0].data, b"%PDF-");
+/// ```
+pub fn attachments<'e, 'a>(root: &'e Entity<'a>, message: &'e [u8]) -> Attachments<'e, 'a> {
+    Attachments { message, stack: vec![root] }
+}
@@ -0,0 +1,507 @@
+//! [DKIM] signing and verification
+//!
+//! Supports the `rsa-sha256`, `rsa-sha1` and [RFC 8463] `ed25519-sha256`
+//! algorithms with simple and relaxed canonicalization. Signatures
+//! are parsed by the [crate::rfc6376] module.
+//!
+//! Public keys are looked up through a [`Resolver`], which allows
+//! plugging in any DNS client.
+//!
+//! Only available with the `dkim` feature.
+//!
+//! [DKIM]: https://tools.ietf.org/html/rfc6376
+//! [RFC 8463]: https://tools.ietf.org/html/rfc8463
+
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair, KeyPair, RsaKeyPair, UnparsedPublicKey};
+
+use crate::dns::{LookupError, Resolver};
+use crate::headersection::{split_message, Field};
+use crate::headerwriter::fold_words;
+use crate::rfc6376::{dkim_key, dkim_signature, Canonicalization, DKIMKey, DKIMSignature, SigningAlgorithm};
+use crate::rfc8601::{AuthResult, Property, ResInfo};
+use crate::types::Domain;
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+// Replace each run of whitespace with a single space and remove
+// trailing whitespace.
+fn compress_wsp(input: &[u8], out: &mut Vec<u8>) {
+    let mut in_wsp = false;
+
+    for &c in input {
+        if is_wsp(c) {
+            in_wsp = true;
+        } else {
+            if in_wsp {
+                out.push(b' ');
+            }
+            in_wsp = false;
+            out.push(c);
+        }
+    }
+}
+
+/// Canonicalize a header field.
+///
+/// `value` is the raw value following the colon, without the
+/// terminating CRLF. The result includes the terminating CRLF.
+pub fn canonicalize_header(canon: Canonicalization, name: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(name.len() + value.len() + 3);
+
+    match canon {
+        Canonicalization::Simple => {
+            out.extend_from_slice(name);
+            out.push(b':');
+            out.extend_from_slice(value);
+        }
+        Canonicalization::Relaxed => {
+            let name_end = name.iter().rposition(|&c| !is_wsp(c)).map(|i| i + 1).unwrap_or(0);
+            out.extend(name[..name_end].iter().map(u8::to_ascii_lowercase));
+            out.push(b':');
+
+            let unfolded: Vec<u8> = value.iter().cloned().filter(|&c| c != b'\r' && c != b'\n').collect();
+            let start = unfolded.iter().position(|&c| !is_wsp(c)).unwrap_or(unfolded.len());
+            compress_wsp(&unfolded[start..], &mut out);
+        }
+    }
+    out.extend_from_slice(b"\r\n");
+
+    out
+}
+
+/// Canonicalize a message body.
+pub fn canonicalize_body(canon: Canonicalization, body: &[u8]) -> Vec<u8> {
+    let mut lines = Vec::new();
+    let mut rem = body;
+
+    while !rem.is_empty() {
+        let end = rem.windows(2).position(|w| w == b"\r\n").unwrap_or(rem.len());
+        lines.push(&rem[..end]);
+        rem = &rem[(end + 2).min(rem.len())..];
+    }
+
+    let mut out = Vec::with_capacity(body.len() + 2);
+    for line in lines {
+        match canon {
+            Canonicalization::Simple => out.extend_from_slice(line),
+            Canonicalization::Relaxed => compress_wsp(line, &mut out),
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    while out.ends_with(b"\r\n\r\n") {
+        out.truncate(out.len() - 2);
+    }
+    if out == b"\r\n" && canon == Canonicalization::Relaxed {
+        out.clear();
+    }
+    if out.is_empty() && canon == Canonicalization::Simple {
+        out.extend_from_slice(b"\r\n");
+    }
+
+    out
+}
+
+// Remove the value of the "b=" tag from a raw DKIM-Signature value.
+pub(crate) fn strip_signature(value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(value.len());
+
+    for (i, spec) in value.split(|&c| c == b';').enumerate() {
+        if i > 0 {
+            out.push(b';');
+        }
+        let name: Vec<u8> = spec.iter().cloned().take_while(|&c| c != b'=')
+            .filter(|c| !c.is_ascii_whitespace()).collect();
+
+        match spec.iter().position(|&c| c == b'=') {
+            Some(eq) if name == b"b" => out.extend_from_slice(&spec[..=eq]),
+            _ => out.extend_from_slice(spec),
+        }
+    }
+
+    out
+}
+
+// Pick the signed headers from the bottom up.
+fn select_headers<'a>(fields: &[Field<'a>], names: &[String]) -> Vec<Field<'a>> {
+    let mut used = vec![false; fields.len()];
+    let mut out = Vec::new();
+
+    for name in names {
+        let found = fields.iter().enumerate().rev()
+            .find(|(i, (n, _))| !used[*i] && n.eq_ignore_ascii_case(name.as_bytes()));
+        if let Some((i, field)) = found {
+            used[i] = true;
+            out.push(*field);
+        }
+    }
+
+    out
+}
+
+fn hash_algorithm(algorithm: &SigningAlgorithm) -> Option<&'static digest::Algorithm> {
+    match algorithm {
+        SigningAlgorithm::RsaSha1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+        SigningAlgorithm::RsaSha256 | SigningAlgorithm::Ed25519Sha256 => Some(&digest::SHA256),
+        SigningAlgorithm::Other(_) => None,
+    }
+}
+
+fn body_hash(hash: &'static digest::Algorithm, canon: Canonicalization, body: &[u8], length: Option<u64>)
+             -> Result<Vec<u8>, &'static str>
+{
+    let body = canonicalize_body(canon, body);
+    let body = match length {
+        Some(l) if l > body.len() as u64 => return Err("Body shorter than l="),
+        Some(l) => &body[..l as usize],
+        None => &body[..],
+    };
+
+    Ok(digest::digest(hash, body).as_ref().to_vec())
+}
+
+// The data covered by the signature: the selected headers followed
+// by the signature header without its value and trailing CRLF.
+fn signed_data(fields: &[Field], names: &[String], canon: Canonicalization,
+               sig_name: &[u8], sig_value: &[u8]) -> Vec<u8>
+{
+    let mut out = Vec::new();
+
+    for (name, value) in select_headers(fields, names) {
+        out.extend(canonicalize_header(canon, name, value));
+    }
+    out.extend(canonicalize_header(canon, sig_name, &strip_signature(sig_value)));
+    out.truncate(out.len() - 2);
+
+    out
+}
+
+// Extract the RSAPublicKey from a SubjectPublicKeyInfo structure.
+// Keys that are not wrapped are returned as is.
+fn rsa_public_key(key: &[u8]) -> &[u8] {
+    fn der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, rem) = input.split_first()?;
+        let (&len, rem) = rem.split_first()?;
+        let (len, rem) = if len < 0x80 {
+            (len as usize, rem)
+        } else {
+            let n = (len & 0x7f) as usize;
+            if n == 0 || n > 4 || rem.len() < n {
+                return None;
+            }
+            (rem[..n].iter().fold(0usize, |acc, &b| acc << 8 | b as usize), &rem[n..])
+        };
+        if rem.len() < len {
+            return None;
+        }
+        Some((tag, &rem[..len], &rem[len..]))
+    }
+
+    let unwrapped = der(key).and_then(|(tag, spki, _)| {
+        let (algo_tag, _, rem) = der(spki)?;
+        let (bits_tag, bits, _) = der(rem)?;
+        match (tag, algo_tag, bits_tag, bits.split_first()) {
+            (0x30, 0x30, 0x03, Some((0, key))) => Some(key),
+            _ => None,
+        }
+    });
+
+    unwrapped.unwrap_or(key)
+}
+
+fn der_element(tag: u8, content: &[u8]) -> Vec<u8> {
+    let len = content.len().to_be_bytes();
+    let skip = len.iter().take_while(|&&b| b == 0).count();
+    let mut out = vec![tag];
+
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        out.push(0x80 | (len.len() - skip) as u8);
+        out.extend_from_slice(&len[skip..]);
+    }
+    out.extend_from_slice(content);
+
+    out
+}
+
+/// The result of verifying a single signature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignatureResult {
+    /// The parsed signature, `None` if it could not be parsed.
+    pub signature: Option<DKIMSignature>,
+    /// The verification result. One of [`AuthResult::Pass`],
+    /// [`AuthResult::Fail`], [`AuthResult::PermError`] or
+    /// [`AuthResult::TempError`].
+    pub result: AuthResult,
+    /// The reason for a result other than pass.
+    pub reason: Option<&'static str>,
+    /// The key is in testing mode.
+    pub testing: bool,
+}
+
+impl SignatureResult {
+    /// Convert to a result for an `"Authentication-Results:"` header.
+    pub fn resinfo(&self) -> ResInfo {
+        let properties = self.signature.iter().flat_map(|sig| {
+            let mut identity = String::new();
+            if let Some(local) = &sig.identity.local_part {
+                identity.push_str(local);
+            }
+            identity.push('@');
+            identity.push_str(&sig.identity.domain);
+
+            vec![("d", sig.domain.to_string()), ("i", identity), ("s", sig.selector.clone())]
+        }).map(|(property, value)| Property { ptype: "header".into(), property: property.into(), value }).collect();
+
+        ResInfo {
+            method: "dkim".into(),
+            method_version: None,
+            result: self.result.clone(),
+            reason: self.reason.map(String::from),
+            properties,
+            comments: Vec::new(),
+        }
+    }
+}
+
+// A verification failure and its reason.
+pub(crate) type Failure = (AuthResult, &'static str);
+
+pub(crate) fn fetch_key<R: Resolver>(resolver: &R, selector: &str, domain: &Domain) -> Result<DKIMKey, Failure> {
+    let name = format!("{}._domainkey.{}", selector, domain);
+    let records = match resolver.lookup_txt(&name) {
+        Ok(records) => records,
+        Err(LookupError::NotFound) => return Err((AuthResult::PermError, "No key record")),
+        Err(LookupError::TempFail) => return Err((AuthResult::TempError, "Key lookup failed")),
+    };
+
+    records.iter()
+        .find_map(|r| dkim_key(r.as_bytes()).ok().filter(|(rem, _)| rem.is_empty()).map(|(_, key)| key))
+        .ok_or((AuthResult::PermError, "Invalid key record"))
+}
+
+// Check a signature over data that was already canonicalized.
+pub(crate) fn verify_data(algorithm: &SigningAlgorithm, key: &DKIMKey, data: &[u8], sig: &[u8]) -> Result<(), Failure> {
+    let perm = |reason| (AuthResult::PermError, reason);
+    let hash = hash_algorithm(algorithm).ok_or_else(|| perm("Unsupported algorithm"))?;
+
+    if key.public_key.is_empty() {
+        return Err(perm("Key revoked"));
+    }
+    let hash_name = if hash == &digest::SHA256 { "sha256" } else { "sha1" };
+    if !key.hash_algorithms.is_empty() && !key.hash_algorithms.iter().any(|h| h == hash_name) {
+        return Err(perm("Hash algorithm not allowed by key"));
+    }
+
+    let verified = match (algorithm, &key.key_type[..]) {
+        (SigningAlgorithm::RsaSha256, "rsa") => {
+            UnparsedPublicKey::new(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, rsa_public_key(&key.public_key))
+                .verify(data, sig)
+        }
+        (SigningAlgorithm::RsaSha1, "rsa") => {
+            UnparsedPublicKey::new(&signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY, rsa_public_key(&key.public_key))
+                .verify(data, sig)
+        }
+        (SigningAlgorithm::Ed25519Sha256, "ed25519") => {
+            UnparsedPublicKey::new(&signature::ED25519, &key.public_key)
+                .verify(digest::digest(hash, data).as_ref(), sig)
+        }
+        _ => return Err(perm("Key type does not match algorithm")),
+    };
+
+    verified.map_err(|_| (AuthResult::Fail, "Signature mismatch"))
+}
+
+// Verify the signature header at `index` once parsed.
+pub(crate) fn check_signature<R: Resolver>(fields: &[Field], index: usize, sig: &DKIMSignature,
+                                              body: &[u8], resolver: &R, now: u64) -> Result<DKIMKey, Failure>
+{
+    let perm = |reason| (AuthResult::PermError, reason);
+    let hash = hash_algorithm(&sig.algorithm).ok_or_else(|| perm("Unsupported algorithm"))?;
+
+    if !sig.query_methods.iter().any(|q| q == "dns/txt") {
+        return Err(perm("Unsupported query method"));
+    }
+    if sig.expiration.map(|x| x < now).unwrap_or(false) {
+        return Err(perm("Signature expired"));
+    }
+
+    let key = fetch_key(resolver, &sig.selector, &sig.domain)?;
+    if key.strict && !sig.identity.domain.eq_ignore_ascii_case(&sig.domain) {
+        return Err(perm("Identity domain must match d="));
+    }
+
+    if body_hash(hash, sig.body_canonicalization, body, sig.body_length).map_err(perm)? != sig.body_hash {
+        return Err((AuthResult::Fail, "Body hash mismatch"));
+    }
+
+    let (sig_name, sig_value) = fields[index];
+    let data = signed_data(fields, &sig.signed_headers, sig.header_canonicalization, sig_name, sig_value);
+    verify_data(&sig.algorithm, &key, &data, &sig.signature)?;
+
+    Ok(key)
+}
+
+fn verify_one<R: Resolver>(fields: &[Field], index: usize, body: &[u8], resolver: &R, now: u64)
+                              -> Result<DKIMKey, Failure>
+{
+    let (_, sig) = dkim_signature(fields[index].1).map_err(|_| (AuthResult::PermError, "Invalid signature"))?;
+    check_signature(fields, index, &sig, body, resolver, now)
+}
+
+/// Verify every `"DKIM-Signature:"` header of a message.
+///
+/// `now` is the current UNIX time, used to check signature
+/// expiration. Returns one result per signature, in header order.
+pub fn verify<R: Resolver>(message: &[u8], resolver: &R, now: u64) -> Vec<SignatureResult> {
+    let (fields, body) = match split_message(message) {
+        Ok(split) => split,
+        Err(_) => return Vec::new(),
+    };
+
+    fields.iter().enumerate()
+        .filter(|(_, (name, _))| name.eq_ignore_ascii_case(b"DKIM-Signature"))
+        .map(|(i, (_, value))| {
+            let signature = dkim_signature(value).ok().map(|(_, sig)| sig);
+            let (result, reason, testing) = match verify_one(&fields, i, body, resolver, now) {
+                Ok(key) => (AuthResult::Pass, None, key.testing),
+                Err((result, reason)) => (result, Some(reason), false),
+            };
+            SignatureResult { signature, result, reason, testing }
+        }).collect()
+}
+
+/// A private key used for signing.
+pub enum SigningKey {
+    /// An RSA key, used with `rsa-sha256`.
+    Rsa(RsaKeyPair),
+    /// An Ed25519 key, used with `ed25519-sha256`.
+    Ed25519(Ed25519KeyPair),
+}
+
+impl SigningKey {
+    /// Load an RSA key from a PKCS#8 DER document.
+    pub fn rsa_from_pkcs8(der: &[u8]) -> Result<Self, &'static str> {
+        RsaKeyPair::from_pkcs8(der).map(SigningKey::Rsa).map_err(|_| "Invalid RSA key")
+    }
+
+    /// Load an RSA key from a PKCS#1 `RSAPrivateKey` DER document.
+    pub fn rsa_from_der(der: &[u8]) -> Result<Self, &'static str> {
+        RsaKeyPair::from_der(der).map(SigningKey::Rsa).map_err(|_| "Invalid RSA key")
+    }
+
+    /// Load an Ed25519 key from a PKCS#8 DER document.
+    pub fn ed25519_from_pkcs8(der: &[u8]) -> Result<Self, &'static str> {
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(der).map(SigningKey::Ed25519).map_err(|_| "Invalid Ed25519 key")
+    }
+
+    /// The public key as published in the `"p="` tag of a key
+    /// record, base64 encoded.
+    ///
+    /// RSA keys are wrapped in a SubjectPublicKeyInfo structure.
+    pub fn public_key(&self) -> String {
+        match self {
+            SigningKey::Rsa(key) => {
+                // rsaEncryption algorithm identifier with NULL parameters
+                let algorithm = b"\x30\x0d\x06\x09\x2a\x86\x48\x86\xf7\x0d\x01\x01\x01\x05\x00";
+                let mut bits = vec![0];
+                bits.extend_from_slice(key.public_key().as_ref());
+
+                let mut spki = algorithm.to_vec();
+                spki.extend(der_element(0x03, &bits));
+                base64::encode(der_element(0x30, &spki))
+            }
+            SigningKey::Ed25519(key) => base64::encode(key.public_key().as_ref()),
+        }
+    }
+}
+
+/// Options for [`sign`].
+#[derive(Clone, Debug)]
+pub struct SignOptions {
+    /// The signing domain.
+    pub domain: Domain,
+    /// The selector of the key.
+    pub selector: String,
+    /// The names of the headers to sign. A name may be repeated to
+    /// sign multiple instances or to prevent the addition of a header.
+    pub headers: Vec<String>,
+    /// The header canonicalization.
+    pub header_canonicalization: Canonicalization,
+    /// The body canonicalization.
+    pub body_canonicalization: Canonicalization,
+    /// The UNIX time at which the message is signed.
+    pub timestamp: Option<u64>,
+    /// The UNIX time after which the signature expires.
+    pub expiration: Option<u64>,
+}
+
+/// Sign a message.
+///
+/// Returns the folded `"DKIM-Signature:"` header line including the
+/// terminating CRLF, to be prepended to the message.
+pub fn sign(message: &[u8], key: &SigningKey, options: &SignOptions) -> Result<String, &'static str> {
+    let (fields, body) = split_message(message)?;
+    if !options.headers.iter().any(|h| h.eq_ignore_ascii_case("from")) {
+        return Err("From must be signed");
+    }
+
+    let algorithm = match key {
+        SigningKey::Rsa(_) => SigningAlgorithm::RsaSha256,
+        SigningKey::Ed25519(_) => SigningAlgorithm::Ed25519Sha256,
+    };
+    let canon_name = |c| match c {
+        Canonicalization::Simple => "simple",
+        Canonicalization::Relaxed => "relaxed",
+    };
+    let bh = body_hash(&digest::SHA256, options.body_canonicalization, body, None)?;
+
+    let mut words = vec![
+        "v=1;".to_string(),
+        format!("a={};", match algorithm {
+            SigningAlgorithm::RsaSha256 => "rsa-sha256",
+            _ => "ed25519-sha256",
+        }),
+        format!("c={}/{};", canon_name(options.header_canonicalization), canon_name(options.body_canonicalization)),
+        format!("d={};", options.domain),
+        format!("s={};", options.selector),
+    ];
+    if let Some(t) = options.timestamp {
+        words.push(format!("t={};", t));
+    }
+    if let Some(x) = options.expiration {
+        words.push(format!("x={};", x));
+    }
+    words.push(format!("h={};", options.headers.join(":")));
+    words.push(format!("bh={};", base64::encode(&bh)));
+    words.push("b=".into());
+
+    let mut line = fold_words("DKIM-Signature", words);
+    line.truncate(line.len() - 2);
+
+    let value = &line.as_bytes()["DKIM-Signature:".len()..];
+    let data = signed_data(&fields, &options.headers, options.header_canonicalization, b"DKIM-Signature", value);
+
+    let signature = match key {
+        SigningKey::Rsa(key) => {
+            let mut out = vec![0; key.public().modulus_len()];
+            key.sign(&signature::RSA_PKCS1_SHA256, &SystemRandom::new(), &data, &mut out)
+                .map_err(|_| "Signing failed")?;
+            out
+        }
+        SigningKey::Ed25519(key) => key.sign(digest::digest(&digest::SHA256, &data).as_ref()).as_ref().to_vec(),
+    };
+
+    let encoded = base64::encode(&signature);
+    for chunk in encoded.as_bytes().chunks(64) {
+        line.push_str("\r\n ");
+        line.push_str(std::str::from_utf8(chunk).unwrap());
+    }
+    line.push_str("\r\n");
+
+    Ok(line)
+}
@@ -0,0 +1,279 @@
+//! [DMARC] policy evaluation
+//!
+//! Finds the policy record of the author domain, checks the alignment
+//! of the SPF and DKIM results with it and computes the disposition
+//! of the message. Records are parsed by the [crate::rfc7489] module
+//! and DNS lookups go through a [`Resolver`].
+//!
+//! Organizational domains are found using an embedded copy of the
+//! [Public Suffix List].
+//!
+//! [DMARC]: https://tools.ietf.org/html/rfc7489
+//! [Public Suffix List]: https://publicsuffix.org/
+
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use crate::dns::{LookupError, Resolver};
+use crate::rfc5322::Address;
+use crate::rfc7489::{dmarc_record, Alignment, DMARCRecord, Policy};
+use crate::rfc8601::{AuthResult, Property, ResInfo};
+use crate::types::{Domain, DomainPart};
+
+#[cfg(feature = "dkim")]
+use crate::dkim::SignatureResult;
+
+static PUBLIC_SUFFIX_LIST: &str = include_str!("public_suffix_list.dat");
+
+struct SuffixRules {
+    rules: HashSet<String>,
+    // The parent of "*." rules.
+    wildcards: HashSet<String>,
+    // Rules starting with "!", without it.
+    exceptions: HashSet<String>,
+}
+
+fn to_ascii(name: &str) -> String {
+    let name = name.trim_end_matches('.');
+    idna::domain_to_ascii(name).unwrap_or_else(|_| name.to_ascii_lowercase())
+}
+
+fn suffix_rules() -> &'static SuffixRules {
+    static RULES: OnceLock<SuffixRules> = OnceLock::new();
+
+    RULES.get_or_init(|| {
+        let mut out = SuffixRules { rules: HashSet::new(), wildcards: HashSet::new(), exceptions: HashSet::new() };
+
+        for line in PUBLIC_SUFFIX_LIST.lines() {
+            let rule = line.split_whitespace().next().unwrap_or("");
+            if rule.is_empty() || rule.starts_with("//") {
+                continue;
+            }
+            if let Some(rule) = rule.strip_prefix("!") {
+                out.exceptions.insert(to_ascii(rule));
+            } else if let Some(rule) = rule.strip_prefix("*.") {
+                out.wildcards.insert(to_ascii(rule));
+            } else {
+                out.rules.insert(to_ascii(rule));
+            }
+        }
+
+        out
+    })
+}
+
+/// Return the organizational domain of `domain`, which is the public
+/// suffix and one more label.
+///
+/// A domain that is itself a public suffix is returned as is.
+/// Internationalized domains are returned in their ASCII form.
+/// # Examples
+/// ```
+/// use rustyknife::dmarc::organizational_domain;
+///
+/// assert_eq!(organizational_domain("mail.example.com"), "example.com");
+/// assert_eq!(organizational_domain("a.b.example.co.uk"), "example.co.uk");
+/// assert_eq!(organizational_domain("co.uk"), "co.uk");
+/// ```
+pub fn organizational_domain(domain: &str) -> String {
+    let domain = to_ascii(domain);
+    let labels: Vec<_> = domain.split('.').collect();
+    let rules = suffix_rules();
+
+    // The default rule "*" makes the last label a public suffix.
+    let mut suffix_start = labels.len() - 1;
+    for i in 0..labels.len() {
+        let candidate = labels[i..].join(".");
+        if rules.exceptions.contains(&candidate) {
+            suffix_start = i + 1;
+            break;
+        }
+        if rules.rules.contains(&candidate) || (i + 1 < labels.len() && rules.wildcards.contains(&labels[i+1..].join("."))) {
+            suffix_start = i;
+            break;
+        }
+    }
+
+    labels[suffix_start.saturating_sub(1)..].join(".")
+}
+
+/// Check whether an authenticated domain is aligned with the author
+/// domain.
+pub fn is_aligned(domain: &str, author: &str, mode: Alignment) -> bool {
+    match mode {
+        Alignment::Strict => to_ascii(domain) == to_ascii(author),
+        Alignment::Relaxed => organizational_domain(domain) == organizational_domain(author),
+    }
+}
+
+/// Find the author domain from the content of the `"From:"` header
+/// as returned by [`rfc5322::from`](crate::rfc5322::from).
+///
+/// Fails if there are no addresses, if an address has no domain or
+/// if the addresses have different domains.
+pub fn author_domain(from: &[Address]) -> Result<Domain, &'static str> {
+    let mut domains = from.iter().flat_map(|a| match a {
+        Address::Mailbox(m) => vec![m],
+        Address::Group(g) => g.members.iter().collect(),
+    }).map(|m| match m.address.domain_part() {
+        DomainPart::Domain(d) => Ok(d),
+        DomainPart::Address(_) => Err("Author address has no domain"),
+    });
+
+    let first = domains.next().ok_or("No author address")??;
+    for domain in domains {
+        if !domain?.as_ref().eq_ignore_ascii_case(first.as_ref()) {
+            return Err("Multiple author domains");
+        }
+    }
+
+    Ok(first.clone())
+}
+
+/// An authenticated domain with its result.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identifier {
+    /// The SPF `"MAIL FROM"` domain or the DKIM `"d="` domain.
+    pub domain: Domain,
+    /// The authentication result.
+    pub result: AuthResult,
+}
+
+impl Identifier {
+    /// Build an identifier from a DKIM verification result.
+    ///
+    /// Returns `None` if the signature could not be parsed.
+    #[cfg(feature = "dkim")]
+    pub fn from_dkim(result: &SignatureResult) -> Option<Self> {
+        result.signature.as_ref().map(|s| Identifier { domain: s.domain.clone(), result: result.result.clone() })
+    }
+}
+
+/// The result of a DMARC evaluation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DMARCResult {
+    /// One of [`AuthResult::None`], [`AuthResult::Pass`],
+    /// [`AuthResult::Fail`] or [`AuthResult::TempError`].
+    pub result: AuthResult,
+    /// The reason for a temporary error or for ignoring the policy
+    /// record.
+    pub reason: Option<&'static str>,
+    /// The author domain.
+    pub from_domain: Domain,
+    /// The domain where the policy record was found.
+    pub policy_domain: Option<String>,
+    /// The policy record.
+    pub record: Option<DMARCRecord>,
+    /// The policy requested by the record for the author domain.
+    pub policy: Option<Policy>,
+    /// The policy to apply once the percentage is taken into
+    /// account. Always [`Policy::None`] unless the result is fail.
+    pub disposition: Policy,
+    /// An SPF pass was aligned.
+    pub spf_aligned: bool,
+    /// A DKIM pass was aligned.
+    pub dkim_aligned: bool,
+}
+
+impl DMARCResult {
+    /// Convert to a result for an `"Authentication-Results:"` header.
+    pub fn resinfo(&self) -> ResInfo {
+        ResInfo {
+            method: "dmarc".into(),
+            method_version: None,
+            result: self.result.clone(),
+            reason: self.reason.map(String::from),
+            properties: vec![Property { ptype: "header".into(), property: "from".into(), value: self.from_domain.to_string() }],
+            comments: Vec::new(),
+        }
+    }
+}
+
+fn is_dmarc(record: &str) -> bool {
+    let record = record.trim_start_matches([' ', '\t']);
+    record.starts_with("v=DMARC1") &&
+        record[8..].trim_start_matches([' ', '\t']).chars().next().map(|c| c == ';').unwrap_or(true)
+}
+
+type Failure = (AuthResult, &'static str);
+
+// Invalid or multiple records stop the policy discovery.
+fn find_record<R: Resolver>(resolver: &R, domain: &str) -> Result<Option<DMARCRecord>, Failure> {
+    let records = match resolver.lookup_txt(&format!("_dmarc.{}", domain)) {
+        Ok(records) => records,
+        Err(LookupError::NotFound) => return Ok(None),
+        Err(LookupError::TempFail) => return Err((AuthResult::TempError, "DNS lookup failed")),
+    };
+    let mut records = records.into_iter().filter(|r| is_dmarc(r));
+
+    match (records.next(), records.next()) {
+        (None, _) => Ok(None),
+        (Some(record), None) => exact!(record.as_bytes(), dmarc_record)
+            .map(|(_, r)| Some(r))
+            .map_err(|_| (AuthResult::None, "Invalid DMARC record")),
+        _ => Err((AuthResult::None, "Multiple DMARC records")),
+    }
+}
+
+fn downgrade(policy: Policy) -> Policy {
+    match policy {
+        Policy::Reject => Policy::Quarantine,
+        _ => Policy::None,
+    }
+}
+
+/// Evaluate the DMARC policy of the author domain `from`.
+///
+/// `spf` is the SPF result for the `"MAIL FROM"` domain and `dkim`
+/// holds one result per DKIM signature. `sample` is a random number
+/// between 0 and 99, compared with the `"pct="` tag of the record.
+pub fn evaluate<R: Resolver>(resolver: &R, from: &Domain, spf: Option<&Identifier>, dkim: &[Identifier], sample: u8) -> DMARCResult {
+    let mut out = DMARCResult {
+        result: AuthResult::None,
+        reason: None,
+        from_domain: from.clone(),
+        policy_domain: None,
+        record: None,
+        policy: None,
+        disposition: Policy::None,
+        spf_aligned: false,
+        dkim_aligned: false,
+    };
+    let author = to_ascii(from);
+    let organizational = organizational_domain(&author);
+
+    let found = match find_record(resolver, &author) {
+        Ok(None) if organizational != author => find_record(resolver, &organizational).map(|r| r.map(|r| (r, organizational))),
+        found => found.map(|r| r.map(|r| (r, author.clone()))),
+    };
+    let (record, policy_domain) = match found {
+        Ok(Some(found)) => found,
+        Ok(None) => return out,
+        Err((result, reason)) => {
+            out.result = result;
+            out.reason = Some(reason);
+            return out;
+        }
+    };
+
+    out.spf_aligned = spf.map(|i| i.result == AuthResult::Pass && is_aligned(&i.domain, &author, record.spf_alignment))
+        .unwrap_or(false);
+    out.dkim_aligned = dkim.iter()
+        .any(|i| i.result == AuthResult::Pass && is_aligned(&i.domain, &author, record.dkim_alignment));
+
+    let policy = match record.subdomain_policy {
+        Some(sp) if policy_domain != author => sp,
+        _ => record.policy,
+    };
+    if out.spf_aligned || out.dkim_aligned {
+        out.result = AuthResult::Pass;
+    } else {
+        out.result = AuthResult::Fail;
+        out.disposition = if sample < record.percent { policy } else { downgrade(policy) };
+    }
+    out.policy = Some(policy);
+    out.policy_domain = Some(policy_domain);
+    out.record = Some(record);
+
+    out
+}
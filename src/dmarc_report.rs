@@ -0,0 +1,382 @@
+//! [DMARC] aggregate report parser
+//!
+//! Parses the XML aggregate reports sent to the `"rua="` addresses of
+//! a DMARC record. Reports are usually sent gzip or zip compressed as
+//! a message attachment; [`parse_report_data`] accepts the decoded
+//! attachment content as is while [`parse_report_attachment`] and
+//! [`message_reports`] work on a parsed message.
+//!
+//! Element names are matched without regard to their namespace.
+//! Unknown elements are ignored.
+//!
+//! [DMARC]: https://tools.ietf.org/html/rfc7489#appendix-C
+
+use std::io::{Cursor, Read};
+use std::net::IpAddr;
+
+use flate2::read::GzDecoder;
+use roxmltree::{Document, Node};
+
+use crate::attachment::{attachments, Attachment};
+use crate::mime::Entity;
+use crate::rfc7489::{Alignment, Policy};
+use crate::rfc8601::AuthResult;
+
+/// The maximum size of a decompressed report.
+pub const MAX_REPORT_SIZE: u64 = 64 << 20;
+
+/// Information about the reporting organization and the report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportMetadata {
+    /// The name of the reporting organization.
+    pub org_name: String,
+    /// The contact address of the reporting organization.
+    pub email: String,
+    /// Additional contact information.
+    pub extra_contact_info: Option<String>,
+    /// The report identifier, unique for the reporting organization.
+    pub report_id: String,
+    /// The start of the reporting period as a UNIX timestamp.
+    pub begin: u64,
+    /// The end of the reporting period as a UNIX timestamp.
+    pub end: u64,
+    /// Errors encountered by the reporting organization.
+    pub errors: Vec<String>,
+}
+
+/// The DMARC record found by the reporting organization.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyPublished {
+    /// The domain where the record was found.
+    pub domain: String,
+    /// The DKIM alignment mode.
+    pub dkim_alignment: Alignment,
+    /// The SPF alignment mode.
+    pub spf_alignment: Alignment,
+    /// The policy for the domain.
+    pub policy: Policy,
+    /// The policy for subdomains.
+    pub subdomain_policy: Option<Policy>,
+    /// The percentage of failing messages the policy applies to.
+    pub percent: u8,
+    /// The failure reporting options as published, such as `"1:d"`.
+    pub failure_options: Option<String>,
+}
+
+/// The reason the applied policy differs from the published one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyOverride {
+    /// The override type such as `"forwarded"` or `"mailing_list"`.
+    pub kind: String,
+    /// A free form comment.
+    pub comment: Option<String>,
+}
+
+/// The result of a single DKIM signature verification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DKIMAuthResult {
+    /// The `"d="` domain.
+    pub domain: String,
+    /// The `"s="` selector.
+    pub selector: Option<String>,
+    /// The verification result.
+    pub result: AuthResult,
+    /// A free form description of the result.
+    pub human_result: Option<String>,
+}
+
+/// The result of an SPF evaluation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SPFAuthResult {
+    /// The checked domain.
+    pub domain: String,
+    /// The checked identity, `"mfrom"` or `"helo"`.
+    pub scope: Option<String>,
+    /// The evaluation result.
+    pub result: AuthResult,
+}
+
+/// A group of messages sharing the same source and results.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportRecord {
+    /// The address of the sending server.
+    pub source_ip: IpAddr,
+    /// The number of messages.
+    pub count: u64,
+    /// The policy applied to the messages.
+    pub disposition: Policy,
+    /// The aligned DKIM result, [`AuthResult::Pass`] or [`AuthResult::Fail`].
+    pub dkim: AuthResult,
+    /// The aligned SPF result, [`AuthResult::Pass`] or [`AuthResult::Fail`].
+    pub spf: AuthResult,
+    /// The reasons for overriding the published policy.
+    pub reasons: Vec<PolicyOverride>,
+    /// The `"From:"` header domain.
+    pub header_from: String,
+    /// The `"MAIL FROM"` domain.
+    pub envelope_from: Option<String>,
+    /// The recipient domain.
+    pub envelope_to: Option<String>,
+    /// The raw DKIM results, aligned or not.
+    pub dkim_results: Vec<DKIMAuthResult>,
+    /// The raw SPF results, aligned or not.
+    pub spf_results: Vec<SPFAuthResult>,
+}
+
+/// A parsed aggregate report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregateReport {
+    /// The report format version.
+    pub version: Option<String>,
+    /// Information about the report.
+    pub metadata: ReportMetadata,
+    /// The evaluated DMARC record.
+    pub policy_published: PolicyPublished,
+    /// The report rows.
+    pub records: Vec<ReportRecord>,
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|c| c.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(node: Node<'a, 'input>, name: &'a str) -> impl Iterator<Item=Node<'a, 'input>> + 'a {
+    node.children().filter(move |c| c.tag_name().name() == name)
+}
+
+fn text(node: Node, name: &str) -> Option<String> {
+    child(node, name).and_then(|c| c.text()).map(str::trim).filter(|t| !t.is_empty()).map(String::from)
+}
+
+fn required(node: Node, name: &str, err: &'static str) -> Result<String, &'static str> {
+    text(node, name).ok_or(err)
+}
+
+fn number<T: std::str::FromStr>(node: Node, name: &str, err: &'static str) -> Result<T, &'static str> {
+    required(node, name, err)?.parse().map_err(|_| err)
+}
+
+fn metadata(node: Node) -> Result<ReportMetadata, &'static str> {
+    let range = child(node, "date_range").ok_or("Invalid date_range")?;
+
+    Ok(ReportMetadata {
+        org_name: required(node, "org_name", "Missing org_name")?,
+        email: required(node, "email", "Missing email")?,
+        extra_contact_info: text(node, "extra_contact_info"),
+        report_id: required(node, "report_id", "Missing report_id")?,
+        begin: number(range, "begin", "Invalid date_range")?,
+        end: number(range, "end", "Invalid date_range")?,
+        errors: children(node, "error").filter_map(|e| e.text()).map(|e| e.trim().into()).collect(),
+    })
+}
+
+fn policy_published(node: Node) -> Result<PolicyPublished, &'static str> {
+    Ok(PolicyPublished {
+        domain: required(node, "domain", "Missing policy domain")?,
+        dkim_alignment: Alignment::parse(text(node, "adkim").as_deref()),
+        spf_alignment: Alignment::parse(text(node, "aspf").as_deref()),
+        policy: text(node, "p").as_deref().and_then(Policy::parse).ok_or("Invalid published policy")?,
+        subdomain_policy: text(node, "sp").as_deref().and_then(Policy::parse),
+        percent: text(node, "pct").and_then(|p| p.parse().ok()).filter(|p| *p <= 100).unwrap_or(100),
+        failure_options: text(node, "fo"),
+    })
+}
+
+fn record(node: Node) -> Result<ReportRecord, &'static str> {
+    let row = child(node, "row").ok_or("Missing row")?;
+    let evaluated = child(row, "policy_evaluated").ok_or("Missing policy_evaluated")?;
+    let identifiers = child(node, "identifiers").ok_or("Missing identifiers")?;
+    let results = child(node, "auth_results");
+    let result = |node: Node, name| text(node, name).as_deref().map(AuthResult::from).unwrap_or(AuthResult::None);
+
+    let dkim_results = results.into_iter().flat_map(|r| children(r, "dkim")).map(|r| {
+        Ok(DKIMAuthResult {
+            domain: required(r, "domain", "Missing DKIM domain")?,
+            selector: text(r, "selector"),
+            result: result(r, "result"),
+            human_result: text(r, "human_result"),
+        })
+    }).collect::<Result<_, &str>>()?;
+    let spf_results = results.into_iter().flat_map(|r| children(r, "spf")).map(|r| {
+        Ok(SPFAuthResult {
+            domain: required(r, "domain", "Missing SPF domain")?,
+            scope: text(r, "scope"),
+            result: result(r, "result"),
+        })
+    }).collect::<Result<_, &str>>()?;
+
+    Ok(ReportRecord {
+        source_ip: number(row, "source_ip", "Invalid source_ip")?,
+        count: number(row, "count", "Invalid count")?,
+        disposition: text(evaluated, "disposition").as_deref().and_then(Policy::parse).ok_or("Invalid disposition")?,
+        dkim: result(evaluated, "dkim"),
+        spf: result(evaluated, "spf"),
+        reasons: children(evaluated, "reason").map(|r| PolicyOverride {
+            kind: text(r, "type").unwrap_or_default(),
+            comment: text(r, "comment"),
+        }).collect(),
+        header_from: required(identifiers, "header_from", "Missing header_from")?,
+        envelope_from: text(identifiers, "envelope_from"),
+        envelope_to: text(identifiers, "envelope_to"),
+        dkim_results,
+        spf_results,
+    })
+}
+
+/// Parse an uncompressed aggregate report.
+/// # Examples
+/// ```
+/// use rustyknife::dmarc_report::parse_report;
+/// use rustyknife::rfc7489::Policy;
+///
+/// let report = parse_report(br#"<?xml version="1.0"?>
+/// <feedback>
+///   <report_metadata>
+///     <org_name>example.net</org_name>
+///     <email>noreply-dmarc@example.net</email>
+///     <report_id>1234</report_id>
+///     <date_range><begin>1600000000</begin><end>1600086399</end></date_range>
+///   </report_metadata>
+///   <policy_published><domain>example.com</domain><p>reject</p></policy_published>
+///   <record>
+///     <row>
+///       <source_ip>192.0.2.1</source_ip><count>3</count>
+///       <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>fail</spf></policy_evaluated>
+///     </row>
+///     <identifiers><header_from>example.com</header_from></identifiers>
+///   </record>
+/// </feedback>"#).unwrap();
+///
+/// assert_eq!(report.metadata.org_name, "example.net");
+/// assert_eq!(report.policy_published.policy, Policy::Reject);
+/// assert_eq!(report.records[0].count, 3);
+/// ```
+pub fn parse_report(xml: &[u8]) -> Result<AggregateReport, &'static str> {
+    let xml = std::str::from_utf8(xml).map_err(|_| "Invalid UTF-8 in report")?;
+    let doc = Document::parse(xml.trim_start_matches('\u{feff}')).map_err(|_| "Invalid XML")?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "feedback" {
+        return Err("Not an aggregate report");
+    }
+
+    Ok(AggregateReport {
+        version: text(root, "version"),
+        metadata: metadata(child(root, "report_metadata").ok_or("Missing report_metadata")?)?,
+        policy_published: policy_published(child(root, "policy_published").ok_or("Missing policy_published")?)?,
+        records: children(root, "record").map(record).collect::<Result<_, _>>()?,
+    })
+}
+
+fn read_limited<R: Read>(input: R) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::new();
+    input.take(MAX_REPORT_SIZE + 1).read_to_end(&mut out).map_err(|_| "Invalid compressed report")?;
+    if out.len() as u64 > MAX_REPORT_SIZE {
+        return Err("Report too large");
+    }
+    Ok(out)
+}
+
+fn unzip(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(|_| "Invalid compressed report")?;
+    let index = (0..archive.len())
+        .find(|&i| archive.name_for_index(i).map(|n| n.to_ascii_lowercase().ends_with(".xml")).unwrap_or(false))
+        .ok_or("No report in archive")?;
+    let file = archive.by_index(index).map_err(|_| "Invalid compressed report")?;
+
+    read_limited(file)
+}
+
+/// Parse an aggregate report that may be gzip or zip compressed.
+///
+/// The format is detected from the content. The first `".xml"` file
+/// of a zip archive is used. Decompressed reports larger than
+/// [`MAX_REPORT_SIZE`] are rejected.
+pub fn parse_report_data(data: &[u8]) -> Result<AggregateReport, &'static str> {
+    if data.starts_with(b"\x1f\x8b") {
+        parse_report(&read_limited(GzDecoder::new(data))?)
+    } else if data.starts_with(b"PK\x03\x04") {
+        parse_report(&unzip(data)?)
+    } else {
+        parse_report(data)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ReportFormat {
+    Gzip,
+    Zip,
+    Xml,
+}
+
+// Generic media types are common so the filename extension is used
+// as a fallback.
+fn report_format(attachment: &Attachment) -> Option<ReportFormat> {
+    match &attachment.content_type.essence()[..] {
+        "application/gzip" | "application/x-gzip" => return Some(ReportFormat::Gzip),
+        "application/zip" | "application/x-zip" | "application/x-zip-compressed" => return Some(ReportFormat::Zip),
+        "text/xml" | "application/xml" => return Some(ReportFormat::Xml),
+        _ => (),
+    }
+
+    let name = attachment.filename.as_deref()?.to_ascii_lowercase();
+    if name.ends_with(".gz") || name.ends_with(".gzip") {
+        Some(ReportFormat::Gzip)
+    } else if name.ends_with(".zip") {
+        Some(ReportFormat::Zip)
+    } else if name.ends_with(".xml") {
+        Some(ReportFormat::Xml)
+    } else {
+        None
+    }
+}
+
+/// Parse an aggregate report from a message attachment.
+///
+/// The format is picked from the media type, `"application/gzip"`,
+/// `"application/zip"` or `"text/xml"`, or from the filename
+/// extension if the media type is not one of these.
+///
+/// Fails with `"Not a report attachment"` if the format cannot be
+/// determined.
+pub fn parse_report_attachment(attachment: &Attachment) -> Result<AggregateReport, &'static str> {
+    let data = &attachment.data;
+
+    match report_format(attachment).ok_or("Not a report attachment")? {
+        ReportFormat::Gzip => parse_report(&read_limited(GzDecoder::new(&data[..]))?),
+        ReportFormat::Zip => parse_report(&unzip(data)?),
+        ReportFormat::Xml => parse_report(data),
+    }
+}
+
+/// Parse the aggregate reports attached to a message.
+///
+/// `message` is the message given to [`crate::mime::parse`].
+/// Attachments that are not reports are skipped. Returns one result
+/// per report attachment, in order.
+/// # Examples
+/// ```
+/// use rustyknife::dmarc_report::message_reports;
+/// use rustyknife::mime::parse;
+///
+/// let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+///                 --b\r\n\r\nA report is attached.\r\n\
+///                 --b\r\n\
+///                 Content-Type: text/xml\r\n\
+///                 Content-Disposition: attachment; filename=report.xml\r\n\r\n\
+///                 <feedback>\
+///                 <report_metadata><org_name>example.net</org_name><email>dmarc@example.net</email><report_id>1</report_id>\
+///                 <date_range><begin>0</begin><end>86399</end></date_range></report_metadata>\
+///                 <policy_published><domain>example.com</domain><p>none</p></policy_published>\
+///                 </feedback>\r\n\
+///                 --b--\r\n";
+///
+/// let reports = message_reports(&parse(message), message);
+/// assert_eq!(reports.len(), 1);
+/// assert_eq!(reports[0].as_ref().unwrap().metadata.org_name, "example.net");
+/// ```
+pub fn message_reports(root: &Entity<'_>, message: &[u8]) -> Vec<Result<AggregateReport, &'static str>> {
+    attachments(root, message)
+        .filter(|a| report_format(a).is_some())
+        .map(|a| parse_report_attachment(&a))
+        .collect()
+}
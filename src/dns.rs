@@ -0,0 +1,38 @@
+//! DNS lookups used for sender authentication
+//!
+//! This crate does not do any network access. Lookups go through the
+//! [`Resolver`] trait which allows plugging in any DNS client.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Error returned by a lookup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LookupError {
+    /// The name does not exist or has no records of the requested type.
+    NotFound,
+    /// The lookup failed temporarily.
+    TempFail,
+}
+
+/// Looks up DNS records.
+///
+/// An empty result is treated the same as [`LookupError::NotFound`].
+pub trait Resolver {
+    /// Return the TXT records for `name`.
+    ///
+    /// The character strings of each record must be concatenated.
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, LookupError>;
+
+    /// Return the IPv4 addresses for `name`.
+    fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, LookupError>;
+
+    /// Return the IPv6 addresses for `name`.
+    fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, LookupError>;
+
+    /// Return the mail exchanger names for `name`, in order of
+    /// preference.
+    fn lookup_mx(&self, name: &str) -> Result<Vec<String>, LookupError>;
+
+    /// Return the names that `ip` maps back to.
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, LookupError>;
+}
@@ -109,3 +109,12 @@ pub fn header(input: &[u8]) -> NomResult<Option<HeaderField>> {
     alt((map(alt((field, invalid_field)), Some),
          map(crlf, |_| None)))(input)
 }
+
+// A header name and raw value.
+pub(crate) type Field<'a> = (&'a [u8], &'a [u8]);
+
+// Split a message into its valid header fields and its body.
+pub(crate) fn split_message(message: &[u8]) -> Result<(Vec<Field<'_>>, &[u8]), &'static str> {
+    let (body, fields) = header_section(message).map_err(|_| "Invalid header section")?;
+    Ok((fields.into_iter().filter_map(Result::ok).collect(), body))
+}
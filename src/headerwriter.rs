@@ -0,0 +1,578 @@
+//! Header serialization to [Internet Message Format] wire form
+//!
+//! Values are quoted, [RFC 2047] encoded and folded as needed so that
+//! they can be parsed back by the [crate::rfc5322] module.
+//!
+//! With the [`Legacy`] behaviour, the output is pure ASCII and text
+//! outside of the ASCII range is written as encoded words. With the
+//! [`Intl`] behaviour, UTF-8 is written as is as allowed by [RFC 6532].
+//!
+//! Lines are folded at 78 characters where possible and are never
+//! longer than 998 octets.
+//!
+//! [Internet Message Format]: https://tools.ietf.org/html/rfc5322
+//! [RFC 2047]: https://tools.ietf.org/html/rfc2047
+//! [RFC 6532]: https://tools.ietf.org/html/rfc6532
+
+use encoding::all::UTF_8;
+
+use crate::behaviour::{Intl, Legacy};
+use crate::rfc2047::{encode, Context};
+use crate::rfc2231::{self, ContentDisposition, MediaType};
+use crate::rfc5322::{Address, Comment, Group, Mailbox, UTF8Policy};
+use crate::rfc5321::ReversePath;
+use crate::rfc7208::ReceivedSPF;
+use crate::rfc8601::{self, AuthenticationResults, ResInfo};
+use crate::types::{self, DomainPart, LocalPart, QuotedString};
+use crate::util::*;
+
+/// Behaviour specific options for the header writer.
+pub trait WriterPolicy {
+    /// Octets above 127 may be written as UTF-8.
+    const RAW_UTF8: bool;
+}
+
+impl WriterPolicy for Legacy {
+    const RAW_UTF8: bool = false;
+}
+
+impl WriterPolicy for Intl {
+    const RAW_UTF8: bool = true;
+}
+
+const FOLD_LEN: usize = 78;
+const MAX_LINE_LEN: usize = 998;
+
+// Builds a header line, folding it before whitespace when it would
+// otherwise get too long.
+struct Folder {
+    out: String,
+    line_len: usize,
+    line_has_word: bool,
+}
+
+impl Folder {
+    fn new(name: &str) -> Self {
+        let mut out = String::with_capacity(FOLD_LEN);
+        out.push_str(name);
+        out.push(':');
+
+        Folder { line_len: out.len(), out, line_has_word: true }
+    }
+
+    fn push(&mut self, ws: &str, word: &str) {
+        if !ws.is_empty() && !word.is_empty() && self.line_has_word
+            && self.line_len + ws.len() + word.len() > FOLD_LEN
+        {
+            self.out.push_str("\r\n");
+            self.line_len = 0;
+            self.line_has_word = false;
+        }
+
+        self.out.push_str(ws);
+        self.out.push_str(word);
+        self.line_len += ws.len() + word.len();
+        self.line_has_word |= !word.is_empty();
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("\r\n");
+        self.out
+    }
+}
+
+pub(crate) fn fold_words<I: IntoIterator<Item=String>>(name: &str, words: I) -> String {
+    let mut folder = Folder::new(name);
+
+    for word in words {
+        folder.push(" ", &word);
+    }
+
+    folder.finish()
+}
+
+// Text that would be mistaken for an encoded word or that cannot be
+// written as is must be encoded.
+fn needs_encoding<P: WriterPolicy>(text: &str) -> bool {
+    text.contains("=?") || text.chars().any(|c| {
+        if c.is_ascii() {
+            c.is_ascii_control() && c != '\t'
+        } else {
+            !P::RAW_UTF8
+        }
+    })
+}
+
+fn is_atom<P: WriterPolicy>(word: &str) -> bool {
+    let atext: fn(&[u8]) -> NomResult<char> = if P::RAW_UTF8 {
+        Intl::atext
+    } else {
+        Legacy::atext
+    };
+
+    matches!(recognize_many1(atext)(word.as_bytes()), Ok((rem, _)) if rem.is_empty())
+}
+
+// Leave room for the folding whitespace before a word and the
+// separators that may be appended to it.
+fn fits_line(word: &str) -> bool {
+    word.len() + 3 <= MAX_LINE_LEN
+}
+
+// Words for a display name. Plain atoms are kept as is, anything
+// else is quoted or encoded. Words that are too long to be folded
+// are encoded so they can be split.
+pub(crate) fn phrase_words<P: WriterPolicy>(text: &str) -> Vec<String> {
+    if !needs_encoding::<P>(text) {
+        let words = if text.split(' ').all(is_atom::<P>) {
+            text.split(' ').map(String::from).collect()
+        } else {
+            vec![QuotedString(text.into()).quoted()]
+        };
+
+        if words.iter().all(|w| fits_line(w)) {
+            return words;
+        }
+    }
+
+    encode(text, Context::Phrase)
+}
+
+fn comment_words<P: WriterPolicy>(comment: &Comment) -> Vec<String> {
+    if !needs_encoding::<P>(comment) {
+        let mut out = String::with_capacity(comment.len()+2);
+        out.push('(');
+        for c in comment.chars() {
+            if c == '(' || c == ')' || c == '\\' {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out.push(')');
+
+        if fits_line(&out) {
+            return vec![out];
+        }
+    }
+
+    let mut words = encode(comment, Context::Comment);
+    words[0].insert(0, '(');
+    push_suffix(&mut words, ")");
+    words
+}
+
+pub(crate) fn addr_spec<P: WriterPolicy>(address: &types::Mailbox) -> Result<String, &'static str> {
+    if P::RAW_UTF8 {
+        return Ok(address.to_string());
+    }
+
+    let local = match address.local_part() {
+        LocalPart::DotAtom(a) if a.is_ascii() => a.to_string(),
+        LocalPart::Quoted(q) if q.is_ascii() => q.quoted(),
+        _ => return Err("Non-ASCII local part"),
+    };
+    let domain = match address.domain_part() {
+        DomainPart::Domain(d) if !d.is_ascii() => {
+            idna::domain_to_ascii(d).map_err(|_| "Invalid internationalized domain")?
+        },
+        dp => dp.to_string(),
+    };
+
+    Ok(format!("{}@{}", local, domain))
+}
+
+fn append_comments<P: WriterPolicy>(words: &mut Vec<String>, comments: &[Comment]) {
+    words.extend(comments.iter().flat_map(comment_words::<P>));
+}
+
+pub(crate) fn mailbox_words<P: WriterPolicy>(mailbox: &Mailbox) -> Result<Vec<String>, &'static str> {
+    let address = addr_spec::<P>(&mailbox.address)?;
+    let mut out = match &mailbox.dname {
+        Some(dname) => {
+            let mut out = phrase_words::<P>(dname);
+            out.push(format!("<{}>", address));
+            out
+        }
+        None => vec![address],
+    };
+    if out.last().is_some_and(|a| !fits_line(a)) {
+        return Err("Address too long");
+    }
+    append_comments::<P>(&mut out, &mailbox.comments);
+
+    Ok(out)
+}
+
+fn push_suffix(words: &mut [String], suffix: &str) {
+    if let Some(last) = words.last_mut() {
+        last.push_str(suffix);
+    }
+}
+
+pub(crate) fn group_words<P: WriterPolicy>(group: &Group) -> Result<Vec<String>, &'static str> {
+    let mut out = phrase_words::<P>(&group.dname);
+    push_suffix(&mut out, ":");
+
+    for (i, member) in group.members.iter().enumerate() {
+        out.extend(mailbox_words::<P>(member)?);
+        if i < group.members.len() - 1 {
+            push_suffix(&mut out, ",");
+        }
+    }
+    push_suffix(&mut out, ";");
+    append_comments::<P>(&mut out, &group.comments);
+
+    Ok(out)
+}
+
+pub(crate) fn address_words<P: WriterPolicy>(address: &Address) -> Result<Vec<String>, &'static str> {
+    match address {
+        Address::Mailbox(m) => mailbox_words::<P>(m),
+        Address::Group(g) => group_words::<P>(g),
+    }
+}
+
+/// Serialize a header containing a single address such as `"Sender:"`.
+///
+/// Returns the folded header line including the terminating CRLF.
+///
+/// Fails if the address cannot be represented with the behaviour `P`,
+/// such as a non-ASCII local part with the [`Legacy`] behaviour, or
+/// if it is too long to fit on a line.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::Legacy;
+/// use rustyknife::headerwriter::address;
+/// use rustyknife::rfc5322::{Address, Mailbox};
+/// use rustyknife::types;
+///
+/// let mailbox = Mailbox::new(Some("Jöhn Doe".into()),
+///                            types::Mailbox::from_imf(b"jdoe@example.org").unwrap());
+///
+/// assert_eq!(address::<Legacy>("Sender", &Address::Mailbox(mailbox)).unwrap(),
+///            "Sender: =?utf-8?B?SsO2aG4gRG9l?= <jdoe@example.org>\r\n");
+/// ```
+pub fn address<P: WriterPolicy>(name: &str, address: &Address) -> Result<String, &'static str> {
+    Ok(fold_words(name, address_words::<P>(address)?))
+}
+
+/// Serialize a header containing an address list such as `"To:"`.
+///
+/// Returns the folded header line including the terminating CRLF.
+///
+/// Fails if an address cannot be represented with the behaviour `P`,
+/// such as a non-ASCII local part with the [`Legacy`] behaviour, or
+/// if it is too long to fit on a line.
+pub fn address_list<P: WriterPolicy>(name: &str, addresses: &[Address]) -> Result<String, &'static str> {
+    let mut words = Vec::new();
+
+    for (i, address) in addresses.iter().enumerate() {
+        words.extend(address_words::<P>(address)?);
+        if i < addresses.len() - 1 {
+            push_suffix(&mut words, ",");
+        }
+    }
+
+    Ok(fold_words(name, words))
+}
+
+// A MIME token, or a quoted string if the value has other characters.
+fn value_word(value: &str) -> String {
+    let is_token = |c: u8| (33..=126).contains(&c) && !b"()<>@,;:\\\"/[]?=".contains(&c);
+
+    if !value.is_empty() && value.bytes().all(is_token) {
+        value.into()
+    } else {
+        QuotedString(value.into()).quoted()
+    }
+}
+
+// Property values may also be written as addresses.
+fn pvalue_word(value: &str) -> String {
+    match rfc8601::pvalue(value.as_bytes()) {
+        Ok((rem, parsed)) if rem.is_empty() && parsed == value => parsed,
+        _ => value_word(value),
+    }
+}
+
+fn resinfo_words<P: WriterPolicy>(res: &ResInfo) -> Vec<String> {
+    let mut out = vec![match res.method_version {
+        Some(version) => format!("{}/{}={}", res.method, version, res.result),
+        None => format!("{}={}", res.method, res.result),
+    }];
+
+    if let Some(reason) = &res.reason {
+        out.push(format!("reason={}", value_word(reason)));
+    }
+    for prop in &res.properties {
+        out.push(format!("{}.{}={}", prop.ptype, prop.property, pvalue_word(&prop.value)));
+    }
+    append_comments::<P>(&mut out, &res.comments);
+
+    out
+}
+
+pub(crate) fn authentication_results_words<P: WriterPolicy>(ar: &AuthenticationResults) -> Vec<String> {
+    let mut out = vec![value_word(&ar.authserv_id)];
+    if let Some(version) = ar.version {
+        out.push(version.to_string());
+    }
+    append_comments::<P>(&mut out, &ar.comments);
+
+    if ar.results.is_empty() {
+        push_suffix(&mut out, ";");
+        out.push("none".into());
+    }
+    for res in &ar.results {
+        push_suffix(&mut out, ";");
+        out.extend(resinfo_words::<P>(res));
+    }
+
+    out
+}
+
+/// Serialize an `"Authentication-Results:"` header.
+///
+/// Returns the folded header line including the terminating CRLF.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::Legacy;
+/// use rustyknife::headerwriter::authentication_results;
+/// use rustyknife::rfc8601::{AuthenticationResults, AuthResult, Property, ResInfo};
+///
+/// let spf = ResInfo { method: "spf".into(), method_version: None, result: AuthResult::Pass,
+///                     reason: None, comments: vec![],
+///                     properties: vec![Property { ptype: "smtp".into(), property: "mailfrom".into(),
+///                                                 value: "example.net".into() }] };
+/// let ar = AuthenticationResults { authserv_id: "mx.example.org".into(), version: None,
+///                                  results: vec![spf], comments: vec![] };
+///
+/// assert_eq!(authentication_results::<Legacy>(&ar),
+///            "Authentication-Results: mx.example.org; spf=pass smtp.mailfrom=example.net\r\n");
+/// ```
+pub fn authentication_results<P: WriterPolicy>(ar: &AuthenticationResults) -> String {
+    fold_words("Authentication-Results", authentication_results_words::<P>(ar))
+}
+
+pub(crate) fn received_spf_words<P: WriterPolicy>(spf: &ReceivedSPF) -> Vec<String> {
+    let mut out = vec![spf.result.to_string()];
+    append_comments::<P>(&mut out, spf.comment.as_slice());
+
+    let mut pairs = Vec::new();
+    if let Some(receiver) = &spf.receiver {
+        pairs.push(("receiver", receiver.clone()));
+    }
+    if let Some(ip) = spf.client_ip {
+        pairs.push(("client-ip", ip.to_string()));
+    }
+    match &spf.envelope_from {
+        Some(ReversePath::Path(path)) => pairs.push(("envelope-from", path.0.to_string())),
+        Some(ReversePath::Null) => pairs.push(("envelope-from", String::new())),
+        None => (),
+    }
+    for (key, value) in [("helo", &spf.helo), ("problem", &spf.problem),
+                         ("identity", &spf.identity), ("mechanism", &spf.mechanism)] {
+        if let Some(value) = value {
+            pairs.push((key, value.clone()));
+        }
+    }
+    pairs.extend(spf.extensions.iter().map(|(k, v)| (&k[..], v.clone())));
+
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            push_suffix(&mut out, ";");
+        }
+        out.push(format!("{}={}", key, value_word(value)));
+    }
+
+    out
+}
+
+/// Serialize a `"Received-SPF:"` header.
+///
+/// Returns the folded header line including the terminating CRLF.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::Legacy;
+/// use rustyknife::headerwriter::received_spf;
+/// use rustyknife::rfc7208::ReceivedSPF;
+/// use rustyknife::rfc8601::AuthResult;
+///
+/// let spf = ReceivedSPF { result: AuthResult::Fail, comment: None,
+///                         client_ip: Some("192.0.2.1".parse().unwrap()),
+///                         envelope_from: Some("<joe@example.com>".parse().unwrap()),
+///                         helo: Some("mail.example.com".into()), problem: None,
+///                         receiver: Some("mx.example.org".into()), identity: Some("mailfrom".into()),
+///                         mechanism: None, extensions: vec![] };
+///
+/// assert_eq!(received_spf::<Legacy>(&spf),
+///            "Received-SPF: fail receiver=mx.example.org; client-ip=192.0.2.1;\r\n envelope-from=\"joe@example.com\"; helo=mail.example.com; identity=mailfrom\r\n");
+/// ```
+pub fn received_spf<P: WriterPolicy>(spf: &ReceivedSPF) -> String {
+    fold_words("Received-SPF", received_spf_words::<P>(spf))
+}
+
+// Room for a parameter on a line of its own with the leading space
+// and the trailing semicolon.
+const PARAMETER_LEN: usize = FOLD_LEN - 2;
+
+pub(crate) fn parameter_words<P: WriterPolicy>(name: &str, value: &str, rfc2047_fallback: bool)
+                                               -> Result<Vec<String>, &'static str>
+{
+    let raw = P::RAW_UTF8 && !value.is_ascii() && !value.chars().any(|c| c.is_control() && c != '\t');
+    if raw && name.len() + value.len() < PARAMETER_LEN - 3 {
+        return Ok(vec![format!("{}={}", name, QuotedString(value.into()).quoted())]);
+    }
+
+    let mut out = Vec::new();
+    let sections = rfc2231::encode_parameter(name, value, UTF_8, None, PARAMETER_LEN)?;
+
+    // A quoted string for clients that do not support RFC 2231, with
+    // encoded words unless UTF-8 can be written as is.
+    if rfc2047_fallback && raw && name.len() + value.len() < MAX_LINE_LEN - 5 {
+        out.push(format!("{}={};", name, QuotedString(value.into()).quoted()));
+    } else if rfc2047_fallback && !value.is_ascii() {
+        let words = encode(value, Context::Phrase);
+        let last = words.len() - 1;
+        for (i, word) in words.into_iter().enumerate() {
+            let prefix = if i == 0 { format!("{}=\"", name) } else { String::new() };
+            let suffix = if i == last { "\";" } else { "" };
+            out.push(format!("{}{}{}", prefix, word, suffix));
+        }
+    }
+
+    let last = sections.len() - 1;
+    out.extend(sections.into_iter().enumerate().map(|(i, s)| if i < last { s + ";" } else { s }));
+    Ok(out)
+}
+
+fn parameter_list_words<P: WriterPolicy>(words: &mut Vec<String>, parameters: &[(String, String)], rfc2047_fallback: bool)
+                                         -> Result<(), &'static str>
+{
+    for (name, value) in parameters {
+        push_suffix(words, ";");
+        words.extend(parameter_words::<P>(name, value, rfc2047_fallback)?);
+    }
+    Ok(())
+}
+
+/// Serialize a `"Content-Type:"` header.
+///
+/// Parameters are split into RFC 2231 sections when needed so that
+/// lines stay short. With `rfc2047_fallback`, non-ASCII parameters are
+/// also written as a quoted string for older clients, using RFC 2047
+/// encoded words with [`Legacy`]. These lines may be longer than 78
+/// characters.
+///
+/// Fails if a parameter name is invalid.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::Legacy;
+/// use rustyknife::headerwriter::content_type;
+/// use rustyknife::rfc2231::MediaType;
+///
+/// let mut media = MediaType::new("text/plain");
+/// media.set_parameter("charset", "utf-8");
+/// media.set_parameter("name", "café.txt");
+///
+/// assert_eq!(content_type::<Legacy>(&media, true).unwrap(),
+///            "Content-Type: text/plain; charset=utf-8; name=\"=?utf-8?B?Y2Fmw6kudHh0?=\";\r\n name*=utf-8''caf%C3%A9.txt\r\n");
+/// ```
+pub fn content_type<P: WriterPolicy>(media: &MediaType, rfc2047_fallback: bool) -> Result<String, &'static str> {
+    let mut words = vec![media.essence()];
+    parameter_list_words::<P>(&mut words, &media.parameters, rfc2047_fallback)?;
+
+    Ok(fold_words("Content-Type", words))
+}
+
+/// Serialize a `"Content-Disposition:"` header.
+///
+/// See [`content_type`] for how parameters are written.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::{Intl, Legacy};
+/// use rustyknife::headerwriter::content_disposition;
+/// use rustyknife::rfc2231::ContentDisposition;
+///
+/// let params = [("filename".to_string(), "Überweisung März.pdf".to_string())];
+///
+/// assert_eq!(content_disposition::<Legacy>(&ContentDisposition::Attachment, &params, false).unwrap(),
+///            "Content-Disposition: attachment;\r\n filename*=utf-8''%C3%9Cberweisung%20M%C3%A4rz.pdf\r\n");
+/// assert_eq!(content_disposition::<Intl>(&ContentDisposition::Attachment, &params, false).unwrap(),
+///            "Content-Disposition: attachment; filename=\"Überweisung März.pdf\"\r\n");
+/// ```
+pub fn content_disposition<P: WriterPolicy>(disposition: &ContentDisposition, parameters: &[(String, String)],
+                                            rfc2047_fallback: bool) -> Result<String, &'static str>
+{
+    let mut words = vec![disposition.to_string()];
+    parameter_list_words::<P>(&mut words, parameters, rfc2047_fallback)?;
+
+    Ok(fold_words("Content-Disposition", words))
+}
+
+// Split text into words each preceded by its whitespace.
+fn split_ws(text: &str) -> Vec<(&str, &str)> {
+    let is_ws = |c: char| c == ' ' || c == '\t';
+    let mut out = Vec::new();
+    let mut rem = text;
+
+    while !rem.is_empty() {
+        let ws_end = rem.find(|c| !is_ws(c)).unwrap_or(rem.len());
+        let word_end = rem[ws_end..].find(is_ws).map(|i| i + ws_end).unwrap_or(rem.len());
+
+        out.push((&rem[..ws_end], &rem[ws_end..word_end]));
+        rem = &rem[word_end..];
+    }
+
+    out
+}
+
+/// Serialize an unstructured header such as `"Subject:"`.
+///
+/// Returns the folded header line including the terminating CRLF.
+/// Whitespace in the text is preserved.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::{Intl, Legacy};
+/// use rustyknife::headerwriter::unstructured;
+///
+/// assert_eq!(unstructured::<Legacy>("Subject", "Café au lait"),
+///            "Subject: =?utf-8?B?Q2Fmw6k=?= au lait\r\n");
+/// assert_eq!(unstructured::<Intl>("Subject", "Café au lait"),
+///            "Subject: Café au lait\r\n");
+/// ```
+pub fn unstructured<P: WriterPolicy>(name: &str, text: &str) -> String {
+    let mut folder = Folder::new(name);
+    let words = split_ws(text);
+    let mut i = 0;
+
+    while i < words.len() {
+        let (ws, word) = words[i];
+        let ws = if i == 0 { format!(" {}", ws) } else { ws.into() };
+
+        // Words that are too long to be folded are encoded so they
+        // can be split.
+        if !needs_encoding::<P>(word) && word.len() < MAX_LINE_LEN - 2 {
+            folder.push(&ws, word);
+            i += 1;
+            continue;
+        }
+
+        // Adjacent encoded words are joined when decoding so the
+        // whitespace between them must be encoded too.
+        let start = i;
+        i += 1;
+        while i < words.len() && !words[i].1.is_empty() && needs_encoding::<P>(words[i].1) {
+            i += 1;
+        }
+        let run: String = words[start..i].iter().enumerate()
+            .flat_map(|(j, (ws, word))| if j == 0 { vec![*word] } else { vec![*ws, *word] })
+            .collect();
+
+        for (j, encoded) in encode(&run, Context::Text).into_iter().enumerate() {
+            folder.push(if j == 0 { &ws } else { " " }, &encoded);
+        }
+    }
+
+    folder.finish()
+}
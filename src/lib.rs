@@ -18,6 +18,30 @@ pub mod behaviour {
     ///  * Activates message/global (RFC6532) support for message content.
     ///  * Activates SMTPUTF8 support for SMTP.
     pub struct Intl;
+
+    /// Wraps another behaviour to keep the comments found in
+    /// structured headers.
+    ///
+    /// Comments are ignored by default. When parsing with
+    /// `Commented<Intl>` or `Commented<Legacy>`, the comments
+    /// attached to each [`crate::rfc5322::Mailbox`] and
+    /// [`crate::rfc5322::Group`] are stored in their `comments`
+    /// field.
+    pub struct Commented<P>(std::marker::PhantomData<P>);
+
+    /// Wraps another behaviour to accept the obsolete syntax from
+    /// [RFC 5322 section 4].
+    ///
+    /// Activates support for CFWS between the atoms of local parts and
+    /// domains, source routes in angle addresses, empty list elements
+    /// and periods in display names. Addresses parsed using obsolete
+    /// syntax have their `obsolete` flag set.
+    ///
+    /// Can be combined with [`Commented`], as in
+    /// `Commented<Obsolete<Intl>>`.
+    ///
+    /// [RFC 5322 section 4]: https://tools.ietf.org/html/rfc5322#section-4
+    pub struct Obsolete<P>(std::marker::PhantomData<P>);
 }
 
 #[macro_use]
@@ -25,12 +49,29 @@ mod util;
 mod rfc5234;
 pub mod rfc2047;
 pub mod rfc2231;
+pub mod rfc2045;
 pub mod rfc5321;
 pub mod rfc5322;
 pub mod rfc3461;
+pub mod rfc3464;
+pub mod rfc6376;
+pub mod rfc8601;
+pub mod arc;
+pub mod rfc7208;
+pub mod dns;
+pub mod spf;
+pub mod rfc7489;
+pub mod dmarc;
 pub mod types;
 pub mod headersection;
+pub mod mime;
+pub mod attachment;
+pub mod headerwriter;
 pub mod xforward;
+#[cfg(feature = "dkim")]
+pub mod dkim;
+#[cfg(feature = "dmarc-report")]
+pub mod dmarc_report;
 
 #[cfg(feature = "python")]
 mod pymod;
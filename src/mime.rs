@@ -0,0 +1,583 @@
+//! [MIME] entity tree parser
+//!
+//! Splits a message into its MIME entities. Multipart bodies are
+//! split recursively at their boundary into a preamble, parts and an
+//! epilogue. Headers are split with [`header`] and the result refers
+//! to the original message, nothing is copied.
+//!
+//! Malformed input is handled in the following ways:
+//! - A missing close delimiter makes the last part extend to the end
+//!   of the body.
+//! - Delimiter lines must match the boundary exactly, so a longer
+//!   nested boundary or a line that merely starts with the boundary
+//!   inside encoded content is not taken as a delimiter.
+//! - Delimiter lines may end with a bare LF.
+//! - A multipart entity without a boundary parameter is not split.
+//!
+//! The bodies of `"message/rfc822"` and `"message/global"` entities
+//! are parsed as nested messages, as are the headers of the external
+//! body of `"message/external-body"` entities. Fragmented
+//! `"message/partial"` messages can be joined with [`reassemble`].
+//!
+//! [MIME]: https://tools.ietf.org/html/rfc2046#section-5.1
+
+use std::ops::Range;
+
+use encoding::all::{ASCII, UTF_16BE, UTF_16LE, UTF_8};
+use encoding::label::encoding_from_whatwg_label;
+use encoding::types::EncodingRef;
+
+use crate::headersection::{header, HeaderField};
+use crate::rfc2045::decode;
+use crate::rfc2231::{content_disposition, content_transfer_encoding, media_type, ContentDisposition, ContentTransferEncoding, MediaType};
+
+/// The default maximum nesting depth of multipart and message
+/// entities. Deeper entities are not split.
+pub const MAX_DEPTH: usize = 50;
+
+/// The body of a multipart entity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Multipart<'a> {
+    /// The boundary parameter.
+    pub boundary: String,
+    /// The range of the text before the first delimiter.
+    pub preamble: Range<usize>,
+    /// The body parts.
+    pub parts: Vec<Entity<'a>>,
+    /// The range of the text after the close delimiter.
+    pub epilogue: Range<usize>,
+    /// Whether the close delimiter was found.
+    pub closed: bool,
+}
+
+/// A MIME entity, either the message itself or a body part.
+///
+/// Ranges are offsets in the message passed to [`parse`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entity<'a> {
+    /// The header fields.
+    pub headers: Vec<HeaderField<'a>>,
+    /// The media type from the `"Content-Type:"` header.
+    ///
+    /// Defaults to `"text/plain; charset=us-ascii"` or to
+    /// `"message/rfc822"` inside a `"multipart/digest"` entity if the
+    /// header is missing or invalid.
+    pub content_type: MediaType,
+    /// The range of the entity including its headers.
+    pub range: Range<usize>,
+    /// The range of the body.
+    pub body: Range<usize>,
+    /// The split body of a multipart entity.
+    pub multipart: Option<Multipart<'a>>,
+    /// The nested message of a `"message/rfc822"` or
+    /// `"message/global"` entity, or the external body headers of a
+    /// `"message/external-body"` entity.
+    ///
+    /// Only set if the body has no transfer encoding.
+    pub message: Option<Box<Entity<'a>>>,
+}
+
+impl<'a> Entity<'a> {
+    /// Return the raw value of the first header named `name`.
+    pub fn header(&self, name: &str) -> Option<&'a [u8]> {
+        self.headers.iter().find_map(|h| match h {
+            Ok((n, value)) if n.eq_ignore_ascii_case(name.as_bytes()) => Some(*value),
+            _ => None,
+        })
+    }
+
+    /// Return the value of the `"Content-Type:"` parameter `name`.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.content_type.parameter(name)
+    }
+
+    /// Return the disposition and parameters from the
+    /// `"Content-Disposition:"` header if it is present and valid.
+    pub fn disposition(&self) -> Option<(ContentDisposition, Vec<(String, String)>)> {
+        self.header("Content-Disposition")
+            .and_then(|value| content_disposition(value).ok())
+            .map(|(_, disposition)| disposition)
+    }
+
+    /// Return the encoding from the `"Content-Transfer-Encoding:"`
+    /// header. Defaults to `"7bit"` if the header is missing or
+    /// invalid.
+    pub fn transfer_encoding(&self) -> ContentTransferEncoding {
+        self.header("Content-Transfer-Encoding")
+            .and_then(|value| exact!(value, content_transfer_encoding).ok())
+            .map(|(_, encoding)| encoding)
+            .unwrap_or(ContentTransferEncoding::SevenBit)
+    }
+
+    /// Decode the body with its transfer encoding.
+    ///
+    /// `message` is the message given to [`parse`]. Returns the
+    /// decoded body and the first problem found while decoding, if
+    /// any.
+    pub fn decode_body(&self, message: &[u8]) -> (Vec<u8>, Option<&'static str>) {
+        decode(&self.transfer_encoding(), &message[self.body.clone()])
+    }
+
+    /// Return the fragment information of a `"message/partial"`
+    /// entity.
+    pub fn partial(&self) -> Option<Partial> {
+        if self.content_type.essence() != "message/partial" {
+            return None;
+        }
+
+        Some(Partial {
+            id: self.parameter("id").filter(|id| !id.is_empty())?.into(),
+            number: self.parameter("number")?.trim().parse().ok().filter(|n| *n > 0)?,
+            total: self.parameter("total").and_then(|t| t.trim().parse().ok()),
+        })
+    }
+
+    /// Return the reference of a `"message/external-body"` entity.
+    pub fn external_body(&self) -> Option<ExternalBody<'_, 'a>> {
+        if self.content_type.essence() != "message/external-body" {
+            return None;
+        }
+
+        Some(ExternalBody {
+            access_type: self.parameter("access-type")?.to_lowercase(),
+            expiration: self.parameter("expiration").map(String::from),
+            size: self.parameter("size").and_then(|s| s.trim().parse().ok()),
+            permission: self.parameter("permission").map(str::to_lowercase),
+            url: self.parameter("url").map(|u| u.split_whitespace().collect()),
+            headers: self.message.as_deref(),
+        })
+    }
+
+    /// Decode a `"text/*"` body to UTF-8.
+    ///
+    /// The body is decoded with its transfer encoding and then with
+    /// its `"charset"` parameter as described in [`decode_text`].
+    /// Returns `None` for other types.
+    pub fn decode_text(&self, message: &[u8], fallback: EncodingRef) -> Option<DecodedText> {
+        if !self.content_type.is_text() {
+            return None;
+        }
+
+        let (bytes, transfer_error) = self.decode_body(message);
+        let mut out = decode_text(&bytes, self.parameter("charset"), fallback);
+        out.transfer_error = transfer_error;
+        Some(out)
+    }
+}
+
+/// The fragment information of a `"message/partial"` entity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partial {
+    /// The identifier shared by all fragments.
+    pub id: String,
+    /// The fragment number, starting at 1.
+    pub number: u32,
+    /// The number of fragments, required on the last fragment.
+    pub total: Option<u32>,
+}
+
+/// The reference of a `"message/external-body"` entity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExternalBody<'e, 'a> {
+    /// The access method in lowercase, such as `"url"` or
+    /// `"anon-ftp"`.
+    pub access_type: String,
+    /// The date after which the body may no longer exist.
+    pub expiration: Option<String>,
+    /// The size of the body in octets.
+    pub size: Option<u64>,
+    /// Whether the body may be overwritten, `"read"` or
+    /// `"read-write"`.
+    pub permission: Option<String>,
+    /// The URL of the `"url"` access type, with whitespace from
+    /// folding removed.
+    pub url: Option<String>,
+    /// The headers of the external body, such as its
+    /// `"Content-Type:"`.
+    pub headers: Option<&'e Entity<'a>>,
+}
+
+/// Text converted to UTF-8 by [`decode_text`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedText {
+    /// The decoded text.
+    pub text: String,
+    /// The name of the charset used for decoding.
+    pub charset: String,
+    /// Whether the declared charset is unknown and the fallback was
+    /// used instead.
+    pub unknown_charset: bool,
+    /// Whether a byte order mark was found and removed.
+    pub bom: bool,
+    /// The number of invalid sequences replaced with U+FFFD.
+    pub invalid_sequences: usize,
+    /// The first problem found while undoing the transfer encoding.
+    pub transfer_error: Option<&'static str>,
+}
+
+// Decode with U+FFFD replacement, returning the number of replaced
+// sequences.
+fn decode_counting(codec: EncodingRef, input: &[u8], out: &mut String) -> usize {
+    let mut decoder = codec.raw_decoder();
+    let mut remaining = 0;
+    let mut invalid = 0;
+
+    loop {
+        let (_, err) = decoder.raw_feed(&input[remaining..], out);
+        match err {
+            Some(err) => {
+                remaining = (remaining as isize + err.upto) as usize;
+                out.push('\u{fffd}');
+                invalid += 1;
+            }
+            None => {
+                remaining = input.len();
+                if let Some(err) = decoder.raw_finish(out) {
+                    remaining = (remaining as isize + err.upto) as usize;
+                    out.push('\u{fffd}');
+                    invalid += 1;
+                }
+                if remaining >= input.len() {
+                    return invalid;
+                }
+            }
+        }
+    }
+}
+
+/// Convert text in `charset` to UTF-8.
+///
+/// A byte order mark overrides the declared charset and is removed.
+/// Undeclared and `"us-ascii"` text containing 8-bit bytes is taken
+/// as UTF-8 if it is valid UTF-8 and decoded with `fallback`
+/// otherwise. Unknown charsets are also decoded with `fallback`.
+/// Invalid sequences are replaced with U+FFFD and counted.
+/// # Examples
+/// ```
+/// use encoding::all::WINDOWS_1252;
+/// use rustyknife::mime::decode_text;
+///
+/// let text = decode_text(b"caf\xe9", Some("iso-8859-1"), WINDOWS_1252);
+/// assert_eq!(text.text, "café");
+///
+/// let text = decode_text(b"caf\xe9", None, WINDOWS_1252);
+/// assert_eq!((&text.text[..], &text.charset[..]), ("café", "windows-1252"));
+///
+/// let text = decode_text(b"caf\xc3\xa9", Some("us-ascii"), WINDOWS_1252);
+/// assert_eq!((&text.text[..], &text.charset[..]), ("café", "utf-8"));
+///
+/// let text = decode_text(b"\xef\xbb\xbfhi \xff", Some("x-unknown"), WINDOWS_1252);
+/// assert_eq!(text.text, "hi \u{fffd}");
+/// assert!(text.bom && text.invalid_sequences == 1 && !text.unknown_charset);
+/// ```
+pub fn decode_text(bytes: &[u8], charset: Option<&str>, fallback: EncodingRef) -> DecodedText {
+    let boms: [(&[u8], EncodingRef); 3] = [(b"\xef\xbb\xbf", UTF_8), (b"\xff\xfe", UTF_16LE), (b"\xfe\xff", UTF_16BE)];
+    let charset = charset.map(str::trim).filter(|c| !c.is_empty());
+    let mut unknown_charset = false;
+    let mut input = bytes;
+
+    let bom = boms.iter().find(|(bom, _)| bytes.starts_with(bom));
+    let codec = match (bom, charset) {
+        (Some((bom, codec)), _) => {
+            input = &bytes[bom.len()..];
+            Some(*codec)
+        }
+        (None, None) => None,
+        (None, Some(c)) if c.eq_ignore_ascii_case("us-ascii") || c.eq_ignore_ascii_case("ascii") => None,
+        (None, Some(c)) => match encoding_from_whatwg_label(c) {
+            Some(codec) => Some(codec),
+            None => {
+                unknown_charset = true;
+                Some(fallback)
+            }
+        },
+    }.unwrap_or_else(|| {
+        if input.is_ascii() {
+            ASCII
+        } else if std::str::from_utf8(input).is_ok() {
+            UTF_8
+        } else {
+            fallback
+        }
+    });
+
+    let mut text = String::with_capacity(input.len());
+    let invalid_sequences = decode_counting(codec, input, &mut text);
+
+    DecodedText {
+        text,
+        charset: codec.whatwg_name().unwrap_or_else(|| codec.name()).into(),
+        unknown_charset,
+        bom: bom.is_some(),
+        invalid_sequences,
+        transfer_error: None,
+    }
+}
+
+// A delimiter line including the line break that precedes it.
+struct Delimiter {
+    start: usize,
+    end: usize,
+    close: bool,
+}
+
+// Check for a delimiter line at `pos`, returning the end of the line
+// and whether it is the close delimiter.
+fn delimiter_at(body: &[u8], pos: usize, boundary: &[u8]) -> Option<(usize, bool)> {
+    let line = &body[pos..];
+    if !line.starts_with(b"--") || !line[2..].starts_with(boundary) {
+        return None;
+    }
+    let mut end = 2 + boundary.len();
+    let close = line[end..].starts_with(b"--");
+    if close {
+        end += 2;
+    }
+    while end < line.len() && (line[end] == b' ' || line[end] == b'\t') {
+        end += 1;
+    }
+
+    if end == line.len() {
+        Some((pos + end, close))
+    } else if line[end..].starts_with(b"\r\n") {
+        Some((pos + end + 2, close))
+    } else if line[end] == b'\n' {
+        Some((pos + end + 1, close))
+    } else {
+        None
+    }
+}
+
+// Find the delimiters up to and including the close delimiter.
+fn delimiters(body: &[u8], boundary: &[u8]) -> Vec<Delimiter> {
+    let line_starts = std::iter::once(0)
+        .chain(body.iter().enumerate().filter(|(_, c)| **c == b'\n').map(|(i, _)| i + 1));
+    let mut out = Vec::new();
+    let mut skip_to = 0;
+
+    for pos in line_starts {
+        if pos < skip_to || pos >= body.len() {
+            continue;
+        }
+        if let Some((end, close)) = delimiter_at(body, pos, boundary) {
+            let start = if pos >= 2 && &body[pos-2..pos] == b"\r\n" {
+                pos - 2
+            } else {
+                pos.saturating_sub(1)
+            };
+            out.push(Delimiter { start, end, close });
+            if close {
+                break;
+            }
+            skip_to = end;
+        }
+    }
+
+    out
+}
+
+// Split the header section of the entity at `range`. The header
+// parser needs to see past the end of a line so the rest of the
+// message is given to it, but fields are only kept if they end within
+// the entity or on the line break of the following delimiter.
+fn headers<'a>(message: &'a [u8], range: &Range<usize>) -> (Vec<HeaderField<'a>>, usize) {
+    let data = &message[range.start..];
+    let len = range.len();
+    let mut out = Vec::new();
+    let mut pos = 0;
+
+    while pos < len {
+        match header(&data[pos..]) {
+            Ok((rem, field)) if data.len() - rem.len() <= len + 2 => {
+                pos = data.len() - rem.len();
+                match field {
+                    Some(field) => out.push(field),
+                    None => break,
+                }
+            }
+            _ => break,
+        }
+    }
+
+    (out, range.start + pos.min(len))
+}
+
+fn entity<'a>(message: &'a [u8], range: Range<usize>, default_type: &str, depth: usize, max_depth: usize) -> Entity<'a> {
+    let (headers, body_start) = headers(message, &range);
+    let mut out = Entity {
+        headers,
+        content_type: MediaType::new(default_type),
+        body: body_start..range.end,
+        range,
+        multipart: None,
+        message: None,
+    };
+
+    match out.header("Content-Type").map(media_type) {
+        Some(Ok((_, content_type))) => out.content_type = content_type,
+        _ if default_type == "text/plain" => out.content_type.set_parameter("charset", "us-ascii"),
+        _ => (),
+    }
+
+    if depth >= max_depth {
+        return out;
+    }
+
+    if out.content_type.is_multipart() {
+        if let Some(boundary) = out.content_type.boundary().filter(|b| !b.is_empty()) {
+            let child_type = if out.content_type.subtype == "digest" { "message/rfc822" } else { "text/plain" };
+            out.multipart = Some(multipart(message, out.body.clone(), boundary.into(), child_type, depth + 1, max_depth));
+        }
+    } else if out.content_type.mtype == "message"
+        && ["rfc822", "global", "external-body"].contains(&&out.content_type.subtype[..])
+        && matches!(out.transfer_encoding(), ContentTransferEncoding::SevenBit
+                    | ContentTransferEncoding::EightBit | ContentTransferEncoding::Binary)
+    {
+        out.message = Some(Box::new(entity(message, out.body.clone(), "text/plain", depth + 1, max_depth)));
+    }
+
+    out
+}
+
+fn multipart<'a>(message: &'a [u8], body: Range<usize>, boundary: String, child_type: &str, depth: usize, max_depth: usize)
+                 -> Multipart<'a>
+{
+    let found = delimiters(&message[body.clone()], boundary.as_bytes());
+    let offset = |pos: usize| body.start + pos;
+
+    let preamble = match found.first() {
+        Some(d) => body.start..offset(d.start),
+        None => body.clone(),
+    };
+    let parts = found.iter().zip(found.iter().skip(1).map(Some).chain(std::iter::once(None)))
+        .filter(|(d, _)| !d.close)
+        .map(|(d, next)| {
+            let end = next.map(|n| offset(n.start)).unwrap_or(body.end);
+            entity(message, offset(d.end)..end, child_type, depth, max_depth)
+        }).collect();
+    let (epilogue, closed) = match found.last() {
+        Some(d) if d.close => (offset(d.end)..body.end, true),
+        _ => (body.end..body.end, false),
+    };
+
+    Multipart { boundary, preamble, parts, epilogue, closed }
+}
+
+/// Parse a message into its tree of MIME entities.
+/// # Examples
+/// ```
+/// use rustyknife::mime::parse;
+///
+/// let message = b"Content-Type: multipart/mixed; boundary=frontier\r\n\r\n\
+///                 This is a preamble.\r\n\
+///                 --frontier\r\n\
+///                 Content-Type: text/plain\r\n\r\n\
+///                 Hello\r\n\
+///                 --frontier\r\n\
+///                 Content-Type: application/octet-stream\r\n\
+///                 Content-Transfer-Encoding: base64\r\n\r\n\
+///                 AAECAw==\r\n\
+///                 --frontier--\r\n";
+///
+/// let root = parse(message);
+/// assert_eq!(root.content_type.essence(), "multipart/mixed");
+///
+/// let multipart = root.multipart.unwrap();
+/// assert_eq!(&message[multipart.preamble], b"This is a preamble.");
+/// assert_eq!(multipart.parts.len(), 2);
+/// assert_eq!(&message[multipart.parts[0].body.clone()], b"Hello");
+/// assert_eq!(multipart.parts[1].header("Content-Transfer-Encoding"), Some(b" base64".as_ref()));
+/// ```
+pub fn parse(message: &[u8]) -> Entity<'_> {
+    parse_with_depth(message, MAX_DEPTH)
+}
+
+/// Parse a message into its tree of MIME entities, nesting multipart
+/// and message entities at most `max_depth` levels deep.
+pub fn parse_with_depth(message: &[u8], max_depth: usize) -> Entity<'_> {
+    entity(message, 0..message.len(), "text/plain", 0, max_depth)
+}
+
+// Fields taken from the enclosed message rather than the enclosing
+// one, in addition to those starting with "Content-".
+const ENCLOSED_FIELDS: [&str; 4] = ["Subject", "Message-ID", "Encrypted", "MIME-Version"];
+
+fn is_enclosed_field(field: &HeaderField) -> bool {
+    match field {
+        Ok((name, _)) => (name.len() >= 8 && name[..8].eq_ignore_ascii_case(b"Content-"))
+            || ENCLOSED_FIELDS.iter().any(|n| name.eq_ignore_ascii_case(n.as_bytes())),
+        Err(_) => false,
+    }
+}
+
+fn write_field(out: &mut Vec<u8>, field: &HeaderField) {
+    if let Ok((name, value)) = field {
+        out.extend_from_slice(name);
+        out.push(b':');
+        out.extend_from_slice(value);
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Join the fragments of a `"message/partial"` message.
+///
+/// The fragments may be given in any order. They must all have the
+/// same id and every fragment up to the total given in one of them
+/// must be present. The headers of the result are built from the
+/// first fragment as described in [RFC 2046].
+///
+/// [RFC 2046]: https://tools.ietf.org/html/rfc2046#section-5.2.2.2
+/// # Examples
+/// ```
+/// use rustyknife::mime::reassemble;
+///
+/// let one = b"From: joe@example.com\r\nSubject: Part 1 of 2\r\n\
+///             Content-Type: message/partial; id=\"abc@example.com\"; number=1\r\n\r\n\
+///             Subject: Holiday pictures\r\nContent-Type: text/plain\r\n\r\nFirst half, ";
+/// let two = b"From: joe@example.com\r\nSubject: Part 2 of 2\r\n\
+///             Content-Type: message/partial; id=\"abc@example.com\"; number=2; total=2\r\n\r\n\
+///             second half.";
+///
+/// assert_eq!(reassemble(&[two, one]).unwrap(),
+///            b"From: joe@example.com\r\nSubject: Holiday pictures\r\n\
+///              Content-Type: text/plain\r\n\r\nFirst half, second half.".to_vec());
+/// ```
+pub fn reassemble(fragments: &[&[u8]]) -> Result<Vec<u8>, &'static str> {
+    let mut parsed = fragments.iter()
+        .map(|f| {
+            let entity = parse_with_depth(f, 0);
+            let partial = entity.partial().ok_or("Not a partial message")?;
+            Ok((partial, entity, *f))
+        }).collect::<Result<Vec<_>, &str>>()?;
+    parsed.sort_by_key(|(partial, _, _)| partial.number);
+
+    let (first, enclosing, _) = parsed.first().ok_or("No fragments")?;
+    if parsed.iter().any(|(p, _, _)| p.id != first.id) {
+        return Err("Mismatched partial id");
+    }
+    let total = parsed.iter().find_map(|(p, _, _)| p.total).ok_or("Unknown fragment total")?;
+    if parsed.windows(2).any(|w| w[0].0.number == w[1].0.number) {
+        return Err("Duplicate fragment");
+    }
+    if parsed.len() != total as usize || parsed.last().unwrap().0.number != total {
+        return Err("Missing fragment");
+    }
+
+    let mut body = Vec::new();
+    for (_, entity, data) in &parsed {
+        body.extend_from_slice(&data[entity.body.clone()]);
+    }
+    let enclosed = parse_with_depth(&body, 0);
+
+    let mut out = Vec::with_capacity(body.len());
+    for field in enclosing.headers.iter().filter(|f| !is_enclosed_field(f)) {
+        write_field(&mut out, field);
+    }
+    for field in enclosed.headers.iter().filter(|f| is_enclosed_field(f)) {
+        write_field(&mut out, field);
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(&body[enclosed.body]);
+
+    Ok(out)
+}
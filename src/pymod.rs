@@ -2,7 +2,7 @@ use std::fmt::Debug;
 use std::fs::File;
 
 use crate::behaviour::{Legacy, Intl};
-use crate::rfc2231::{content_type, content_disposition, content_transfer_encoding};
+use crate::rfc2231::{_content_type, content_disposition, content_transfer_encoding};
 use crate::rfc3461::{orcpt_address, dsn_mail_params, DSNMailParams, DSNRet};
 use crate::rfc5321::{Param as ESMTPParam, mail_command, rcpt_command, validate_address, ForwardPath, ReversePath};
 use crate::rfc5322::{Address, Mailbox, Group, from, sender, reply_to, unstructured};
@@ -230,7 +230,7 @@ fn rustyknife(_py: Python, m: &PyModule) -> PyResult<()> {
     /// content_type(input, all=False)
     #[pyfn(m, "content_type", input, all=false)]
     fn py_content_type(input: &PyBytes, all: bool) -> PyResult<(String, Vec<(String, String)>)> {
-        convert_result(content_type(input.as_bytes()), all)
+        convert_result(_content_type(input.as_bytes()), all)
     }
 
     /// content_disposition(input, all=False)
@@ -0,0 +1,474 @@
+//! [Multipurpose Internet Mail Extensions (MIME) Part One]
+//!
+//! Streaming decoders and encoders for the body encodings identified
+//! by [`ContentTransferEncoding`] and selection of the encoding to use
+//! for a body.
+//!
+//! Decoding is lenient: problems are reported but never stop the
+//! decoding, the output is always complete. Unknown encodings are
+//! passed through as is by both the decoders and the encoders.
+//!
+//! [Multipurpose Internet Mail Extensions (MIME) Part One]: https://tools.ietf.org/html/rfc2045#section-6
+
+use crate::rfc2231::ContentTransferEncoding;
+
+fn base64_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+#[derive(Clone, Debug)]
+struct Base64State {
+    quantum: [u8; 4],
+    len: usize,
+    // An "=" was seen after two characters, another one is expected.
+    pad_pending: bool,
+    // The padding was complete.
+    padded: bool,
+}
+
+#[derive(Clone, Debug)]
+enum State {
+    Identity,
+    Base64(Base64State),
+    // Bytes at the end of the last chunk that could not be decoded
+    // without the following ones.
+    QuotedPrintable(Vec<u8>),
+}
+
+/// A streaming body decoder.
+///
+/// Input can be split at any point between calls to
+/// [`update`](Decoder::update).
+/// # Examples
+/// ```
+/// use rustyknife::rfc2045::Decoder;
+/// use rustyknife::rfc2231::ContentTransferEncoding;
+///
+/// let mut decoder = Decoder::new(&ContentTransferEncoding::QuotedPrintable);
+/// let mut out = Vec::new();
+/// decoder.update(b"caf=C3=", &mut out);
+/// decoder.update(b"a9 au =\r\nlait", &mut out);
+///
+/// assert_eq!(decoder.finish(&mut out), None);
+/// assert_eq!(out, "café au lait".as_bytes());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    state: State,
+    error: Option<&'static str>,
+}
+
+impl Decoder {
+    /// Create a decoder for `encoding`.
+    pub fn new(encoding: &ContentTransferEncoding) -> Self {
+        let state = match encoding {
+            ContentTransferEncoding::Base64 => State::Base64(Base64State { quantum: [0; 4], len: 0, pad_pending: false, padded: false }),
+            ContentTransferEncoding::QuotedPrintable => State::QuotedPrintable(Vec::new()),
+            _ => State::Identity,
+        };
+
+        Decoder { state, error: None }
+    }
+
+    fn report(&mut self, error: &'static str) {
+        self.error.get_or_insert(error);
+    }
+
+    /// Decode a chunk of input, appending the result to `out`.
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        match &mut self.state {
+            State::Identity => out.extend_from_slice(input),
+            State::Base64(state) => {
+                let error = base64_update(state, input, out);
+                if let Some(error) = error {
+                    self.report(error);
+                }
+            }
+            State::QuotedPrintable(pending) => {
+                let mut buf = std::mem::take(pending);
+                buf.extend_from_slice(input);
+                let (rest, error) = qp_update(&buf, out, false);
+                *pending = rest.to_vec();
+                if let Some(error) = error {
+                    self.report(error);
+                }
+            }
+        }
+    }
+
+    /// Flush the remaining input to `out`.
+    ///
+    /// Returns the first problem found while decoding, if any.
+    pub fn finish(mut self, out: &mut Vec<u8>) -> Option<&'static str> {
+        match std::mem::replace(&mut self.state, State::Identity) {
+            State::Identity => (),
+            State::Base64(state) => {
+                match state.len {
+                    0 if state.pad_pending => self.report("Missing base64 padding"),
+                    0 => (),
+                    1 => self.report("Truncated base64"),
+                    _ => {
+                        base64_flush(&state, out);
+                        self.report("Missing base64 padding");
+                    }
+                }
+            }
+            State::QuotedPrintable(pending) => {
+                if let (_, Some(error)) = qp_update(&pending, out, true) {
+                    self.report(error);
+                }
+            }
+        }
+
+        self.error
+    }
+}
+
+// Output the bytes of a partial quantum.
+fn base64_flush(state: &Base64State, out: &mut Vec<u8>) {
+    let q = &state.quantum;
+    let bytes = [q[0] << 2 | q[1] >> 4, q[1] << 4 | q[2] >> 2, q[2] << 6 | q[3]];
+    out.extend_from_slice(&bytes[..state.len.saturating_sub(1)]);
+}
+
+// Whitespace and characters outside of the alphabet are ignored.
+fn base64_update(state: &mut Base64State, input: &[u8], out: &mut Vec<u8>) -> Option<&'static str> {
+    let mut error = None;
+
+    for &c in input {
+        if c == b'=' {
+            match state.len {
+                2 => {
+                    base64_flush(state, out);
+                    state.pad_pending = true;
+                }
+                3 => {
+                    base64_flush(state, out);
+                    state.padded = true;
+                }
+                0 if state.pad_pending => {
+                    state.pad_pending = false;
+                    state.padded = true;
+                }
+                _ => {
+                    error.get_or_insert("Invalid base64 padding");
+                }
+            }
+            state.len = 0;
+            continue;
+        }
+
+        let value = match base64_value(c) {
+            Some(value) => value,
+            None => continue,
+        };
+        if state.pad_pending || state.padded {
+            error.get_or_insert(if state.pad_pending { "Missing base64 padding" } else { "Data after base64 padding" });
+            state.pad_pending = false;
+            state.padded = false;
+        }
+        state.quantum[state.len] = value;
+        state.len += 1;
+        if state.len == 4 {
+            base64_flush(state, out);
+            state.len = 0;
+        }
+    }
+
+    error
+}
+
+// Decode as much of `input` as possible and return the undecoded
+// rest. With `last` set, everything is decoded.
+fn qp_update<'a>(input: &'a [u8], out: &mut Vec<u8>, last: bool) -> (&'a [u8], Option<&'static str>) {
+    let is_ws = |c: u8| c == b' ' || c == b'\t';
+    let mut error = None;
+    let mut i = 0;
+
+    while i < input.len() {
+        match input[i] {
+            b'=' => {
+                if i + 2 >= input.len() && !last {
+                    return (&input[i..], error);
+                }
+                if let (Some(hi), Some(lo)) = (input.get(i+1).and_then(|c| hex_value(*c)), input.get(i+2).and_then(|c| hex_value(*c))) {
+                    out.push(hi << 4 | lo);
+                    i += 3;
+                    continue;
+                }
+
+                // A soft line break, possibly with trailing whitespace.
+                let j = i + 1 + input[i+1..].iter().take_while(|c| is_ws(**c)).count();
+                if !last && (j == input.len() || (input[j] == b'\r' && j + 1 == input.len())) {
+                    return (&input[i..], error);
+                }
+                if input[j..].starts_with(b"\r\n") {
+                    i = j + 2;
+                } else if input[j..].starts_with(b"\n") || j == input.len() {
+                    i = (j + 1).min(input.len());
+                } else {
+                    error.get_or_insert("Invalid quoted-printable escape");
+                    out.push(b'=');
+                    i += 1;
+                }
+            }
+            c if is_ws(c) => {
+                let j = i + input[i..].iter().take_while(|c| is_ws(**c)).count();
+                if !last && (j == input.len() || (input[j] == b'\r' && j + 1 == input.len())) {
+                    return (&input[i..], error);
+                }
+                // Trailing whitespace is removed.
+                if !(j == input.len() || input[j..].starts_with(b"\r\n") || input[j] == b'\n') {
+                    out.extend_from_slice(&input[i..j]);
+                }
+                i = j;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    (&input[input.len()..], error)
+}
+
+/// Decode a whole body encoded with `encoding`.
+///
+/// Returns the decoded body and the first problem found while
+/// decoding, if any.
+/// # Examples
+/// ```
+/// use rustyknife::rfc2045::decode;
+/// use rustyknife::rfc2231::ContentTransferEncoding;
+///
+/// assert_eq!(decode(&ContentTransferEncoding::Base64, b"SGVs\r\nbG8=\r\n"), (b"Hello".to_vec(), None));
+/// assert_eq!(decode(&ContentTransferEncoding::Base64, b"SGVsbG8"), (b"Hello".to_vec(), Some("Missing base64 padding")));
+/// ```
+pub fn decode(encoding: &ContentTransferEncoding, input: &[u8]) -> (Vec<u8>, Option<&'static str>) {
+    let mut decoder = Decoder::new(encoding);
+    let mut out = Vec::with_capacity(input.len());
+    decoder.update(input, &mut out);
+    let error = decoder.finish(&mut out);
+
+    (out, error)
+}
+
+// The number of input bytes in a line of base64 output.
+const BASE64_LINE_BYTES: usize = 57;
+
+// The maximum length of an encoded line, excluding the CRLF.
+const MAX_ENCODED_LINE: usize = 76;
+
+#[derive(Clone, Debug)]
+enum EncoderState {
+    Identity,
+    // Bytes that do not fill a line yet.
+    Base64(Vec<u8>),
+    // The current line, up to its CRLF.
+    QuotedPrintable(Vec<u8>),
+}
+
+/// A streaming body encoder.
+///
+/// Base64 output is split in lines of 76 characters. Quoted-printable
+/// output keeps the CRLF line breaks of the input and uses soft line
+/// breaks to keep lines within 76 characters. Whitespace at the end of
+/// a line and all other line break characters are encoded.
+/// # Examples
+/// ```
+/// use rustyknife::rfc2045::Encoder;
+/// use rustyknife::rfc2231::ContentTransferEncoding;
+///
+/// let mut encoder = Encoder::new(&ContentTransferEncoding::QuotedPrintable);
+/// let mut out = Vec::new();
+/// encoder.update("café \r\n".as_bytes(), &mut out);
+/// encoder.update(b"1+1=2", &mut out);
+/// encoder.finish(&mut out);
+///
+/// assert_eq!(out, b"caf=C3=A9=20\r\n1+1=3D2");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    state: EncoderState,
+}
+
+impl Encoder {
+    /// Create an encoder for `encoding`.
+    pub fn new(encoding: &ContentTransferEncoding) -> Self {
+        let state = match encoding {
+            ContentTransferEncoding::Base64 => EncoderState::Base64(Vec::new()),
+            ContentTransferEncoding::QuotedPrintable => EncoderState::QuotedPrintable(Vec::new()),
+            _ => EncoderState::Identity,
+        };
+
+        Encoder { state }
+    }
+
+    /// Encode a chunk of input, appending the result to `out`.
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        match &mut self.state {
+            EncoderState::Identity => out.extend_from_slice(input),
+            EncoderState::Base64(pending) => {
+                pending.extend_from_slice(input);
+                let full = pending.len() - pending.len() % BASE64_LINE_BYTES;
+                for line in pending[..full].chunks(BASE64_LINE_BYTES) {
+                    out.extend_from_slice(base64::encode(line).as_bytes());
+                    out.extend_from_slice(b"\r\n");
+                }
+                pending.drain(..full);
+            }
+            EncoderState::QuotedPrintable(pending) => {
+                pending.extend_from_slice(input);
+                let mut start = 0;
+                while let Some(pos) = pending[start..].windows(2).position(|w| w == b"\r\n") {
+                    qp_line(&pending[start..start+pos], out);
+                    out.extend_from_slice(b"\r\n");
+                    start += pos + 2;
+                }
+                pending.drain(..start);
+            }
+        }
+    }
+
+    /// Flush the remaining input to `out`.
+    ///
+    /// Base64 output always ends with a CRLF. Quoted-printable output
+    /// ends with one only if the input did.
+    pub fn finish(self, out: &mut Vec<u8>) {
+        match self.state {
+            EncoderState::Identity => (),
+            EncoderState::Base64(pending) => {
+                if !pending.is_empty() {
+                    out.extend_from_slice(base64::encode(&pending).as_bytes());
+                    out.extend_from_slice(b"\r\n");
+                }
+            }
+            EncoderState::QuotedPrintable(pending) => qp_line(&pending, out),
+        }
+    }
+}
+
+// Encode a line without its CRLF.
+fn qp_line(line: &[u8], out: &mut Vec<u8>) {
+    let mut column = 0;
+
+    for (i, &c) in line.iter().enumerate() {
+        let last = i + 1 == line.len();
+        let literal = match c {
+            b' ' | b'\t' => !last,
+            b'=' => false,
+            33..=126 => true,
+            _ => false,
+        };
+        let width = if literal { 1 } else { 3 };
+        // Leave room for the "=" of a soft line break.
+        let limit = if last { MAX_ENCODED_LINE } else { MAX_ENCODED_LINE - 1 };
+
+        if column + width > limit {
+            out.extend_from_slice(b"=\r\n");
+            column = 0;
+        }
+        if literal {
+            out.push(c);
+        } else {
+            out.extend_from_slice(format!("={:02X}", c).as_bytes());
+        }
+        column += width;
+    }
+}
+
+/// Encode a whole body with `encoding`.
+/// # Examples
+/// ```
+/// use rustyknife::rfc2045::encode;
+/// use rustyknife::rfc2231::ContentTransferEncoding;
+///
+/// assert_eq!(encode(&ContentTransferEncoding::Base64, b"Hello"), b"SGVsbG8=\r\n");
+/// ```
+pub fn encode(encoding: &ContentTransferEncoding, input: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new(encoding);
+    let mut out = Vec::with_capacity(input.len() * 4 / 3 + 2);
+    encoder.update(input, &mut out);
+    encoder.finish(&mut out);
+
+    out
+}
+
+// The length of the base64 encoding of `len` bytes, line breaks
+// included.
+fn base64_len(len: usize) -> usize {
+    len.div_ceil(3) * 4 + len.div_ceil(BASE64_LINE_BYTES) * 2
+}
+
+/// The body types accepted by the SMTP server a message is sent to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BodyType {
+    /// Only 7bit data in lines of up to 998 octets.
+    SevenBit,
+    /// `"8BITMIME"`, 8bit data in lines of up to 998 octets.
+    EightBitMIME,
+    /// `"BINARYMIME"`, any data.
+    BinaryMIME,
+}
+
+/// Choose the transfer encoding of a body sent to a server accepting
+/// `accepted`.
+///
+/// The body is sent as is when possible. Otherwise quoted-printable
+/// is used for mostly ASCII text and base64 for everything else.
+/// # Examples
+/// ```
+/// use rustyknife::rfc2045::{choose_encoding, BodyType};
+/// use rustyknife::rfc2231::ContentTransferEncoding;
+///
+/// assert_eq!(choose_encoding(b"Hello\r\n", BodyType::SevenBit), ContentTransferEncoding::SevenBit);
+/// assert_eq!(choose_encoding("Café\r\n".as_bytes(), BodyType::SevenBit), ContentTransferEncoding::QuotedPrintable);
+/// assert_eq!(choose_encoding("Café\r\n".as_bytes(), BodyType::EightBitMIME), ContentTransferEncoding::EightBit);
+/// assert_eq!(choose_encoding(&[0, 1, 2, 255], BodyType::EightBitMIME), ContentTransferEncoding::Base64);
+/// assert_eq!(choose_encoding(&[0, 1, 2, 255], BodyType::BinaryMIME), ContentTransferEncoding::Binary);
+/// ```
+pub fn choose_encoding(input: &[u8], accepted: BodyType) -> ContentTransferEncoding {
+    let mut eight_bit = false;
+    // NUL, a CR or LF outside of a CRLF or a line that is too long.
+    let mut binary = false;
+    let mut qp_len = 0;
+    let mut line_len = 0;
+
+    for (i, &c) in input.iter().enumerate() {
+        match c {
+            b'\r' if input.get(i+1) == Some(&b'\n') => (),
+            b'\n' if i > 0 && input[i-1] == b'\r' => line_len = 0,
+            b'\r' | b'\n' | 0 => binary = true,
+            128..=255 => eight_bit = true,
+            _ => (),
+        }
+        if c != b'\r' && c != b'\n' {
+            line_len += 1;
+            binary |= line_len > 998;
+        }
+        qp_len += match c {
+            b'\t' | b'\r' | b'\n' | 32..=60 | 62..=126 => 1,
+            _ => 3,
+        };
+    }
+
+    match (binary, eight_bit, accepted) {
+        (false, false, _) => ContentTransferEncoding::SevenBit,
+        (false, true, BodyType::EightBitMIME) | (false, true, BodyType::BinaryMIME) => ContentTransferEncoding::EightBit,
+        (true, _, BodyType::BinaryMIME) => ContentTransferEncoding::Binary,
+        _ if qp_len <= base64_len(input.len()) => ContentTransferEncoding::QuotedPrintable,
+        _ => ContentTransferEncoding::Base64,
+    }
+}
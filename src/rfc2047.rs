@@ -5,13 +5,14 @@
 
 use std::borrow::Cow;
 
-use encoding::DecoderTrap;
-use encoding::all::ASCII;
+use encoding::{DecoderTrap, EncoderTrap};
+use encoding::all::{ASCII, UTF_8};
+use encoding::types::{Encoding, EncodingRef};
 use encoding::label::encoding_from_whatwg_label;
 
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while1};
-use nom::combinator::{map, opt};
+use nom::combinator::{map, opt, verify};
 use nom::multi::many0;
 use nom::sequence::{delimited, preceded, terminated, tuple};
 
@@ -50,23 +51,177 @@ fn decode_text(encoding: &[u8], text: &[u8]) -> Option<Vec<u8>>
     }
 }
 
-fn _encoded_word(input: &[u8]) -> NomResult<(Cow<str>, Vec<u8>)> {
-    map(tuple((preceded(tag("=?"), token),
+fn charset(input: &[u8]) -> NomResult<'_, &[u8]> {
+    take_while1(|c| (33..=126).contains(&c) && !b"()<>@,;:\\\"/[]?.=*".contains(&c))(input)
+}
+
+/// The encoding of an encoded word.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WordEncoding {
+    /// Base64, `"B"`.
+    B,
+    /// Modified quoted-printable, `"Q"`.
+    Q,
+    /// Any other encoding, which is never decoded.
+    Unknown(String),
+}
+
+// An encoded word with the transfer encoding undone but not the
+// charset decoding.
+#[derive(Clone, Debug)]
+pub(crate) struct RawWord<'a> {
+    pub(crate) charset: Cow<'a, str>,
+    pub(crate) language: Option<Cow<'a, str>>,
+    pub(crate) encoding: WordEncoding,
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) failed: bool,
+}
+
+pub(crate) fn raw_word(input: &[u8]) -> NomResult<'_, RawWord<'_>> {
+    map(tuple((preceded(tag("=?"), charset),
                opt(preceded(tag("*"), token)),
                delimited(tag("?"), token, tag("?")),
                terminated(encoded_text, tag("?=")))),
-        |(charset, _lang, encoding, text)| {
-            (ascii_to_string(charset), decode_text(encoding, text).unwrap_or_else(|| text.to_vec()))
+        |(charset, language, encoding, text)| {
+            let decoded = decode_text(encoding, text);
+            let encoding = match &encoding.to_ascii_lowercase()[..] {
+                b"b" => WordEncoding::B,
+                b"q" => WordEncoding::Q,
+                _ => WordEncoding::Unknown(ascii_to_string(encoding).into()),
+            };
+
+            RawWord {
+                charset: ascii_to_string(charset),
+                language: language.map(ascii_to_string),
+                encoding,
+                failed: decoded.is_none(),
+                bytes: decoded.unwrap_or_else(|| text.to_vec()),
+            }
         })(input)
 }
 
-fn decode_charset((charset, bytes): (Cow<str>, Vec<u8>)) -> String
+// Returns the decoded text and whether the charset is known.
+pub(crate) fn decode_charset(charset: &str, bytes: &[u8]) -> (String, bool)
 {
-    encoding_from_whatwg_label(&charset).unwrap_or(ASCII).decode(&bytes, DecoderTrap::Replace).unwrap()
+    match encoding_from_whatwg_label(charset) {
+        Some(codec) => (codec.decode(bytes, DecoderTrap::Replace).unwrap(), true),
+        None => (ASCII.decode(bytes, DecoderTrap::Replace).unwrap(), false),
+    }
+}
+
+// Decode a run of adjacent encoded words. Words with the same
+// charset have their bytes joined before decoding since mailers may
+// split a multibyte character across two words.
+pub(crate) fn decode_words(words: &[RawWord]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < words.len() {
+        let mut bytes = words[i].bytes.clone();
+        let mut j = i + 1;
+        while j < words.len() && !words[i].failed && !words[j].failed
+            && words[j].charset.eq_ignore_ascii_case(&words[i].charset)
+        {
+            bytes.extend_from_slice(&words[j].bytes);
+            j += 1;
+        }
+
+        out.push_str(&decode_charset(&words[i].charset, &bytes).0);
+        i = j;
+    }
+
+    out
+}
+
+// Decode encoded words found anywhere in the text, as some mailers
+// write them where they are not allowed such as in MIME parameters.
+// Whitespace between adjacent encoded words is removed.
+pub(crate) fn decode_embedded(text: &str) -> String {
+    let is_ws = |c: u8| b" \t\r\n".contains(&c);
+    let input = text.as_bytes();
+    let mut out = String::new();
+    let mut pos = 0;
+    let mut i = 0;
+
+    while i < input.len() {
+        if !input[i..].starts_with(b"=?") {
+            i += 1;
+            continue;
+        }
+
+        let mut words = Vec::new();
+        let mut end = i;
+        let mut next = i;
+        while let Ok((rem, word)) = raw_word(&input[next..]) {
+            words.push(word);
+            end = input.len() - rem.len();
+            next = end;
+            while next < input.len() && is_ws(input[next]) {
+                next += 1;
+            }
+        }
+
+        if words.is_empty() {
+            i += 2;
+        } else {
+            out.push_str(&text[pos..i]);
+            out.push_str(&decode_words(&words));
+            pos = end;
+            i = end;
+        }
+    }
+
+    out.push_str(&text[pos..]);
+    out
+}
+
+/// Details about how an encoded word was decoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeReport {
+    /// The decoded text.
+    pub decoded: String,
+    /// The charset as written in the encoded word.
+    pub charset: String,
+    /// The [RFC 2231] language tag, if present.
+    ///
+    /// [RFC 2231]: https://tools.ietf.org/html/rfc2231#section-5
+    pub language: Option<String>,
+    /// The encoding as written in the encoded word.
+    pub encoding: WordEncoding,
+    /// Set when the base64 or Q decoding failed. The raw encoded text
+    /// is then decoded with the charset instead.
+    pub decoding_failed: bool,
+    /// Set when the charset is not known. ASCII is used instead.
+    pub unknown_charset: bool,
+    /// Set when the encoded word is not allowed where it was found.
+    ///
+    /// Only set by [`scan_encoded_words`].
+    pub forbidden_position: bool,
+}
+
+impl<'a> From<RawWord<'a>> for DecodeReport {
+    fn from(word: RawWord<'a>) -> DecodeReport {
+        let (decoded, known) = decode_charset(&word.charset, &word.bytes);
+
+        DecodeReport {
+            decoded,
+            charset: word.charset.into(),
+            language: word.language.map(Into::into),
+            encoding: word.encoding,
+            decoding_failed: word.failed,
+            unknown_charset: !known,
+            forbidden_position: false,
+        }
+    }
 }
 
 /// Decode an encoded word.
 ///
+/// Decoding is lenient: invalid base64 or Q text is decoded as is
+/// and unknown charsets are decoded as ASCII. Use
+/// [`encoded_word_report`] to detect these cases or
+/// [`encoded_word_strict`] to reject them.
+///
 /// # Examples
 /// ```
 /// use rustyknife::rfc2047::encoded_word;
@@ -75,5 +230,219 @@ fn decode_charset((charset, bytes): (Cow<str>, Vec<u8>)) -> String
 /// assert_eq!(decoded, "忍法写メ光飛ばし(笑)");
 /// ```
 pub fn encoded_word(input: &[u8]) -> NomResult<String> {
-    map(_encoded_word, decode_charset)(input)
+    map(raw_word, |w| decode_charset(&w.charset, &w.bytes).0)(input)
+}
+
+/// Decode an encoded word and report how it was decoded.
+///
+/// # Examples
+/// ```
+/// use rustyknife::rfc2047::{encoded_word_report, WordEncoding};
+///
+/// let (_, report) = encoded_word_report(b"=?utf-8*fr?Q?caf=C3=A9?=").unwrap();
+/// assert_eq!(report.decoded, "café");
+/// assert_eq!(report.language, Some("fr".into()));
+/// assert_eq!(report.encoding, WordEncoding::Q);
+/// assert!(!report.decoding_failed);
+/// ```
+pub fn encoded_word_report(input: &[u8]) -> NomResult<'_, DecodeReport> {
+    map(raw_word, DecodeReport::from)(input)
+}
+
+/// Decode an encoded word, failing if the charset is unknown or if
+/// the base64 or Q decoding fails.
+pub fn encoded_word_strict(input: &[u8]) -> NomResult<'_, String> {
+    map(verify(encoded_word_report, |r: &DecodeReport| !r.decoding_failed && !r.unknown_charset),
+        |r| r.decoded)(input)
+}
+
+/// Find and decode every encoded word in a header value.
+///
+/// Returns the offset of each encoded word along with its report.
+/// An encoded word is in a forbidden position when it is not
+/// delimited by whitespace or, in `structured` headers, when it is
+/// inside a quoted string, an address or a domain literal.
+/// # Examples
+/// ```
+/// use rustyknife::rfc2047::scan_encoded_words;
+///
+/// let found = scan_encoded_words(b"=?utf-8?Q?Andr=C3=A9?= <a@b>, \"=?utf-8?Q?Bob?=\" <b@c>", true);
+/// assert_eq!(found.len(), 2);
+/// assert_eq!(found[0].0, 0);
+/// assert!(!found[0].1.forbidden_position);
+/// assert!(found[1].1.forbidden_position);
+/// ```
+pub fn scan_encoded_words(input: &[u8], structured: bool) -> Vec<(usize, DecodeReport)> {
+    let is_ws = |c: u8| b" \t\r\n".contains(&c);
+    let delim_before = |c: u8| is_ws(c) || c == b'(' || (structured && b",:".contains(&c));
+    let delim_after = |c: u8| is_ws(c) || c == b')' || (structured && b",;<".contains(&c));
+
+    let mut out = Vec::new();
+    let mut quoted = false;
+    let mut comment_depth = 0usize;
+    let mut angle = false;
+    let mut literal = false;
+    let mut i = 0;
+
+    while i < input.len() {
+        let c = input[i];
+
+        if input[i..].starts_with(b"=?") {
+            if let Ok((rem, raw)) = raw_word(&input[i..]) {
+                let end = input.len() - rem.len();
+                let mut report = DecodeReport::from(raw);
+
+                let in_structure = structured && comment_depth == 0 && (quoted || angle || literal);
+                let before_ok = i == 0 || delim_before(input[i-1]);
+                let after_ok = end == input.len() || delim_after(input[end]);
+                report.forbidden_position = in_structure || !before_ok || !after_ok;
+
+                out.push((i, report));
+                i = end;
+                continue;
+            }
+        }
+
+        if structured {
+            match c {
+                b'\\' if quoted || comment_depth > 0 => i += 1,
+                b'"' if comment_depth == 0 => quoted = !quoted,
+                _ if quoted => (),
+                b'(' => comment_depth += 1,
+                b')' if comment_depth > 0 => comment_depth -= 1,
+                _ if comment_depth > 0 => (),
+                b'<' => angle = true,
+                b'>' => angle = false,
+                b'[' => literal = true,
+                b']' => literal = false,
+                _ => (),
+            }
+        }
+        i += 1;
+    }
+
+    out
+}
+
+/// Where an encoded word is placed in a header.
+///
+/// Determines which characters may appear unencoded in Q encoded
+/// text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Context {
+    /// A word in a display name or other phrase.
+    Phrase,
+    /// Text inside a comment.
+    Comment,
+    /// An unstructured header such as `"Subject:"`.
+    Text,
+}
+
+// Longest encoded word allowed by the RFC.
+const MAX_WORD_LEN: usize = 75;
+
+fn q_literal(c: u8, context: Context) -> bool {
+    match context {
+        Context::Phrase => c.is_ascii_alphanumeric() || b"!*+-/".contains(&c),
+        Context::Comment => q_literal(c, Context::Text) && !b"()\"\\".contains(&c),
+        Context::Text => (33..=126).contains(&c) && !b"=?_".contains(&c),
+    }
+}
+
+fn q_len(bytes: &[u8], context: Context) -> usize {
+    bytes.iter().map(|c| if *c == b' ' || q_literal(*c, context) { 1 } else { 3 }).sum()
+}
+
+fn b_len(len: usize) -> usize {
+    len.div_ceil(3) * 4
+}
+
+fn encode_q(bytes: &[u8], context: Context) -> String {
+    let mut out = String::with_capacity(q_len(bytes, context));
+
+    for c in bytes {
+        match *c {
+            b' ' => out.push('_'),
+            c if q_literal(c, context) => out.push(char::from(c)),
+            c => out.push_str(&format!("={:02X}", c)),
+        }
+    }
+
+    out
+}
+
+/// Encode text as a sequence of encoded words using the given charset.
+///
+/// Each word is at most 75 characters long and characters are never
+/// split across words. For each word, the Q or B encoding is picked
+/// depending on which one fits the most text.
+///
+/// Fails if the text cannot be represented in the charset or if the
+/// charset name is too long for a character to fit in a word. Only
+/// stateless charsets are supported.
+/// # Examples
+/// ```
+/// use encoding::all::ISO_8859_1;
+/// use rustyknife::rfc2047::{encode_charset, Context};
+///
+/// assert_eq!(encode_charset("Café", ISO_8859_1, Context::Phrase).unwrap(),
+///            ["=?iso-8859-1?Q?Caf=E9?="]);
+/// ```
+pub fn encode_charset(text: &str, charset: EncodingRef, context: Context) -> Result<Vec<String>, &'static str> {
+    let name = charset.whatwg_name().unwrap_or_else(|| charset.name());
+    let budget = MAX_WORD_LEN.checked_sub(name.len() + 7).ok_or("Charset name too long")?;
+    let mut buf = [0; 4];
+
+    let chars = text.chars()
+        .map(|c| charset.encode(c.encode_utf8(&mut buf), EncoderTrap::Strict).map_err(|_| "Character not representable in charset"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut out = Vec::new();
+    let mut rem = &chars[..];
+
+    while !rem.is_empty() {
+        let (mut q_count, mut q_total) = (0, 0);
+        while q_count < rem.len() && q_total + q_len(&rem[q_count], context) <= budget {
+            q_total += q_len(&rem[q_count], context);
+            q_count += 1;
+        }
+
+        let (mut b_count, mut b_total) = (0, 0);
+        while b_count < rem.len() && b_len(b_total + rem[b_count].len()) <= budget {
+            b_total += rem[b_count].len();
+            b_count += 1;
+        }
+
+        let use_q = q_count > b_count || (q_count == b_count && q_total <= b_len(b_total));
+        let count = if use_q { q_count } else { b_count };
+        if count == 0 {
+            return Err("Charset name too long");
+        }
+        let bytes = rem[..count].concat();
+
+        out.push(if use_q {
+            format!("=?{}?Q?{}?=", name, encode_q(&bytes, context))
+        } else {
+            format!("=?{}?B?{}?=", name, base64::encode(&bytes))
+        });
+        rem = &rem[count..];
+    }
+
+    Ok(out)
+}
+
+/// Encode text as a sequence of UTF-8 encoded words.
+///
+/// The words must be separated by whitespace when written. They
+/// are joined back together without the whitespace when decoded.
+/// See [`encode_charset`] for details.
+/// # Examples
+/// ```
+/// use rustyknife::rfc2047::{encode, Context};
+///
+/// assert_eq!(encode("Café au lait", Context::Text), ["=?utf-8?Q?Caf=C3=A9_au_lait?="]);
+/// assert_eq!(encode("忍法写メ光飛ばし", Context::Phrase), ["=?utf-8?B?5b+N5rOV5YaZ44Oh5YWJ6aOb44Gw44GX?="]);
+/// ```
+pub fn encode(text: &str, context: Context) -> Vec<String> {
+    encode_charset(text, UTF_8, context).unwrap()
 }
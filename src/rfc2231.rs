@@ -13,7 +13,7 @@ use std::collections::HashMap;
 
 use encoding::label::encoding_from_whatwg_label;
 use encoding::types::EncodingRef;
-use encoding::DecoderTrap;
+use encoding::{DecoderTrap, EncoderTrap};
 use encoding::all::ASCII;
 
 use nom::branch::alt;
@@ -23,6 +23,7 @@ use nom::combinator::{map, opt, recognize, verify};
 use nom::multi::many0;
 use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
 
+use crate::types::QuotedString;
 use crate::util::*;
 use crate::rfc3461::hexpair;
 use crate::rfc5234::crlf;
@@ -71,7 +72,7 @@ fn regular_parameter_name(input: &[u8]) -> NomResult<Name> {
     )(input)
 }
 
-fn token(input: &[u8]) -> NomResult<&str> {
+pub(crate) fn token(input: &[u8]) -> NomResult<'_, &str> {
     map(take_while1(|c| (33..=126).contains(&c) && !b"()<>@,;:\\\"/[]?=".contains(&c)),
         |t| std::str::from_utf8(t).unwrap())(input)
 }
@@ -140,7 +141,7 @@ fn extended_other_values(input: &[u8]) -> NomResult<Vec<u8>> {
     many0(alt((ext_octet, attribute_char)))(input)
 }
 
-fn value(input: &[u8]) -> NomResult<Cow<str>> {
+pub(crate) fn value(input: &[u8]) -> NomResult<'_, Cow<'_, str>> {
     alt((map(token, Cow::from),
          map(quoted_string::<crate::behaviour::Intl>, |qs| Cow::from(qs.0))))(input)
 }
@@ -187,9 +188,13 @@ fn decode_parameter_list(input: Vec<Parameter>) -> Vec<(String, String)> {
     let mut simple_encoded = HashMap::<String, String>::new();
     let mut composite = HashMap::<String, Vec<(u32, Segment)>>::new();
     let mut composite_encoding = HashMap::new();
+    let mut order = Vec::new();
 
     for Parameter{name, value} in input {
         let name_norm = name.name.to_lowercase();
+        if !order.contains(&name_norm) {
+            order.push(name_norm.clone());
+        }
 
         match name.section {
             None => {
@@ -234,18 +239,258 @@ fn decode_parameter_list(input: Vec<Parameter>) -> Vec<(String, String)> {
         simple.insert(name, value);
     }
 
-    simple.into_iter().collect()
+    // Keep the order in which the parameters first appeared.
+    order.into_iter().filter_map(|name| simple.remove(&name).map(|value| (name, value))).collect()
 }
 
-/// Parse a MIME `"Content-Type"` header.
-///
-/// Returns a tuple of the MIME type and parameters.
-pub fn content_type(input: &[u8]) -> NomResult<(String, Vec<(String, String)>)> {
+pub(crate) fn _content_type(input: &[u8]) -> NomResult<'_, (String, Vec<(String, String)>)> {
     map(pair(delimited(ofws, _mime_type, ofws),
              _parameter_list),
         |(mt, p)| (ascii_to_string(mt).to_lowercase(), decode_parameter_list(p)))(input)
 }
 
+/// Parse a MIME `"Content-Type"` header.
+///
+/// Returns a tuple of the MIME type and parameters.
+#[deprecated(since = "0.3.0", note = "use `media_type` instead")]
+pub fn content_type(input: &[u8]) -> NomResult<'_, (String, Vec<(String, String)>)> {
+    _content_type(input)
+}
+
+/// A parsed `"Content-Type"` value.
+///
+/// The type, subtype and parameter names are lowercase.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaType {
+    /// The top level type such as `"text"`.
+    pub mtype: String,
+    /// The subtype such as `"plain"` or `"svg+xml"`.
+    pub subtype: String,
+    /// The decoded parameters in their original order.
+    pub parameters: Vec<(String, String)>,
+}
+
+impl MediaType {
+    /// Create a media type without parameters from a string such as
+    /// `"text/plain"`.
+    ///
+    /// A string without a `"/"` is taken as the top level type with
+    /// an empty subtype.
+    pub fn new(essence: &str) -> Self {
+        let essence = essence.to_lowercase();
+        let (mtype, subtype) = match essence.find('/') {
+            Some(i) => (essence[..i].into(), essence[i+1..].into()),
+            None => (essence, String::new()),
+        };
+
+        MediaType { mtype, subtype, parameters: Vec::new() }
+    }
+
+    /// Return the type and subtype such as `"text/plain"`.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.mtype, self.subtype)
+    }
+
+    /// Return the structured syntax suffix such as `"xml"` for
+    /// `"image/svg+xml"`.
+    pub fn suffix(&self) -> Option<&str> {
+        self.subtype.rfind('+').map(|i| &self.subtype[i+1..]).filter(|s| !s.is_empty())
+    }
+
+    /// Return the value of the parameter `name`, compared without
+    /// regard to case.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// Set the parameter `name`, replacing any previous value.
+    pub fn set_parameter(&mut self, name: &str, value: &str) {
+        let name = name.to_lowercase();
+        match self.parameters.iter_mut().find(|(n, _)| *n == name) {
+            Some(p) => p.1 = value.into(),
+            None => self.parameters.push((name, value.into())),
+        }
+    }
+
+    /// Return the `"charset"` parameter.
+    pub fn charset(&self) -> Option<&str> {
+        self.parameter("charset")
+    }
+
+    /// Return the `"boundary"` parameter.
+    pub fn boundary(&self) -> Option<&str> {
+        self.parameter("boundary")
+    }
+
+    /// Return the `"name"` parameter.
+    pub fn name(&self) -> Option<&str> {
+        self.parameter("name")
+    }
+
+    /// Whether this is a `"multipart/*"` type.
+    pub fn is_multipart(&self) -> bool {
+        self.mtype == "multipart"
+    }
+
+    /// Whether this is a `"text/*"` type.
+    pub fn is_text(&self) -> bool {
+        self.mtype == "text"
+    }
+}
+
+/// Write the parameters as RFC 2045 tokens or quoted strings, or as
+/// RFC 2231 UTF-8 extended values if they are not ASCII.
+impl Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.mtype, self.subtype)?;
+        for (name, value) in &self.parameters {
+            write!(f, "; {}", parameter_string(name, value))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_plain(value: &str) -> bool {
+    value.bytes().all(|c| c == b'\t' || (32..=126).contains(&c))
+}
+
+fn percent_encode(bytes: &[u8], out: &mut String) {
+    for c in bytes {
+        if is_attribute_char(*c) {
+            out.push(char::from(*c));
+        } else {
+            out.push_str(&format!("%{:02X}", c));
+        }
+    }
+}
+
+// Serialize a single parameter on one line.
+pub(crate) fn parameter_string(name: &str, value: &str) -> String {
+    if !value.is_empty() && exact!(value.as_bytes(), token).is_ok() {
+        format!("{}={}", name, value)
+    } else if is_plain(value) {
+        format!("{}={}", name, QuotedString(value.into()).quoted())
+    } else {
+        let mut out = format!("{}*=utf-8''", name);
+        percent_encode(value.as_bytes(), &mut out);
+        out
+    }
+}
+
+/// Encode a MIME parameter, splitting it into RFC 2231 sections if
+/// needed.
+///
+/// Each returned section is at most `max_len` characters long and
+/// must be separated from the next one by `";"`. Printable ASCII
+/// values are written as is, as tokens or quoted strings. Other values
+/// are converted to `charset` and percent-encoded, with the optional
+/// `language` tag. Characters and escapes are never split across
+/// sections.
+///
+/// Fails if the name or language is invalid, if the value cannot be
+/// represented in `charset` or if `max_len` is too short.
+/// # Examples
+/// ```
+/// use encoding::all::UTF_8;
+/// use rustyknife::rfc2231::encode_parameter;
+///
+/// assert_eq!(encode_parameter("filename", "report.pdf", UTF_8, None, 78).unwrap(),
+///            ["filename=report.pdf"]);
+/// assert_eq!(encode_parameter("filename", "résumé.pdf", UTF_8, Some("fr"), 78).unwrap(),
+///            ["filename*=utf-8'fr'r%C3%A9sum%C3%A9.pdf"]);
+/// assert_eq!(encode_parameter("filename", "résumé.pdf", UTF_8, None, 30).unwrap(),
+///            ["filename*0*=utf-8''r%C3%A9sum", "filename*1*=%C3%A9.pdf"]);
+/// ```
+pub fn encode_parameter(name: &str, value: &str, charset: EncodingRef, language: Option<&str>, max_len: usize)
+                        -> Result<Vec<String>, &'static str>
+{
+    if name.is_empty() || !name.bytes().all(is_attribute_char) {
+        return Err("Invalid parameter name");
+    }
+    if !language.unwrap_or("").bytes().all(is_attribute_char) {
+        return Err("Invalid language");
+    }
+
+    let plain = is_plain(value);
+    let initial = format!("{}'{}'", charset.whatwg_name().unwrap_or_else(|| charset.name()), language.unwrap_or(""));
+    let single = if plain {
+        parameter_string(name, value)
+    } else {
+        let mut out = format!("{}*={}", name, initial);
+        let bytes = charset.encode(value, EncoderTrap::Strict).map_err(|_| "Value not representable in charset")?;
+        percent_encode(&bytes, &mut out);
+        out
+    };
+    if single.len() <= max_len {
+        return Ok(vec![single]);
+    }
+
+    // Every character as it is written in a section.
+    let mut buf = [0; 4];
+    let pieces = value.chars().map(|c| {
+        let mut out = String::new();
+        if !plain {
+            let bytes = charset.encode(c.encode_utf8(&mut buf), EncoderTrap::Strict).map_err(|_| "Value not representable in charset")?;
+            percent_encode(&bytes, &mut out);
+        } else if c == '"' || c == '\\' {
+            out.push('\\');
+            out.push(c);
+        } else {
+            out.push(c);
+        }
+        Ok(out)
+    }).collect::<Result<Vec<_>, &str>>()?;
+
+    let mut out = Vec::new();
+    let mut rem = &pieces[..];
+
+    while !rem.is_empty() {
+        let section = out.len();
+        let mut line = if plain {
+            format!("{}*{}=\"", name, section)
+        } else if section == 0 {
+            format!("{}*0*={}", name, initial)
+        } else {
+            format!("{}*{}*=", name, section)
+        };
+        let closing = if plain { 1 } else { 0 };
+
+        let count = rem.iter().scan(line.len() + closing, |len, piece| {
+            *len += piece.len();
+            Some(*len)
+        }).take_while(|len| *len <= max_len).count();
+        if count == 0 {
+            return Err("Maximum length too short");
+        }
+
+        line.extend(rem[..count].iter().map(String::as_str));
+        if plain {
+            line.push('"');
+        }
+        out.push(line);
+        rem = &rem[count..];
+    }
+
+    Ok(out)
+}
+
+/// Parse a MIME `"Content-Type"` header into a [`MediaType`].
+///
+/// Replaces [`content_type`] which returns a tuple.
+/// # Examples
+/// ```
+/// use rustyknife::rfc2231::media_type;
+///
+/// let (_, media) = media_type(b" Image/SVG+XML; Name*=utf-8''%C3%A9t%C3%A9.svg").unwrap();
+/// assert_eq!((&media.mtype[..], &media.subtype[..]), ("image", "svg+xml"));
+/// assert_eq!(media.suffix(), Some("xml"));
+/// assert_eq!(media.name(), Some("été.svg"));
+/// assert_eq!(media.to_string(), "image/svg+xml; name*=utf-8''%C3%A9t%C3%A9.svg");
+/// ```
+pub fn media_type(input: &[u8]) -> NomResult<'_, MediaType> {
+    map(_content_type, |(essence, parameters)| MediaType { parameters, ..MediaType::new(&essence) })(input)
+}
+
 fn _x_token(input: &[u8]) -> NomResult<&str> {
     preceded(tag_no_case("x-"), token)(input)
 }
@@ -294,7 +539,7 @@ pub fn content_disposition(input: &[u8]) -> NomResult<(ContentDisposition, Vec<(
 }
 
 /// Value from a MIME `"Content-Transfer-Encoding"` header.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ContentTransferEncoding {
     /// "7bit"
     SevenBit,
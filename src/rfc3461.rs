@@ -33,6 +33,20 @@ pub(crate) fn xtext(input: &[u8]) -> NomResult<Vec<u8>> {
     many0(alt((xchar, hexchar)))(input)
 }
 
+// Encode text as xtext, the reverse of `xtext`.
+pub(crate) fn encode_xtext(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.bytes() {
+        match c {
+            33..=42 | 44..=60 | 62..=126 => out.push(char::from(c)),
+            c => out.push_str(&format!("+{:02X}", c)),
+        }
+    }
+
+    out
+}
+
 fn _printable_xtext(input: &[u8]) -> NomResult<Vec<u8>> {
     verify(xtext, |xtext: &[u8]| {
         xtext.iter().all(|c| match c { 9..=13 | 32..=126 => true, _ => false})
@@ -136,6 +150,7 @@ pub fn dsn_mail_params<'a>(input: &[Param<'a>]) -> Result<(DSNMailParams, Vec<Pa
     Ok((DSNMailParams{envid: envid_val, ret: ret_val}, out))
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Notify {
     pub on_success: bool,
     pub on_failure: bool,
@@ -0,0 +1,581 @@
+//! [Delivery status notification] parser and generator
+//!
+//! Parses the `"message/delivery-status"` part of a
+//! `"multipart/report"` bounce, and its [RFC 6533]
+//! `"message/global-delivery-status"` counterpart which may contain
+//! UTF-8.
+//!
+//! Parsing is lenient: folded lines are joined, comments after the
+//! values are dropped, unknown fields are kept as extensions and
+//! fields that cannot be parsed are ignored.
+//!
+//! Reports are generated with [`generate_dsn`].
+//!
+//! [Delivery status notification]: https://tools.ietf.org/html/rfc3464
+//! [RFC 6533]: https://tools.ietf.org/html/rfc6533
+
+use std::fmt::{self, Display};
+
+use crate::mime::{parse_with_depth, Entity};
+use crate::rfc3461::{encode_xtext, orcpt_address, xtext, DSNMailParams, DSNRet, Notify};
+use crate::rfc5321::ReversePath;
+use crate::types::Mailbox;
+
+/// The action taken by the reporting MTA for a recipient.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// "failed"
+    Failed,
+    /// "delayed"
+    Delayed,
+    /// "delivered"
+    Delivered,
+    /// "relayed"
+    Relayed,
+    /// "expanded"
+    Expanded,
+}
+
+impl Action {
+    fn parse(value: &str) -> Option<Self> {
+        match &value.to_ascii_lowercase()[..] {
+            "failed" => Some(Action::Failed),
+            "delayed" => Some(Action::Delayed),
+            "delivered" => Some(Action::Delivered),
+            "relayed" => Some(Action::Relayed),
+            "expanded" => Some(Action::Expanded),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Action::Failed => "failed",
+            Action::Delayed => "delayed",
+            Action::Delivered => "delivered",
+            Action::Relayed => "relayed",
+            Action::Expanded => "expanded",
+        })
+    }
+}
+
+/// An [RFC 3463] enhanced status code such as `"5.1.1"`.
+///
+/// [RFC 3463]: https://tools.ietf.org/html/rfc3463
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status {
+    /// The class, 2 for success, 4 for a temporary and 5 for a
+    /// permanent failure.
+    pub class: u8,
+    /// The subject.
+    pub subject: u16,
+    /// The detail.
+    pub detail: u16,
+}
+
+impl Status {
+    /// Parse a status code, ignoring any trailing comment.
+    pub fn parse(value: &str) -> Option<Self> {
+        let code = value.split(|c: char| c.is_whitespace() || c == '(').next()?;
+        let mut parts = code.split('.');
+        let class = parts.next()?.parse().ok().filter(|c| [2, 4, 5].contains(c))?;
+        let subject = parts.next().filter(|s| s.len() <= 3)?.parse().ok()?;
+        let detail = parts.next().filter(|s| s.len() <= 3)?.parse().ok()?;
+
+        match parts.next() {
+            None => Some(Status { class, subject, detail }),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.class, self.subject, self.detail)
+    }
+}
+
+/// The fields describing the whole report.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PerMessage {
+    /// The `"ENVID"` given by the sender, xtext decoded.
+    pub original_envelope_id: Option<String>,
+    /// The MTA that generated the report, as an MTA name type such as
+    /// `"dns"` and a name.
+    pub reporting_mta: Option<(String, String)>,
+    /// The gateway that translated a foreign notification.
+    pub dsn_gateway: Option<(String, String)>,
+    /// The MTA the message was received from.
+    pub received_from_mta: Option<(String, String)>,
+    /// The date the message arrived at the reporting MTA.
+    pub arrival_date: Option<String>,
+    /// Other fields with their names in lowercase.
+    pub extensions: Vec<(String, String)>,
+}
+
+/// The fields describing the delivery to one recipient.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PerRecipient {
+    /// The `"ORCPT"` given by the sender as an address type and an
+    /// xtext decoded address.
+    pub original_recipient: Option<(String, String)>,
+    /// The recipient as an address type such as `"rfc822"` and an
+    /// address.
+    pub final_recipient: (String, String),
+    /// The action taken.
+    pub action: Action,
+    /// The delivery status.
+    pub status: Status,
+    /// The MTA that reported the status.
+    pub remote_mta: Option<(String, String)>,
+    /// The diagnostic from the remote MTA, as a diagnostic type such
+    /// as `"smtp"` and its text.
+    pub diagnostic_code: Option<(String, String)>,
+    /// The date of the last delivery attempt.
+    pub last_attempt_date: Option<String>,
+    /// The log identifier of the final delivery attempt.
+    pub final_log_id: Option<String>,
+    /// The date after which delivery attempts will stop.
+    pub will_retry_until: Option<String>,
+    /// Other fields with their names in lowercase.
+    pub extensions: Vec<(String, String)>,
+}
+
+/// A parsed `"message/delivery-status"` body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliveryStatus {
+    /// The per-message fields.
+    pub message: PerMessage,
+    /// The per-recipient fields, one entry per recipient.
+    pub recipients: Vec<PerRecipient>,
+}
+
+// Split the body into groups of unfolded fields separated by blank
+// lines.
+fn field_groups(input: &str) -> Vec<Vec<(String, String)>> {
+    let mut groups = Vec::new();
+    let mut group: Vec<(String, String)> = Vec::new();
+
+    for line in input.lines() {
+        if line.trim().is_empty() {
+            if !group.is_empty() {
+                groups.push(std::mem::take(&mut group));
+            }
+        } else if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = group.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some(i) = line.find(':') {
+            group.push((line[..i].trim().to_ascii_lowercase(), line[i+1..].trim().into()));
+        }
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+
+    groups
+}
+
+// Remove a trailing comment such as in "dns; mx.example.com (Postfix)".
+fn strip_comment(value: &str) -> &str {
+    match value.rfind('(') {
+        Some(i) if value.ends_with(')') => value[..i].trim_end(),
+        _ => value,
+    }
+}
+
+// Split a "type; value" field, lowercasing the type.
+fn typed(value: &str) -> Option<(String, String)> {
+    let i = value.find(';')?;
+    let kind = value[..i].trim();
+    if kind.is_empty() {
+        return None;
+    }
+
+    Some((kind.to_ascii_lowercase(), value[i+1..].trim().into()))
+}
+
+fn decode_xtext(value: &str) -> String {
+    match exact!(value.as_bytes(), xtext) {
+        Ok((_, decoded)) => String::from_utf8_lossy(&decoded).into(),
+        Err(_) => value.into(),
+    }
+}
+
+fn per_message(fields: Vec<(String, String)>) -> PerMessage {
+    let mut out = PerMessage::default();
+
+    for (name, value) in fields {
+        match &name[..] {
+            "original-envelope-id" => out.original_envelope_id = Some(decode_xtext(&value)),
+            "reporting-mta" => out.reporting_mta = typed(strip_comment(&value)),
+            "dsn-gateway" => out.dsn_gateway = typed(strip_comment(&value)),
+            "received-from-mta" => out.received_from_mta = typed(strip_comment(&value)),
+            "arrival-date" => out.arrival_date = Some(value),
+            _ => out.extensions.push((name, value)),
+        }
+    }
+
+    out
+}
+
+fn per_recipient(fields: Vec<(String, String)>) -> Result<PerRecipient, &'static str> {
+    let (mut original_recipient, mut final_recipient, mut action, mut status) = (None, None, None, None);
+    let (mut remote_mta, mut diagnostic_code) = (None, None);
+    let (mut last_attempt_date, mut final_log_id, mut will_retry_until) = (None, None, None);
+    let mut extensions = Vec::new();
+
+    for (name, value) in fields {
+        match &name[..] {
+            "original-recipient" => {
+                let value = strip_comment(&value);
+                original_recipient = match exact!(value.as_bytes(), orcpt_address) {
+                    Ok((_, (kind, address))) => Some((kind.to_ascii_lowercase(), address.trim().into())),
+                    Err(_) => typed(value),
+                };
+            }
+            "final-recipient" => final_recipient = typed(strip_comment(&value)),
+            "action" => action = Action::parse(strip_comment(&value)),
+            "status" => status = Status::parse(&value),
+            "remote-mta" => remote_mta = typed(strip_comment(&value)),
+            "diagnostic-code" => diagnostic_code = typed(&value),
+            "last-attempt-date" => last_attempt_date = Some(value),
+            "final-log-id" => final_log_id = Some(value),
+            "will-retry-until" => will_retry_until = Some(value),
+            _ => extensions.push((name, value)),
+        }
+    }
+
+    Ok(PerRecipient {
+        original_recipient,
+        final_recipient: final_recipient.ok_or("Missing Final-Recipient")?,
+        action: action.ok_or("Invalid Action")?,
+        status: status.ok_or("Invalid Status")?,
+        remote_mta,
+        diagnostic_code,
+        last_attempt_date,
+        final_log_id,
+        will_retry_until,
+        extensions,
+    })
+}
+
+/// Parse a `"message/delivery-status"` or
+/// `"message/global-delivery-status"` body.
+///
+/// Fails if there are no recipients or if a recipient lacks a valid
+/// `"Final-Recipient"`, `"Action"` or `"Status"` field.
+/// # Examples
+/// ```
+/// use rustyknife::rfc3464::{delivery_status, Action};
+///
+/// let status = delivery_status(b"Reporting-MTA: dns; mx.example.com\r\n\
+///                                Original-Envelope-Id: QQ314159+2B1\r\n\r\n\
+///                                Final-Recipient: rfc822; bob@example.org\r\n\
+///                                Action: failed\r\n\
+///                                Status: 5.1.1 (bad mailbox)\r\n\
+///                                Diagnostic-Code: smtp; 550 5.1.1 User unknown\r\n").unwrap();
+///
+/// assert_eq!(status.message.reporting_mta, Some(("dns".into(), "mx.example.com".into())));
+/// assert_eq!(status.message.original_envelope_id.as_deref(), Some("QQ314159+1"));
+///
+/// let rcpt = &status.recipients[0];
+/// assert_eq!(rcpt.final_recipient, ("rfc822".into(), "bob@example.org".into()));
+/// assert_eq!(rcpt.action, Action::Failed);
+/// assert_eq!(rcpt.status.to_string(), "5.1.1");
+/// assert_eq!(rcpt.diagnostic_code, Some(("smtp".into(), "550 5.1.1 User unknown".into())));
+/// ```
+pub fn delivery_status(input: &[u8]) -> Result<DeliveryStatus, &'static str> {
+    let text = String::from_utf8_lossy(input);
+    let mut groups = field_groups(&text).into_iter();
+    let message = per_message(groups.next().ok_or("Empty delivery status")?);
+    let recipients = groups.map(per_recipient).collect::<Result<Vec<_>, _>>()?;
+
+    if recipients.is_empty() {
+        return Err("No recipients in delivery status");
+    }
+
+    Ok(DeliveryStatus { message, recipients })
+}
+
+/// The parts of a `"multipart/report"` delivery report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliveryReport<'e, 'a> {
+    /// The human readable explanation.
+    pub description: Option<&'e Entity<'a>>,
+    /// The parsed delivery status.
+    pub status: DeliveryStatus,
+    /// The returned message or its headers.
+    pub original: Option<&'e Entity<'a>>,
+}
+
+fn find_report<'e, 'a>(entity: &'e Entity<'a>) -> Option<&'e Entity<'a>> {
+    let report_type = entity.parameter("report-type").unwrap_or("");
+    if entity.content_type.essence() == "multipart/report"
+        && (report_type.eq_ignore_ascii_case("delivery-status") || report_type.eq_ignore_ascii_case("global-delivery-status"))
+    {
+        return Some(entity);
+    }
+
+    entity.multipart.iter().flat_map(|m| m.parts.iter()).find_map(find_report)
+}
+
+/// Find the delivery report in a parsed message and parse its status
+/// part.
+///
+/// The first `"multipart/report"` entity with a `"report-type"` of
+/// `"delivery-status"` or `"global-delivery-status"` is used.
+/// `message` is the message given to [`crate::mime::parse`].
+pub fn delivery_report<'e, 'a>(root: &'e Entity<'a>, message: &[u8]) -> Result<DeliveryReport<'e, 'a>, &'static str> {
+    let report = find_report(root).ok_or("No delivery report")?;
+    let parts = &report.multipart.as_ref().ok_or("No delivery report")?.parts;
+    let is_type = |entity: &&Entity, types: &[&str]| types.contains(&&entity.content_type.essence()[..]);
+
+    let status = parts.iter()
+        .find(|p| is_type(p, &["message/delivery-status", "message/global-delivery-status"]))
+        .ok_or("Missing delivery status")?;
+    let original = parts.iter()
+        .find(|p| is_type(p, &["message/rfc822", "message/global", "text/rfc822-headers", "message/global-headers"]));
+
+    Ok(DeliveryReport {
+        description: parts.first().filter(|p| !std::ptr::eq(*p, status)),
+        status: delivery_status(&status.decode_body(message).0)?,
+        original,
+    })
+}
+
+/// The outcome of the delivery to one recipient, see [`DSNMessage`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecipientOutcome {
+    /// The recipient address.
+    pub recipient: Mailbox,
+    /// The `"ORCPT"` parameter as returned by
+    /// [`crate::rfc3461::orcpt_address`].
+    pub original_recipient: Option<(String, String)>,
+    /// The `"NOTIFY"` parameter, `None` if it was not given.
+    pub notify: Option<Notify>,
+    /// The action taken.
+    pub action: Action,
+    /// The delivery status.
+    pub status: Status,
+    /// The host name of the remote MTA.
+    pub remote_mta: Option<String>,
+    /// The SMTP reply of the remote MTA.
+    pub diagnostic_code: Option<String>,
+    /// The date of the last delivery attempt.
+    pub last_attempt_date: Option<String>,
+    /// The date after which delivery attempts will stop.
+    pub will_retry_until: Option<String>,
+}
+
+/// A delivery status notification to generate with [`generate_dsn`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DSNMessage<'a> {
+    /// The host name of the MTA generating the report.
+    pub reporting_mta: &'a str,
+    /// The `"From:"` address of the report.
+    pub from: &'a Mailbox,
+    /// The `"MAIL FROM"` path of the original message.
+    pub sender: &'a ReversePath,
+    /// The `"Date:"` header value.
+    pub date: &'a str,
+    /// The date the original message was received.
+    pub arrival_date: Option<&'a str>,
+    /// The DSN parameters of the original `"MAIL FROM"`.
+    pub params: &'a DSNMailParams,
+    /// Whether the original message was sent with `"SMTPUTF8"`, in
+    /// which case the [RFC 6533] report types are used.
+    ///
+    /// [RFC 6533]: https://tools.ietf.org/html/rfc6533
+    pub smtputf8: bool,
+    /// The boundary of the report, which must not appear in the
+    /// original message.
+    pub boundary: &'a str,
+    /// The original message.
+    pub original: &'a [u8],
+    /// The recipients of the original message.
+    pub recipients: &'a [RecipientOutcome],
+}
+
+// Whether the sender asked to be notified of this outcome. Without a
+// NOTIFY parameter, only failures are reported.
+fn wants_notification(outcome: &RecipientOutcome) -> bool {
+    match (&outcome.notify, &outcome.action) {
+        (None, action) => *action == Action::Failed,
+        (Some(n), Action::Failed) => n.on_failure,
+        (Some(n), Action::Delayed) => n.delay,
+        (Some(n), _) => n.on_success,
+    }
+}
+
+fn recipient_fields(out: &mut String, outcome: &RecipientOutcome) {
+    let address = outcome.recipient.to_string();
+    let address_type = if address.is_ascii() { "rfc822" } else { "utf-8" };
+
+    if let Some((kind, address)) = &outcome.original_recipient {
+        out.push_str(&format!("Original-Recipient: {};{}\r\n", kind, encode_xtext(address)));
+    }
+    out.push_str(&format!("Final-Recipient: {}; {}\r\n", address_type, address));
+    out.push_str(&format!("Action: {}\r\nStatus: {}\r\n", outcome.action, outcome.status));
+    if let Some(mta) = &outcome.remote_mta {
+        out.push_str(&format!("Remote-MTA: dns; {}\r\n", mta));
+    }
+    if let Some(code) = &outcome.diagnostic_code {
+        out.push_str(&format!("Diagnostic-Code: smtp; {}\r\n", code));
+    }
+    if let Some(date) = &outcome.last_attempt_date {
+        out.push_str(&format!("Last-Attempt-Date: {}\r\n", date));
+    }
+    if let Some(date) = &outcome.will_retry_until {
+        out.push_str(&format!("Will-Retry-Until: {}\r\n", date));
+    }
+}
+
+/// Generate a `"multipart/report"` delivery status notification.
+///
+/// Only the recipients whose `"NOTIFY"` parameter asks for their
+/// outcome are reported. Without `"NOTIFY"`, only failures are.
+/// Returns `None` if no recipient is reported or if the original
+/// sender is the null path.
+///
+/// The full original message is returned if `"RET=FULL"` was given
+/// and a failure is reported, otherwise only its headers are.
+///
+/// Fails if the boundary appears in the original message or if a
+/// recipient address is not ASCII without `smtputf8`.
+/// # Examples
+/// ```
+/// use rustyknife::rfc3461::{DSNMailParams, DSNRet};
+/// use rustyknife::rfc3464::{generate_dsn, Action, DSNMessage, RecipientOutcome, Status};
+/// use rustyknife::rfc5321::ReversePath;
+/// use rustyknife::types::Mailbox;
+///
+/// let outcome = RecipientOutcome {
+///     recipient: Mailbox::from_smtp(b"bob@example.org").unwrap(),
+///     original_recipient: Some(("rfc822".into(), "bob+list@example.org".into())),
+///     notify: None,
+///     action: Action::Failed,
+///     status: Status::parse("5.1.1").unwrap(),
+///     remote_mta: Some("mx.example.org".into()),
+///     diagnostic_code: Some("550 5.1.1 User unknown".into()),
+///     last_attempt_date: None,
+///     will_retry_until: None,
+/// };
+/// let dsn = DSNMessage {
+///     reporting_mta: "relay.example.com",
+///     from: &Mailbox::from_smtp(b"MAILER-DAEMON@relay.example.com").unwrap(),
+///     sender: &"<alice@example.com>".parse::<ReversePath>().unwrap(),
+///     date: "Mon, 1 Jun 2020 10:00:00 +0000",
+///     arrival_date: None,
+///     params: &DSNMailParams { envid: Some("abc".into()), ret: Some(DSNRet::Hdrs) },
+///     smtputf8: false,
+///     boundary: "report-boundary",
+///     original: b"Subject: hi\r\n\r\nbody\r\n",
+///     recipients: &[outcome],
+/// };
+///
+/// let report = String::from_utf8(generate_dsn(&dsn).unwrap().unwrap()).unwrap();
+/// assert!(report.contains("Content-Type: multipart/report; report-type=delivery-status;"));
+/// assert!(report.contains("Original-Envelope-Id: abc\r\n"));
+/// assert!(report.contains("Original-Recipient: rfc822;bob+2Blist@example.org\r\n\
+///                           Final-Recipient: rfc822; bob@example.org\r\nAction: failed\r\nStatus: 5.1.1\r\n"));
+/// assert!(report.contains("Content-Type: text/rfc822-headers\r\n\r\nSubject: hi\r\n"));
+/// ```
+pub fn generate_dsn(dsn: &DSNMessage) -> Result<Option<Vec<u8>>, &'static str> {
+    let sender = match dsn.sender {
+        ReversePath::Path(path) => &path.0,
+        ReversePath::Null => return Ok(None),
+    };
+    let reported: Vec<_> = dsn.recipients.iter().filter(|r| wants_notification(r)).collect();
+    if reported.is_empty() {
+        return Ok(None);
+    }
+
+    let boundary = format!("--{}", dsn.boundary);
+    if dsn.original.windows(boundary.len()).any(|w| w == boundary.as_bytes()) {
+        return Err("Boundary found in original message");
+    }
+    if !dsn.smtputf8 && reported.iter().any(|r| !r.recipient.to_string().is_ascii()) {
+        return Err("Non-ASCII recipient without SMTPUTF8");
+    }
+
+    let failed = reported.iter().any(|r| r.action == Action::Failed);
+    let kind = if failed {
+        "Failure"
+    } else if reported.iter().any(|r| r.action == Action::Delayed) {
+        "Delay"
+    } else {
+        "Success"
+    };
+    let (report_type, charset) = if dsn.smtputf8 { ("global-delivery-status", "utf-8") } else { ("delivery-status", "us-ascii") };
+
+    let mut out = format!("From: <{}>\r\nTo: <{}>\r\nSubject: Delivery Status Notification ({})\r\n\
+                           Date: {}\r\nMIME-Version: 1.0\r\nAuto-Submitted: auto-replied\r\n\
+                           Content-Type: multipart/report; report-type={};\r\n boundary=\"{}\"\r\n\r\n",
+                          dsn.from, sender, kind, dsn.date, report_type, dsn.boundary);
+
+    let mut text = format!("This is the mail system at host {}.\r\n\r\n", dsn.reporting_mta);
+    for r in &reported {
+        text.push_str(&format!("<{}>: {} ({})", r.recipient, r.action, r.status));
+        if let Some(code) = &r.diagnostic_code {
+            text.push_str(&format!(": {}", code));
+        }
+        text.push_str("\r\n");
+    }
+    let charset = if text.is_ascii() { charset } else { "utf-8" };
+    out.push_str(&format!("{}\r\nContent-Description: Notification\r\nContent-Type: text/plain; charset={}\r\n",
+                          boundary, charset));
+    if !text.is_ascii() {
+        out.push_str("Content-Transfer-Encoding: 8bit\r\n");
+    }
+    out.push_str("\r\n");
+    out.push_str(&text);
+
+    out.push_str(&format!("\r\n{}\r\nContent-Description: Delivery report\r\nContent-Type: message/{}\r\n", boundary, report_type));
+    if dsn.smtputf8 {
+        out.push_str("Content-Transfer-Encoding: 8bit\r\n");
+    }
+    out.push_str(&format!("\r\nReporting-MTA: dns; {}\r\n", dsn.reporting_mta));
+    if let Some(envid) = &dsn.params.envid {
+        out.push_str(&format!("Original-Envelope-Id: {}\r\n", encode_xtext(envid)));
+    }
+    if let Some(date) = dsn.arrival_date {
+        out.push_str(&format!("Arrival-Date: {}\r\n", date));
+    }
+    for r in &reported {
+        out.push_str("\r\n");
+        recipient_fields(&mut out, r);
+    }
+
+    // RET=FULL only applies to failure reports.
+    let full = failed && dsn.params.ret == Some(DSNRet::Full);
+    let original = if full {
+        dsn.original
+    } else {
+        &dsn.original[..parse_with_depth(dsn.original, 0).body.start]
+    };
+    let original_type = match (full, dsn.smtputf8) {
+        (true, false) => "message/rfc822",
+        (true, true) => "message/global",
+        (false, false) => "text/rfc822-headers",
+        (false, true) => "message/global-headers",
+    };
+    let description = if full { "Undelivered Message" } else { "Undelivered Message Headers" };
+    out.push_str(&format!("\r\n{}\r\nContent-Description: {}\r\nContent-Type: {}\r\n", boundary, description, original_type));
+    if !original.is_ascii() {
+        out.push_str("Content-Transfer-Encoding: 8bit\r\n");
+    }
+    out.push_str("\r\n");
+
+    let mut out = out.into_bytes();
+    out.extend_from_slice(original);
+    if !original.ends_with(b"\r\n") {
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(format!("{}--\r\n", boundary).as_bytes());
+
+    Ok(Some(out))
+}
@@ -4,7 +4,7 @@
 
 use std::convert::TryFrom;
 use std::fmt::{self, Display};
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::{self, FromStr};
 
 #[cfg(feature = "serde")]
@@ -337,14 +337,28 @@ fn _ip_int(input: &[u8]) -> NomResult<u8> {
             |ip| str::from_utf8(ip).unwrap().parse())(input)
 }
 
-fn _ipv4_literal(input: &[u8]) -> NomResult<AddressLiteral> {
+fn _ipv4_address(input: &[u8]) -> NomResult<'_, Ipv4Addr> {
     map(pair(_ip_int, many_m_n(3, 3, preceded(tag("."), _ip_int))),
-        |(a, b)| (AddressLiteral::IP(Ipv4Addr::new(a, b[0], b[1], b[2]).into())))(input)
+        |(a, b)| Ipv4Addr::new(a, b[0], b[1], b[2]))(input)
+}
+
+fn _ipv6_address(input: &[u8]) -> NomResult<'_, Ipv6Addr> {
+    map_res(take_while1(|c| is_hex_digit(c) || c == b':' || c == b'.'),
+            |addr| Ipv6Addr::from_str(str::from_utf8(addr).unwrap()))(input)
+}
+
+fn _ipv4_literal(input: &[u8]) -> NomResult<'_, AddressLiteral> {
+    map(_ipv4_address, |ip| AddressLiteral::IP(ip.into()))(input)
 }
 
 fn _ipv6_literal(input: &[u8]) -> NomResult<AddressLiteral> {
-    map_res(preceded(tag_no_case("IPv6:"), take_while1(|c| is_hex_digit(c) || c == b':' || c == b'.')),
-            |addr| Ipv6Addr::from_str(str::from_utf8(addr).unwrap()).map(|ip| AddressLiteral::IP(ip.into())))(input)
+    map(preceded(tag_no_case("IPv6:"), _ipv6_address), |ip| AddressLiteral::IP(ip.into()))(input)
+}
+
+// A bare IP address without the brackets and "IPv6:" prefix of an
+// address literal.
+pub(crate) fn ip_address(input: &[u8]) -> NomResult<'_, IpAddr> {
+    alt((map(_ipv6_address, IpAddr::V6), map(_ipv4_address, IpAddr::V4)))(input)
 }
 
 fn dcontent(input: &[u8]) -> NomResult<u8> {
@@ -1,22 +1,26 @@
 //! Parsers for [Internet Message Format] messages.
 //!
-//! Comments are ignored. [RFC 2047] decoding is applied where appropriate.
+//! Comments are ignored unless parsing with the
+//! [`Commented`](crate::behaviour::Commented) behaviour. [RFC 2047]
+//! decoding is applied where appropriate.
 //!
 //! [Internet Message Format]: https://tools.ietf.org/html/rfc5322
 //! [RFC 2047]: https://tools.ietf.org/html/rfc2047
 
 use std::borrow::Cow;
+use std::fmt;
 use std::str;
 use std::mem;
 
 use nom::branch::alt;
-use nom::bytes::complete::{tag, take};
-use nom::combinator::{map, map_opt, opt, recognize};
+use nom::bytes::complete::{tag, take, take_till};
+use nom::combinator::{map, map_opt, opt, recognize, verify};
 use nom::multi::{fold_many0, many0, many1};
-use nom::sequence::{delimited, pair, preceded, separated_pair, terminated};
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
 
 use crate::behaviour::*;
-use crate::rfc2047::encoded_word;
+use crate::headerwriter;
+use crate::rfc2047::{decode_words, raw_word};
 use crate::rfc5234::*;
 use crate::types::{self, *};
 use crate::util::*;
@@ -28,6 +32,11 @@ pub trait UTF8Policy {
     fn atext(input: &[u8]) -> NomResult<char>;
     fn qtext(input: &[u8]) -> NomResult<char>;
     fn dtext(input: &[u8]) -> NomResult<char>;
+
+    /// Keep the comments attached to addresses.
+    const KEEP_COMMENTS: bool = false;
+    /// Accept the obsolete syntax.
+    const ALLOW_OBSOLETE: bool = false;
 }
 
 impl UTF8Policy for Legacy {
@@ -78,6 +87,56 @@ impl UTF8Policy for Intl {
     }
 }
 
+impl<P: UTF8Policy> UTF8Policy for Commented<P> {
+    fn vchar(input: &[u8]) -> NomResult<'_, char> {
+        P::vchar(input)
+    }
+
+    fn ctext(input: &[u8]) -> NomResult<'_, char> {
+        P::ctext(input)
+    }
+
+    fn atext(input: &[u8]) -> NomResult<'_, char> {
+        P::atext(input)
+    }
+
+    fn qtext(input: &[u8]) -> NomResult<'_, char> {
+        P::qtext(input)
+    }
+
+    fn dtext(input: &[u8]) -> NomResult<'_, char> {
+        P::dtext(input)
+    }
+
+    const KEEP_COMMENTS: bool = true;
+    const ALLOW_OBSOLETE: bool = P::ALLOW_OBSOLETE;
+}
+
+impl<P: UTF8Policy> UTF8Policy for Obsolete<P> {
+    fn vchar(input: &[u8]) -> NomResult<'_, char> {
+        P::vchar(input)
+    }
+
+    fn ctext(input: &[u8]) -> NomResult<'_, char> {
+        P::ctext(input)
+    }
+
+    fn atext(input: &[u8]) -> NomResult<'_, char> {
+        P::atext(input)
+    }
+
+    fn qtext(input: &[u8]) -> NomResult<'_, char> {
+        P::qtext(input)
+    }
+
+    fn dtext(input: &[u8]) -> NomResult<'_, char> {
+        P::dtext(input)
+    }
+
+    const KEEP_COMMENTS: bool = P::KEEP_COMMENTS;
+    const ALLOW_OBSOLETE: bool = true;
+}
+
 fn quoted_pair<P: UTF8Policy>(input: &[u8]) -> NomResult<char> {
     preceded(tag("\\"), alt((P::vchar, map(wsp, char::from))))(input)
 }
@@ -148,13 +207,80 @@ fn comment<P: UTF8Policy>(input: &[u8]) -> NomResult<Vec<CommentContent>> {
         |(a, b)| _concat_comment(a.into_iter().chain(std::iter::once(CommentContent::Text(b)))))(input)
 }
 
-fn cfws<P: UTF8Policy>(input: &[u8]) -> NomResult<&[u8]> {
+pub(crate) fn cfws<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, &[u8]> {
     alt((recognize(pair(many1(pair(ofws, comment::<P>)), ofws)), recognize(fws)))(input)
 }
 
+/// A comment found in a structured header.
+///
+/// The enclosing parentheses are removed, quoted pairs are unescaped
+/// and folding is undone. Nested comments are kept with their
+/// parentheses.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Comment(pub(crate) String);
+string_newtype!(Comment);
+
+fn _flatten_comment(content: &[CommentContent], out: &mut String) {
+    for cc in content {
+        match cc {
+            CommentContent::Text(text) => out.push_str(text),
+            CommentContent::QP(qp) => out.push(*qp),
+            CommentContent::Comment(nested) => {
+                out.push('(');
+                _flatten_comment(nested, out);
+                out.push(')');
+            }
+        }
+    }
+}
+
+impl<'a> From<Vec<CommentContent<'a>>> for Comment {
+    fn from(content: Vec<CommentContent<'a>>) -> Comment {
+        let mut out = String::new();
+        _flatten_comment(&content, &mut out);
+        Comment(out)
+    }
+}
+
+// A single comment, converted to its flattened form.
+pub(crate) fn flat_comment<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, Comment> {
+    map(comment::<P>, Comment::from)(input)
+}
+
+// Extract the comments from input that was already parsed. Quoted
+// strings and domain literals are skipped since they may contain
+// parentheses.
+fn collect_comments<P: UTF8Policy>(input: &[u8]) -> Vec<Comment> {
+    let (_, found) = many0(alt((
+        map(comment::<P>, |c| Some(c.into())),
+        map(_inner_quoted_string::<P>, |_| None),
+        map(delimited(tag("["), take_till(|c| c == b']'), tag("]")), |_| None),
+        map(take(1usize), |_| None),
+    )))(input).unwrap_or_default();
+
+    found.into_iter().flatten().collect()
+}
+
+// Run `f` and also return the comments found in the input it consumed
+// if the policy asks for them.
+pub(crate) fn commented<'a, P, O, F>(mut f: F) -> impl FnMut(&'a [u8]) -> NomResult<'a, (O, Vec<Comment>)>
+    where P: UTF8Policy,
+          F: FnMut(&'a [u8]) -> NomResult<'a, O>,
+{
+    move |input| {
+        let (rem, out) = f(input)?;
+        let comments = if P::KEEP_COMMENTS {
+            collect_comments::<P>(&input[..input.len()-rem.len()])
+        } else {
+            Vec::new()
+        };
+        Ok((rem, (out, comments)))
+    }
+}
+
 #[cfg(feature = "quoted-string-rfc2047")]
 fn qcontent<P: UTF8Policy>(input: &[u8]) -> NomResult<QContent> {
-    alt((map(encoded_word, QContent::EncodedWord),
+    alt((map(raw_word, QContent::EncodedWord),
          map(recognize_many1(P::qtext), |q| QContent::Literal(String::from_utf8_lossy(q))),
          map(quoted_pair::<P>, QContent::QP))
     )(input)
@@ -194,21 +320,53 @@ pub(crate) fn quoted_string<P: UTF8Policy>(input: &[u8]) -> NomResult<QuotedStri
 }
 
 /// A single mailbox with an optional display name.
+///
+/// Fields may be added in the future, use [`Mailbox::new`] to create
+/// one.
 #[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub struct Mailbox {
     /// The display name.
     pub dname: Option<String>,
     /// The address of this mailbox.
     pub address: types::Mailbox,
+    /// The comments attached to this mailbox.
+    ///
+    /// Always empty unless parsed with the
+    /// [`Commented`](crate::behaviour::Commented) behaviour.
+    pub comments: Vec<Comment>,
+    /// Set if obsolete syntax was used to parse this mailbox.
+    ///
+    /// Also set when this mailbox follows empty list elements. Always
+    /// `false` unless parsed with the
+    /// [`Obsolete`](crate::behaviour::Obsolete) behaviour.
+    pub obsolete: bool,
 }
 
 /// A group of many [`Mailbox`].
+///
+/// Fields may be added in the future, use [`Group::new`] to create
+/// one.
 #[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub struct Group {
     /// This group's display name.
     pub dname: String,
     /// The members of this group. May be empty.
     pub members: Vec<Mailbox>,
+    /// The comments attached to the group itself. Comments attached
+    /// to the members are found in each [`Mailbox`].
+    ///
+    /// Always empty unless parsed with the
+    /// [`Commented`](crate::behaviour::Commented) behaviour.
+    pub comments: Vec<Comment>,
+    /// Set if obsolete syntax was used to parse this group's display
+    /// name or if its member list is made of empty elements.
+    ///
+    /// Also set when this group follows empty list elements. Always
+    /// `false` unless parsed with the
+    /// [`Obsolete`](crate::behaviour::Obsolete) behaviour.
+    pub obsolete: bool,
 }
 
 /// An address is either a single [`Mailbox`] or a [`Group`].
@@ -220,11 +378,48 @@ pub enum Address {
     Group(Group),
 }
 
+impl Mailbox {
+    /// Create a mailbox without comments.
+    pub fn new(dname: Option<String>, address: types::Mailbox) -> Self {
+        Mailbox { dname, address, comments: Vec::new(), obsolete: false }
+    }
+}
+
+impl Group {
+    /// Create a group without comments.
+    pub fn new(dname: String, members: Vec<Mailbox>) -> Self {
+        Group { dname, members, comments: Vec::new(), obsolete: false }
+    }
+}
+
+impl fmt::Display for Mailbox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = headerwriter::mailbox_words::<Intl>(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = headerwriter::group_words::<Intl>(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Mailbox(m) => m.fmt(f),
+            Address::Group(g) => g.fmt(f),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 enum QContent<'a> {
     Literal(Cow<'a, str>),
     #[cfg(feature = "quoted-string-rfc2047")]
-    EncodedWord(String),
+    EncodedWord(crate::rfc2047::RawWord<'a>),
     QP(char),
 }
 
@@ -245,15 +440,30 @@ impl <'a> From<&'a Text<'a>> for &'a str {
 
 fn concat_qs<'a, A: Iterator<Item=QContent<'a>>>(input: A) -> String {
     let mut out = String::new();
+    #[cfg(feature = "quoted-string-rfc2047")]
+    let mut words = Vec::new();
 
     for qc in input {
+        #[cfg(feature = "quoted-string-rfc2047")]
+        {
+            if let QContent::EncodedWord(ew) = qc {
+                words.push(ew);
+                continue;
+            }
+            out.push_str(&decode_words(&words));
+            words.clear();
+        }
+
         match qc {
             QContent::Literal(lit) => out.push_str(&lit),
             #[cfg(feature = "quoted-string-rfc2047")]
-            QContent::EncodedWord(ew) => out.push_str(&ew),
+            QContent::EncodedWord(_) => (),
             QContent::QP(c) => out.push(c),
         }
     }
+    #[cfg(feature = "quoted-string-rfc2047")]
+    out.push_str(&decode_words(&words));
+
     out
 }
 
@@ -282,8 +492,14 @@ pub(crate) fn atom<P: UTF8Policy>(input: &[u8]) -> NomResult<&[u8]> {
     delimited(opt(cfws::<P>), recognize_many1(P::atext), opt(cfws::<P>))(input)
 }
 
+// Adjacent encoded words separated by folding whitespace, decoded
+// together.
+fn encoded_words(input: &[u8]) -> NomResult<'_, String> {
+    map(fold_prefix0(raw_word, preceded(fws, raw_word)), |w| decode_words(&w))(input)
+}
+
 pub(crate) fn _padded_encoded_word<P: UTF8Policy>(input: &[u8]) -> NomResult<String> {
-    delimited(opt(cfws::<P>), encoded_word, opt(cfws::<P>))(input)
+    delimited(opt(cfws::<P>), encoded_words, opt(cfws::<P>))(input)
 }
 
 fn word<P: UTF8Policy>(input: &[u8]) -> NomResult<Text> {
@@ -311,8 +527,68 @@ fn _concat_atom_and_qs<'a, A>(input: A) -> String
     out
 }
 
-fn display_name<P: UTF8Policy>(input: &[u8]) -> NomResult<String> {
-    map(many1(word::<P>), |words| _concat_atom_and_qs(words.into_iter().map(Into::into)))(input)
+// Run both the strict and the obsolete syntax if the policy allows
+// it and keep the longest match. The returned flag is set when the
+// obsolete syntax was needed.
+fn obs_alt<'a, P, O, F, G>(mut strict: F, mut obs: G) -> impl FnMut(&'a [u8]) -> NomResult<'a, (O, bool)>
+    where P: UTF8Policy,
+          F: FnMut(&'a [u8]) -> NomResult<'a, O>,
+          G: FnMut(&'a [u8]) -> NomResult<'a, O>,
+{
+    move |input| {
+        let strict_res = strict(input);
+        if !P::ALLOW_OBSOLETE {
+            return strict_res.map(|(rem, out)| (rem, (out, false)));
+        }
+
+        match (strict_res, obs(input)) {
+            (Ok((srem, _)), Ok((orem, out))) if orem.len() < srem.len() => Ok((orem, (out, true))),
+            (Ok((srem, out)), _) => Ok((srem, (out, false))),
+            (Err(_), Ok((orem, out))) => Ok((orem, (out, true))),
+            (Err(e), Err(_)) => Err(e),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum PhraseItem<'a> {
+    Word(Text<'a>),
+    // A period, followed by whitespace or not.
+    Dot(bool),
+}
+
+fn _concat_obs_phrase(input: Vec<PhraseItem>) -> String {
+    let mut out = String::new();
+    let mut prev: Option<PhraseItem> = None;
+
+    for item in input {
+        match (&prev, &item) {
+            (_, PhraseItem::Dot(_)) => out.push('.'),
+            (Some(PhraseItem::Dot(true)), PhraseItem::Word(_)) => out.push(' '),
+            (Some(PhraseItem::Word(Text::Atom(_))), PhraseItem::Word(_)) |
+            (Some(PhraseItem::Word(_)), PhraseItem::Word(Text::Atom(_))) => out.push(' '),
+            _ => (),
+        }
+        if let PhraseItem::Word(ref t) = item {
+            out.push_str(t.into());
+        }
+        prev = Some(item);
+    }
+
+    out
+}
+
+// Phrase with periods.
+fn obs_phrase<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, String> {
+    map(fold_prefix0(map(word::<P>, PhraseItem::Word),
+                     alt((map(word::<P>, PhraseItem::Word),
+                          map(preceded(tag("."), opt(cfws::<P>)), |ws| PhraseItem::Dot(ws.is_some()))))),
+        _concat_obs_phrase)(input)
+}
+
+fn display_name<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, (String, bool)> {
+    obs_alt::<P, _, _, _>(map(many1(word::<P>), |words| _concat_atom_and_qs(words.into_iter())),
+                          obs_phrase::<P>)(input)
 }
 
 pub(crate) fn local_part<P: UTF8Policy>(input: &[u8]) -> NomResult<LocalPart> {
@@ -320,6 +596,27 @@ pub(crate) fn local_part<P: UTF8Policy>(input: &[u8]) -> NomResult<LocalPart> {
          map(quoted_string::<P>, LocalPart::Quoted)))(input)
 }
 
+// Local part with CFWS between each word and quoted strings mixed
+// with atoms.
+fn obs_local_part<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, LocalPart> {
+    map(fold_prefix0(_obs_lp_word::<P>, preceded(tag("."), _obs_lp_word::<P>)),
+        |words| {
+            let quoted = words.iter().any(|(q, _)| *q);
+            let joined = words.into_iter().map(|(_, w)| w).collect::<Vec<_>>().join(".");
+
+            if quoted {
+                LocalPart::Quoted(QuotedString(joined))
+            } else {
+                LocalPart::DotAtom(DotAtom(joined))
+            }
+        })(input)
+}
+
+fn _obs_lp_word<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, (bool, String)> {
+    alt((map(atom::<P>, |a| (false, str::from_utf8(a).unwrap().into())),
+         map(quoted_string::<P>, |qs| (true, qs.0))))(input)
+}
+
 pub(crate) fn domain_literal<P: UTF8Policy>(input: &[u8]) -> NomResult<AddressLiteral> {
     map(delimited(pair(opt(cfws::<P>), tag("[")),
                   pair(many0(pair(ofws, recognize_many1(P::dtext))), ofws),
@@ -341,39 +638,150 @@ pub(crate) fn domain<P: UTF8Policy>(input: &[u8]) -> NomResult<DomainPart> {
          map(domain_literal::<P>, DomainPart::Address)))(input)
 }
 
+// Domain with CFWS between each atom.
+fn obs_domain<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, DomainPart> {
+    alt((map(fold_prefix0(atom::<P>, preceded(tag("."), atom::<P>)),
+             |atoms| {
+                 let atoms: Vec<_> = atoms.into_iter().map(|a| str::from_utf8(a).unwrap()).collect();
+                 DomainPart::Domain(Domain(atoms.join(".")))
+             }),
+         map(domain_literal::<P>, DomainPart::Address)))(input)
+}
+
 pub(crate) fn addr_spec<P: UTF8Policy>(input: &[u8]) -> NomResult<types::Mailbox> {
     map(separated_pair(local_part::<P>, tag("@"), domain::<P>),
         |(lp, domain)| types::Mailbox(lp, domain))(input)
 }
 
-fn angle_addr<P: UTF8Policy>(input: &[u8]) -> NomResult<types::Mailbox> {
-    delimited(pair(opt(cfws::<P>), tag("<")),
-              addr_spec::<P>,
-              pair(tag(">"), opt(cfws::<P>)))(input)
+fn obs_addr_spec<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, types::Mailbox> {
+    map(separated_pair(obs_local_part::<P>, tag("@"), obs_domain::<P>),
+        |(lp, domain)| types::Mailbox(lp, domain))(input)
+}
+
+// The source route is parsed but discarded.
+fn obs_route<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, ()> {
+    map(tuple((many0(alt((cfws::<P>, tag(",")))),
+               tag("@"), obs_domain::<P>,
+               many0(tuple((tag(","), opt(cfws::<P>), opt(preceded(tag("@"), obs_domain::<P>))))),
+               tag(":"))),
+        |_| ())(input)
 }
 
-fn name_addr<P: UTF8Policy>(input: &[u8]) -> NomResult<Mailbox> {
+fn angle_addr<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, (types::Mailbox, bool)> {
+    obs_alt::<P, _, _, _>(
+        delimited(pair(opt(cfws::<P>), tag("<")),
+                  addr_spec::<P>,
+                  pair(tag(">"), opt(cfws::<P>))),
+        delimited(pair(opt(cfws::<P>), tag("<")),
+                  preceded(opt(obs_route::<P>), obs_addr_spec::<P>),
+                  pair(tag(">"), opt(cfws::<P>))))(input)
+}
+
+fn name_addr<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, (Option<String>, types::Mailbox, bool)> {
     map(pair(opt(display_name::<P>), angle_addr::<P>),
-        |(dname, address)| Mailbox{dname, address})(input)
+        |(dname, (address, obs_addr))| match dname {
+            Some((dname, obs_dname)) => (Some(dname), address, obs_dname || obs_addr),
+            None => (None, address, obs_addr),
+        })(input)
 }
 
 fn mailbox<P: UTF8Policy>(input: &[u8]) -> NomResult<Mailbox> {
-    alt((name_addr::<P>,
-         map(addr_spec::<P>, |a| Mailbox{dname: None, address: a})))(input)
+    map(commented::<P, _, _>(alt((name_addr::<P>,
+                                  map(obs_alt::<P, _, _, _>(addr_spec::<P>, obs_addr_spec::<P>),
+                                      |(a, obsolete)| (None, a, obsolete))))),
+        |((dname, address, obsolete), comments)| Mailbox{dname, address, comments, obsolete})(input)
+}
+
+trait MarkObsolete {
+    fn mark_obsolete(&mut self);
+}
+
+impl MarkObsolete for Mailbox {
+    fn mark_obsolete(&mut self) {
+        self.obsolete = true;
+    }
+}
+
+impl MarkObsolete for Group {
+    fn mark_obsolete(&mut self) {
+        self.obsolete = true;
+    }
+}
+
+impl MarkObsolete for Address {
+    fn mark_obsolete(&mut self) {
+        match self {
+            Address::Mailbox(m) => m.mark_obsolete(),
+            Address::Group(g) => g.mark_obsolete(),
+        }
+    }
+}
+
+// List with empty elements. The element following empty elements is
+// marked as obsolete, or the last element if they are trailing.
+fn obs_list<'a, P, O, F>(f: F) -> impl FnMut(&'a [u8]) -> NomResult<'a, Vec<O>>
+    where P: UTF8Policy,
+          O: MarkObsolete,
+          F: Fn(&'a [u8]) -> NomResult<'a, O> + Copy,
+{
+    map(pair(many0(pair(opt(cfws::<P>), tag(","))),
+             pair(f, many0(preceded(tag(","), opt(alt((map(f, Some), map(cfws::<P>, |_| None)))))))),
+        |(leading, (first, rest))| {
+            let mut out = Vec::with_capacity(rest.len()+1);
+            let mut pending = !leading.is_empty();
+
+            for elem in std::iter::once(Some(Some(first))).chain(rest) {
+                match elem {
+                    Some(Some(mut elem)) => {
+                        if pending {
+                            elem.mark_obsolete();
+                            pending = false;
+                        }
+                        out.push(elem);
+                    }
+                    _ => pending = true,
+                }
+            }
+            if pending {
+                if let Some(last) = out.last_mut() {
+                    last.mark_obsolete();
+                }
+            }
+
+            out
+        })
 }
 
 fn mailbox_list<P: UTF8Policy>(input: &[u8]) -> NomResult<Vec<Mailbox>> {
-    fold_prefix0(mailbox::<P>, preceded(tag(","), mailbox::<P>))(input)
+    if P::ALLOW_OBSOLETE {
+        obs_list::<P, _, _>(mailbox::<P>)(input)
+    } else {
+        fold_prefix0(mailbox::<P>, preceded(tag(","), mailbox::<P>))(input)
+    }
 }
 
-fn group_list<P: UTF8Policy>(input: &[u8]) -> NomResult<Vec<Mailbox>> {
-    alt((mailbox_list::<P>, map(cfws::<P>, |_| vec![])))(input)
+// Group list made only of commas.
+fn obs_group_list<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, &[u8]> {
+    verify(recognize(pair(many1(pair(opt(cfws::<P>), tag(","))), opt(cfws::<P>))),
+           |_: &[u8]| P::ALLOW_OBSOLETE)(input)
+}
+
+fn group_list<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, (Vec<Mailbox>, Vec<Comment>, bool)> {
+    alt((map(mailbox_list::<P>, |members| (members, vec![], false)),
+         map(commented::<P, _, _>(obs_group_list::<P>), |(_, comments)| (vec![], comments, true)),
+         map(commented::<P, _, _>(cfws::<P>), |(_, comments)| (vec![], comments, false))))(input)
 }
 
 fn group<P: UTF8Policy>(input: &[u8]) -> NomResult<Group> {
-    map(pair(terminated(display_name::<P>, tag(":")),
-             terminated(opt(group_list::<P>), pair(tag(";"), opt(cfws::<P>)))),
-        |(dname, members)| Group{dname, members: members.unwrap_or_default()})(input)
+    map(tuple((commented::<P, _, _>(terminated(display_name::<P>, tag(":"))),
+               opt(group_list::<P>),
+               commented::<P, _, _>(pair(tag(";"), opt(cfws::<P>))))),
+        |(((dname, obs_dname), mut comments), list, (_, trailing))| {
+            let (members, list_comments, obs_list) = list.unwrap_or_default();
+            comments.extend(list_comments);
+            comments.extend(trailing);
+            Group{dname, members, comments, obsolete: obs_dname || obs_list}
+        })(input)
 }
 
 fn address<P: UTF8Policy>(input: &[u8]) -> NomResult<Address> {
@@ -382,7 +790,11 @@ fn address<P: UTF8Policy>(input: &[u8]) -> NomResult<Address> {
 }
 
 fn address_list<P: UTF8Policy>(input: &[u8]) -> NomResult<Vec<Address>> {
-    fold_prefix0(address::<P>, preceded(tag(","), address::<P>))(input)
+    if P::ALLOW_OBSOLETE {
+        obs_list::<P, _, _>(address::<P>)(input)
+    } else {
+        fold_prefix0(address::<P>, preceded(tag(","), address::<P>))(input)
+    }
 }
 
 fn address_list_crlf<P: UTF8Policy>(input: &[u8]) -> NomResult<Vec<Address>> {
@@ -403,7 +815,7 @@ fn _8bit_char(input: &[u8]) -> NomResult<char> {
 pub fn unstructured<P: UTF8Policy>(input: &[u8]) -> NomResult<String> {
     map(pair(
         many0(alt((
-            pair(ofws, map(fold_prefix0(encoded_word, preceded(fws, encoded_word)), |ew| ew.into_iter().collect())),
+            pair(ofws, encoded_words),
             pair(ofws, map(many1(alt((P::vchar, _8bit_char))), |c| c.iter().collect::<String>()))
         ))),
         many0(wsp)),
@@ -441,3 +853,114 @@ pub fn sender<P: UTF8Policy>(i: &[u8]) -> NomResult<Address> {
 pub fn reply_to<P: UTF8Policy>(i: &[u8]) -> NomResult<Vec<Address>> {
     address_list_crlf::<P>(i)
 }
+
+/// A fragment of an address list that could not be parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Skipped<'a> {
+    /// Offset of the fragment from the start of the input.
+    pub offset: usize,
+    /// The unparsed fragment with surrounding whitespace removed.
+    pub content: &'a [u8],
+}
+
+/// Addresses extracted by [`recover_address_list`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecoveredAddresses<'a> {
+    /// The addresses that were parsed successfully.
+    pub addresses: Vec<Address>,
+    /// The list elements that were skipped, in input order.
+    pub skipped: Vec<Skipped<'a>>,
+}
+
+// Find the end of the current list element. Commas inside quoted
+// strings, comments, angle brackets and groups do not end the
+// element. A group that is never closed ends at its first comma.
+fn _element_end(input: &[u8]) -> usize {
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut comment_depth = 0usize;
+    let mut angle = false;
+    let mut group = false;
+    let mut group_comma = None;
+
+    for (i, c) in input.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            b'\\' if quoted || comment_depth > 0 => escaped = true,
+            b'"' if comment_depth == 0 => quoted = !quoted,
+            _ if quoted => (),
+            b'(' => comment_depth += 1,
+            b')' if comment_depth > 0 => comment_depth -= 1,
+            _ if comment_depth > 0 => (),
+            b'<' => angle = true,
+            b'>' => angle = false,
+            _ if angle => (),
+            b':' => group = true,
+            b';' => group = false,
+            b',' if group => { group_comma.get_or_insert(i); },
+            b',' => return i,
+            _ => (),
+        }
+    }
+
+    match group_comma {
+        Some(i) if group => i,
+        _ => input.len(),
+    }
+}
+
+fn _trim_fragment(offset: usize, input: &[u8]) -> Option<Skipped<'_>> {
+    let is_ws = |c: &u8| b" \t\r\n".contains(c);
+    let start = input.iter().position(|c| !is_ws(c))?;
+    let end = input.iter().rposition(|c| !is_ws(c)).unwrap() + 1;
+
+    Some(Skipped{offset: offset + start, content: &input[start..end]})
+}
+
+/// Parse an address list such as the content of a `"To:"` header,
+/// skipping over invalid elements.
+///
+/// Each list element that fails to parse is skipped up to the next
+/// comma that is not inside a quoted string, a comment, angle
+/// brackets or a group. The whole input is always consumed.
+///
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::Intl;
+/// use rustyknife::rfc5322::recover_address_list;
+///
+/// let (rem, parsed) = recover_address_list::<Intl>(b"a@example.org, broken@, \"b, c\" <b@example.org>\r\n").unwrap();
+///
+/// assert!(rem.is_empty());
+/// assert_eq!(parsed.addresses.len(), 2);
+/// assert_eq!(parsed.skipped.len(), 1);
+/// assert_eq!(parsed.skipped[0].offset, 15);
+/// assert_eq!(parsed.skipped[0].content, b"broken@");
+/// ```
+pub fn recover_address_list<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, RecoveredAddresses<'_>> {
+    let content = input.strip_suffix(b"\r\n").unwrap_or(input);
+    let mut out = RecoveredAddresses{addresses: Vec::new(), skipped: Vec::new()};
+    let mut pos = 0;
+
+    while pos <= content.len() {
+        let rem = &content[pos..];
+
+        match address::<P>(rem) {
+            Ok((after, addr)) if after.is_empty() || after[0] == b',' => {
+                out.addresses.push(addr);
+                pos += rem.len() - after.len() + 1;
+            }
+            _ => {
+                let end = _element_end(rem);
+                out.skipped.extend(_trim_fragment(pos, &rem[..end]));
+                pos += end + 1;
+            }
+        }
+    }
+
+    Ok((&input[input.len()..], out))
+}
@@ -0,0 +1,368 @@
+//! [DomainKeys Identified Mail (DKIM) Signatures]
+//!
+//! Parser for the content of the `"DKIM-Signature:"` header and the
+//! generic tag=value list syntax it uses.
+//!
+//! [DomainKeys Identified Mail (DKIM) Signatures]: https://tools.ietf.org/html/rfc6376
+
+use std::str;
+
+use nom::bytes::complete::{tag, take_while, take_while1};
+use nom::combinator::{map, map_res, opt, recognize};
+use nom::multi::separated_list1;
+use nom::sequence::{pair, terminated, tuple};
+
+use crate::rfc3461::hexpair;
+use crate::rfc5234::crlf;
+use crate::rfc5322::ofws;
+use crate::types::Domain;
+use crate::util::*;
+
+fn tag_name(input: &[u8]) -> NomResult<'_, &str> {
+    map(recognize(pair(take_while1(|c: u8| c.is_ascii_alphabetic()),
+                       take_while(|c: u8| c.is_ascii_alphanumeric() || c == b'_'))),
+        |n| str::from_utf8(n).unwrap())(input)
+}
+
+fn tval(input: &[u8]) -> NomResult<'_, &[u8]> {
+    take_while1(|c| (0x21..=0x3a).contains(&c) || (0x3c..=0x7e).contains(&c))(input)
+}
+
+fn tag_value(input: &[u8]) -> NomResult<'_, String> {
+    map(recognize_many0(pair(ofws, tval)), |v| {
+        // Unfold the value, whitespace is kept.
+        let v = str::from_utf8(v).unwrap().replace("\r\n", "");
+        v.trim_start_matches([' ', '\t']).into()
+    })(input)
+}
+
+fn tag_spec(input: &[u8]) -> NomResult<'_, (String, String)> {
+    map(tuple((ofws, tag_name, ofws, tag("="), ofws, tag_value, ofws)),
+        |(_, name, _, _, _, value, _)| (name.into(), value))(input)
+}
+
+/// Parse a tag=value list such as used by DKIM signatures and keys.
+///
+/// Values are unfolded but whitespace inside values is kept. A
+/// trailing semicolon is allowed.
+/// # Examples
+/// ```
+/// use rustyknife::rfc6376::tag_list;
+///
+/// let (_, tags) = tag_list(b"v=1; a=rsa-sha256;\r\n h=From : To;").unwrap();
+/// assert_eq!(tags, [("v".into(), "1".into()),
+///                   ("a".into(), "rsa-sha256".into()),
+///                   ("h".into(), "From : To".into())]);
+/// ```
+pub fn tag_list(input: &[u8]) -> NomResult<'_, Vec<(String, String)>> {
+    terminated(separated_list1(tag(";"), tag_spec), opt(pair(tag(";"), ofws)))(input)
+}
+
+// Decode DKIM quoted-printable, whitespace is ignored.
+fn decode_dkim_qp(input: &str) -> Result<Vec<u8>, &'static str> {
+    let input = input.as_bytes();
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < input.len() {
+        match input[i] {
+            b'=' => {
+                let (_, c) = hexpair(&input[i+1..]).map_err(|_| "Invalid quoted-printable")?;
+                out.push(c);
+                i += 3;
+                continue;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => (),
+            c => out.push(c),
+        }
+        i += 1;
+    }
+
+    Ok(out)
+}
+
+pub(crate) fn decode_base64(input: &str) -> Result<Vec<u8>, &'static str> {
+    let stripped: String = input.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    base64::decode(&stripped).map_err(|_| "Invalid base64")
+}
+
+/// A DKIM signing algorithm.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SigningAlgorithm {
+    /// `"rsa-sha1"`
+    RsaSha1,
+    /// `"rsa-sha256"`
+    RsaSha256,
+    /// `"ed25519-sha256"` from [RFC 8463](https://tools.ietf.org/html/rfc8463).
+    Ed25519Sha256,
+    /// Any other algorithm, in lowercase.
+    Other(String),
+}
+
+impl From<&str> for SigningAlgorithm {
+    fn from(value: &str) -> Self {
+        match &value.to_ascii_lowercase()[..] {
+            "rsa-sha1" => SigningAlgorithm::RsaSha1,
+            "rsa-sha256" => SigningAlgorithm::RsaSha256,
+            "ed25519-sha256" => SigningAlgorithm::Ed25519Sha256,
+            other => SigningAlgorithm::Other(other.into()),
+        }
+    }
+}
+
+/// A canonicalization algorithm for the header or the body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Canonicalization {
+    /// `"simple"`
+    Simple,
+    /// `"relaxed"`
+    Relaxed,
+}
+
+impl Canonicalization {
+    pub(crate) fn parse(value: &str) -> Result<Self, &'static str> {
+        match &value.to_ascii_lowercase()[..] {
+            "simple" => Ok(Canonicalization::Simple),
+            "relaxed" => Ok(Canonicalization::Relaxed),
+            _ => Err("Invalid canonicalization"),
+        }
+    }
+}
+
+/// The agent or user identifier from the `"i="` tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    /// The local part, if any.
+    pub local_part: Option<String>,
+    /// The domain. Same as or a subdomain of the signing domain.
+    pub domain: Domain,
+}
+
+/// The content of a `"DKIM-Signature:"` header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DKIMSignature {
+    /// The version from `"v="`. Always 1.
+    pub version: u32,
+    /// The signing algorithm from `"a="`.
+    pub algorithm: SigningAlgorithm,
+    /// The decoded signature from `"b="`.
+    pub signature: Vec<u8>,
+    /// The decoded body hash from `"bh="`.
+    pub body_hash: Vec<u8>,
+    /// The header canonicalization from `"c="`.
+    pub header_canonicalization: Canonicalization,
+    /// The body canonicalization from `"c="`.
+    pub body_canonicalization: Canonicalization,
+    /// The signing domain from `"d="`.
+    pub domain: Domain,
+    /// The signed header names from `"h="`, in signing order.
+    pub signed_headers: Vec<String>,
+    /// The identity from `"i="`. Defaults to an empty local part at
+    /// the signing domain.
+    pub identity: Identity,
+    /// The number of body octets signed from `"l="`.
+    pub body_length: Option<u64>,
+    /// The key query methods from `"q="`.
+    pub query_methods: Vec<String>,
+    /// The selector from `"s="`.
+    pub selector: String,
+    /// The signature timestamp from `"t="`.
+    pub timestamp: Option<u64>,
+    /// The signature expiration from `"x="`.
+    pub expiration: Option<u64>,
+    /// The decoded copied header fields from `"z="`.
+    pub copied_headers: Vec<(String, String)>,
+}
+
+pub(crate) fn parse_number(value: &str) -> Result<u64, &'static str> {
+    if value.is_empty() || !value.bytes().all(|c| c.is_ascii_digit()) {
+        return Err("Invalid number");
+    }
+    value.parse().map_err(|_| "Number out of range")
+}
+
+fn parse_identity(value: &str, domain: &Domain) -> Result<Identity, &'static str> {
+    let decoded = String::from_utf8(decode_dkim_qp(value)?).map_err(|_| "Invalid i=")?;
+    let at = decoded.rfind('@').ok_or("Invalid i=")?;
+    let identity = Identity {
+        local_part: Some(&decoded[..at]).filter(|l| !l.is_empty()).map(String::from),
+        domain: Domain::from_smtp(&decoded.as_bytes()[at+1..]).map_err(|_| "Invalid i= domain")?,
+    };
+
+    let id_domain = identity.domain.to_ascii_lowercase();
+    let domain = domain.to_ascii_lowercase();
+    if id_domain != domain && !id_domain.ends_with(&format!(".{}", domain)) {
+        return Err("i= domain is not within d=");
+    }
+
+    Ok(identity)
+}
+
+fn parse_copied_headers(value: &str) -> Result<Vec<(String, String)>, &'static str> {
+    value.split('|').map(|copy| {
+        let colon = copy.find(':').ok_or("Invalid z=")?;
+        let value = decode_dkim_qp(&copy[colon+1..])?;
+        Ok((copy[..colon].trim().into(), String::from_utf8_lossy(&value).into()))
+    }).collect()
+}
+
+pub(crate) fn check_duplicates(tags: &[(String, String)]) -> Result<(), &'static str> {
+    for (i, (name, _)) in tags.iter().enumerate() {
+        if tags[..i].iter().any(|(other, _)| other == name) {
+            return Err("Duplicate tag");
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn get_tag<'a>(tags: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+}
+
+impl DKIMSignature {
+    /// Build a signature from a parsed tag list.
+    ///
+    /// Fails if a required tag is missing, a tag is duplicated or a
+    /// tag value is invalid. Unknown tags are ignored.
+    pub fn from_tags(tags: &[(String, String)]) -> Result<Self, &'static str> {
+        Self::from_tags_with(tags, false)
+    }
+
+    // ARC message signatures have no version, use "i=" for the
+    // instance number and don't require From to be signed.
+    pub(crate) fn from_tags_with(tags: &[(String, String)], arc: bool) -> Result<Self, &'static str> {
+        check_duplicates(tags)?;
+        let get = |name: &str| get_tag(tags, name);
+
+        if !arc && get("v").ok_or("Missing v=")? != "1" {
+            return Err("Unsupported version");
+        }
+        let domain = Domain::from_smtp(get("d").ok_or("Missing d=")?.as_bytes())
+            .map_err(|_| "Invalid d=")?;
+
+        let signed_headers: Vec<String> = get("h").ok_or("Missing h=")?
+            .split(':').map(|h| h.trim().into()).collect();
+        if !arc && !signed_headers.iter().any(|h| h.eq_ignore_ascii_case("from")) {
+            return Err("h= does not include From");
+        }
+
+        let (header_canonicalization, body_canonicalization) = match get("c") {
+            Some(c) => {
+                let mut split = c.splitn(2, '/');
+                (Canonicalization::parse(split.next().unwrap())?,
+                 split.next().map(Canonicalization::parse).transpose()?.unwrap_or(Canonicalization::Simple))
+            }
+            None => (Canonicalization::Simple, Canonicalization::Simple),
+        };
+
+        let identity = match get("i") {
+            Some(i) if !arc => parse_identity(i, &domain)?,
+            _ => Identity { local_part: None, domain: domain.clone() },
+        };
+
+        Ok(DKIMSignature {
+            version: 1,
+            algorithm: get("a").ok_or("Missing a=")?.into(),
+            signature: decode_base64(get("b").ok_or("Missing b=")?)?,
+            body_hash: decode_base64(get("bh").ok_or("Missing bh=")?)?,
+            header_canonicalization,
+            body_canonicalization,
+            domain,
+            signed_headers,
+            identity,
+            body_length: get("l").map(parse_number).transpose()?,
+            query_methods: get("q").map(|q| q.split(':').map(|m| m.trim().to_ascii_lowercase()).collect())
+                .unwrap_or_else(|| vec!["dns/txt".into()]),
+            selector: get("s").ok_or("Missing s=")?.into(),
+            timestamp: get("t").map(parse_number).transpose()?,
+            expiration: get("x").map(parse_number).transpose()?,
+            copied_headers: get("z").map(parse_copied_headers).transpose()?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Parse the content of a `"DKIM-Signature:"` header.
+///
+/// Fails if the tag list is invalid or if a required tag is missing.
+/// See [`DKIMSignature::from_tags`] for details on the failure.
+/// # Examples
+/// ```
+/// use rustyknife::rfc6376::{dkim_signature, Canonicalization, SigningAlgorithm};
+///
+/// let (_, sig) = dkim_signature(b"v=1; a=rsa-sha256; c=relaxed; d=example.net; s=brisbane;\r
+///  h=from:to:subject; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r
+///  b=AuUoFEfDxTDkHlLXSZEpZj79LICEps6eda7W3deTVFOk4yAUoqOB\r
+///    4nujc7YopdG5dWLSdNg6xNAZpOPr+kHxt1IrE+NahM6L/LbvaHut\r\n").unwrap();
+///
+/// assert_eq!(sig.algorithm, SigningAlgorithm::RsaSha256);
+/// assert_eq!(sig.header_canonicalization, Canonicalization::Relaxed);
+/// assert_eq!(sig.body_canonicalization, Canonicalization::Simple);
+/// assert_eq!(sig.domain.to_string(), "example.net");
+/// assert_eq!(sig.signed_headers, ["from", "to", "subject"]);
+/// assert_eq!(sig.signature.len(), 78);
+/// ```
+pub fn dkim_signature(input: &[u8]) -> NomResult<'_, DKIMSignature> {
+    map_res(terminated(tag_list, opt(crlf)), |tags| DKIMSignature::from_tags(&tags))(input)
+}
+
+/// A DKIM public key record, usually found in DNS.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DKIMKey {
+    /// The key type from `"k="` in lowercase. Defaults to `"rsa"`.
+    pub key_type: String,
+    /// The decoded public key from `"p="`. Empty if the key was
+    /// revoked.
+    pub public_key: Vec<u8>,
+    /// The acceptable hash algorithms from `"h="`. Empty if all
+    /// algorithms are allowed.
+    pub hash_algorithms: Vec<String>,
+    /// The domain is testing DKIM, from the `"y"` flag of `"t="`.
+    pub testing: bool,
+    /// The identity domain must match the signing domain exactly,
+    /// from the `"s"` flag of `"t="`.
+    pub strict: bool,
+}
+
+fn split_colon_list(value: &str) -> Vec<String> {
+    value.split(':').map(|v| v.trim().to_ascii_lowercase()).filter(|v| !v.is_empty()).collect()
+}
+
+impl DKIMKey {
+    /// Build a key from a parsed tag list.
+    ///
+    /// Fails if `"p="` is missing, a tag is duplicated or the version
+    /// is not `"DKIM1"`. Unknown tags are ignored.
+    pub fn from_tags(tags: &[(String, String)]) -> Result<Self, &'static str> {
+        check_duplicates(tags)?;
+        let get = |name: &str| get_tag(tags, name);
+
+        match tags.first() {
+            Some((name, value)) if name == "v" && value != "DKIM1" => return Err("Unsupported version"),
+            _ if tags.iter().skip(1).any(|(name, _)| name == "v") => return Err("v= is not the first tag"),
+            _ => (),
+        }
+        let flags = get("t").map(split_colon_list).unwrap_or_default();
+
+        Ok(DKIMKey {
+            key_type: get("k").unwrap_or("rsa").to_ascii_lowercase(),
+            public_key: decode_base64(get("p").ok_or("Missing p=")?)?,
+            hash_algorithms: get("h").map(split_colon_list).unwrap_or_default(),
+            testing: flags.iter().any(|f| f == "y"),
+            strict: flags.iter().any(|f| f == "s"),
+        })
+    }
+}
+
+/// Parse a DKIM public key record.
+/// # Examples
+/// ```
+/// use rustyknife::rfc6376::dkim_key;
+///
+/// let (_, key) = dkim_key(b"v=DKIM1; k=ed25519; t=y; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=").unwrap();
+/// assert_eq!(key.key_type, "ed25519");
+/// assert_eq!(key.public_key.len(), 32);
+/// assert!(key.testing);
+/// ```
+pub fn dkim_key(input: &[u8]) -> NomResult<'_, DKIMKey> {
+    map_res(tag_list, |tags| DKIMKey::from_tags(&tags))(input)
+}
@@ -0,0 +1,483 @@
+//! [Sender Policy Framework (SPF)]
+//!
+//! Parser for SPF records, macro strings and the content of the
+//! `"Received-SPF:"` header. Evaluation is done by the [crate::spf]
+//! module and the header is serialized by
+//! [`headerwriter::received_spf`](crate::headerwriter::received_spf).
+//!
+//! Mechanism and modifier names are case insensitive and are
+//! returned in lowercase.
+//!
+//! [Sender Policy Framework (SPF)]: https://tools.ietf.org/html/rfc7208
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str;
+
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while, take_while1};
+use nom::combinator::{map, map_res, opt};
+use nom::multi::{many0, many1, separated_list0};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+
+use crate::behaviour::Intl;
+use crate::headerwriter;
+use crate::rfc5234::crlf;
+use crate::rfc5321::{ip_address, mailbox, Path, ReversePath};
+use crate::rfc5322::{cfws, flat_comment, ofws, quoted_string, Comment, UTF8Policy};
+use crate::rfc8601::AuthResult;
+use crate::util::*;
+
+/// The qualifier of a directive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Qualifier {
+    /// `"+"`, the default.
+    Pass,
+    /// `"-"`
+    Fail,
+    /// `"~"`
+    SoftFail,
+    /// `"?"`
+    Neutral,
+}
+
+/// A macro such as `"%{ir}"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Macro {
+    /// The macro letter in lowercase.
+    pub letter: char,
+    /// Whether the letter was uppercase, which requests URL escaping.
+    pub url_escape: bool,
+    /// The number of rightmost parts to keep.
+    pub digits: Option<u32>,
+    /// Whether the parts are reversed.
+    pub reverse: bool,
+    /// The characters that split the value into parts. Empty means
+    /// `"."`.
+    pub delimiters: String,
+}
+
+/// A part of a macro string.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroToken {
+    /// Literal text, with the `"%%"`, `"%_"` and `"%-"` escapes
+    /// already replaced.
+    Literal(String),
+    /// A macro to expand.
+    Macro(Macro),
+}
+
+/// A string that may contain macros, such as a domain specification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroString(pub Vec<MacroToken>);
+
+impl fmt::Display for MacroString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in &self.0 {
+            match token {
+                MacroToken::Literal(l) => write!(f, "{}", l.replace('%', "%%").replace(' ', "%_"))?,
+                MacroToken::Macro(m) => {
+                    let letter = if m.url_escape { m.letter.to_ascii_uppercase() } else { m.letter };
+                    write!(f, "%{{{}", letter)?;
+                    if let Some(d) = m.digits {
+                        write!(f, "{}", d)?;
+                    }
+                    write!(f, "{}{}}}", if m.reverse { "r" } else { "" }, m.delimiters)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A mechanism. Domain specifications default to the current domain
+/// when absent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mechanism {
+    /// `"all"`
+    All,
+    /// `"include:<domain>"`
+    Include(MacroString),
+    /// `"a[:<domain>][/<ip4-cidr>][//<ip6-cidr>]"`
+    A {
+        /// The domain to look up.
+        domain: Option<MacroString>,
+        /// The IPv4 prefix length.
+        ip4_cidr: Option<u8>,
+        /// The IPv6 prefix length.
+        ip6_cidr: Option<u8>,
+    },
+    /// `"mx[:<domain>][/<ip4-cidr>][//<ip6-cidr>]"`
+    MX {
+        /// The domain to look up.
+        domain: Option<MacroString>,
+        /// The IPv4 prefix length.
+        ip4_cidr: Option<u8>,
+        /// The IPv6 prefix length.
+        ip6_cidr: Option<u8>,
+    },
+    /// `"ptr[:<domain>]"`
+    PTR(Option<MacroString>),
+    /// `"ip4:<address>[/<cidr>]"`
+    IP4 {
+        /// The network address.
+        address: Ipv4Addr,
+        /// The prefix length.
+        cidr: Option<u8>,
+    },
+    /// `"ip6:<address>[/<cidr>]"`
+    IP6 {
+        /// The network address.
+        address: Ipv6Addr,
+        /// The prefix length.
+        cidr: Option<u8>,
+    },
+    /// `"exists:<domain>"`
+    Exists(MacroString),
+}
+
+/// A mechanism with its qualifier.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Directive {
+    /// The result if the mechanism matches.
+    pub qualifier: Qualifier,
+    /// The mechanism.
+    pub mechanism: Mechanism,
+}
+
+/// A parsed SPF record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SPFRecord {
+    /// The directives in evaluation order.
+    pub directives: Vec<Directive>,
+    /// The domain from the `"redirect="` modifier.
+    pub redirect: Option<MacroString>,
+    /// The domain from the `"exp="` modifier.
+    pub explanation: Option<MacroString>,
+    /// Unknown modifiers, which are ignored during evaluation.
+    pub modifiers: Vec<(String, MacroString)>,
+}
+
+fn macro_literal(input: &[u8]) -> NomResult<'_, MacroToken> {
+    map(take_while1(|c: u8| (0x21..=0x7e).contains(&c) && c != b'%'),
+        |l| MacroToken::Literal(str::from_utf8(l).unwrap().into()))(input)
+}
+
+fn macro_escape(input: &[u8]) -> NomResult<'_, MacroToken> {
+    map(alt((map(tag("%%"), |_| "%"), map(tag("%_"), |_| " "), map(tag("%-"), |_| "%20"))),
+        |l| MacroToken::Literal(l.into()))(input)
+}
+
+fn macro_expand(input: &[u8]) -> NomResult<'_, MacroToken> {
+    map(delimited(tag("%{"),
+                  tuple((take1_filter(|c| b"slodiphcrtvSLODIPHCRTV".contains(&c)),
+                         opt(map_res(take_while1(|c: u8| c.is_ascii_digit()),
+                                     |d| str::from_utf8(d).unwrap().parse::<u32>().ok().filter(|d| *d > 0).ok_or(()))),
+                         opt(tag_no_case("r")),
+                         take_while(|c: u8| b".-+,/_=".contains(&c)))),
+                  tag("}")),
+        |(letter, digits, reverse, delimiters)| MacroToken::Macro(Macro {
+            letter: (letter as char).to_ascii_lowercase(),
+            url_escape: letter.is_ascii_uppercase(),
+            digits,
+            reverse: reverse.is_some(),
+            delimiters: str::from_utf8(delimiters).unwrap().into(),
+        }))(input)
+}
+
+/// Parse a macro string.
+/// # Examples
+/// ```
+/// use rustyknife::rfc7208::{macro_string, MacroToken};
+///
+/// let (_, parsed) = macro_string(b"%{ir}.%{v}._spf.%{d2}").unwrap();
+/// assert_eq!(parsed.0.len(), 5);
+/// assert_eq!(parsed.0[1], MacroToken::Literal(".".into()));
+/// assert_eq!(parsed.to_string(), "%{ir}.%{v}._spf.%{d2}");
+/// ```
+pub fn macro_string(input: &[u8]) -> NomResult<'_, MacroString> {
+    map(many0(alt((macro_escape, macro_expand, macro_literal))), MacroString)(input)
+}
+
+/// Parse the explanation string found at the domain of the `"exp="`
+/// modifier. Unlike [`macro_string`], spaces are allowed.
+pub fn explain_string(input: &[u8]) -> NomResult<'_, MacroString> {
+    map(many0(alt((macro_escape, macro_expand, macro_literal, map(tag(" "), |_| MacroToken::Literal(" ".into()))))),
+        MacroString)(input)
+}
+
+fn parse_macro_string(value: &str) -> Result<MacroString, &'static str> {
+    exact!(value.as_bytes(), macro_string).map(|(_, m)| m).map_err(|_| "Invalid macro string")
+}
+
+fn parse_cidr(value: &str, max: u8) -> Result<u8, &'static str> {
+    match value.parse::<u8>() {
+        Ok(c) if c <= max && !(value.len() > 1 && value.starts_with('0')) && value.bytes().all(|c| c.is_ascii_digit()) => Ok(c),
+        _ => Err("Invalid CIDR length"),
+    }
+}
+
+// Split the trailing "/<ip4-cidr>//<ip6-cidr>" from a domain specification.
+fn dual_cidr(value: &str) -> Result<(&str, Option<u8>, Option<u8>), &'static str> {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+
+    let (value, ip6) = match value.rfind("//") {
+        Some(pos) if is_number(&value[pos+2..]) => (&value[..pos], Some(parse_cidr(&value[pos+2..], 128)?)),
+        _ => (value, None),
+    };
+    let (value, ip4) = match value.rfind('/') {
+        Some(pos) if is_number(&value[pos+1..]) => (&value[..pos], Some(parse_cidr(&value[pos+1..], 32)?)),
+        _ => (value, None),
+    };
+
+    Ok((value, ip4, ip6))
+}
+
+fn domain_arg(arg: Option<&str>) -> Result<Option<MacroString>, &'static str> {
+    arg.map(|d| match d.strip_prefix(':') {
+        Some(d) if !d.is_empty() => parse_macro_string(d),
+        _ => Err("Invalid domain specification"),
+    }).transpose()
+}
+
+fn required_domain(arg: Option<&str>) -> Result<MacroString, &'static str> {
+    domain_arg(arg)?.ok_or("Missing domain specification")
+}
+
+fn address_arg<T: str::FromStr>(arg: Option<&str>, max: u8) -> Result<(T, Option<u8>), &'static str> {
+    let arg = arg.and_then(|a| a.strip_prefix(':')).ok_or("Missing address")?;
+    let (address, cidr) = match arg.find('/') {
+        Some(pos) => (&arg[..pos], Some(parse_cidr(&arg[pos+1..], max)?)),
+        None => (arg, None),
+    };
+
+    Ok((address.parse().map_err(|_| "Invalid address")?, cidr))
+}
+
+fn directive(term: &str) -> Result<Directive, &'static str> {
+    let (qualifier, term) = match term.as_bytes().first().ok_or("Empty term")? {
+        b'+' => (Qualifier::Pass, &term[1..]),
+        b'-' => (Qualifier::Fail, &term[1..]),
+        b'~' => (Qualifier::SoftFail, &term[1..]),
+        b'?' => (Qualifier::Neutral, &term[1..]),
+        _ => (Qualifier::Pass, term),
+    };
+    let split = term.find([':', '/']).unwrap_or(term.len());
+    let (name, arg) = (&term[..split], Some(&term[split..]).filter(|a| !a.is_empty()));
+
+    let mechanism = match &name.to_ascii_lowercase()[..] {
+        "all" if arg.is_none() => Mechanism::All,
+        "include" => Mechanism::Include(required_domain(arg)?),
+        "exists" => Mechanism::Exists(required_domain(arg)?),
+        "ptr" => Mechanism::PTR(domain_arg(arg)?),
+        "a" | "mx" => {
+            let (domain, ip4_cidr, ip6_cidr) = dual_cidr(arg.unwrap_or(""))?;
+            let domain = domain_arg(Some(domain).filter(|d| !d.is_empty()))?;
+            if name.eq_ignore_ascii_case("a") {
+                Mechanism::A { domain, ip4_cidr, ip6_cidr }
+            } else {
+                Mechanism::MX { domain, ip4_cidr, ip6_cidr }
+            }
+        }
+        "ip4" => {
+            let (address, cidr) = address_arg(arg, 32)?;
+            Mechanism::IP4 { address, cidr }
+        }
+        "ip6" => {
+            let (address, cidr) = address_arg(arg, 128)?;
+            Mechanism::IP6 { address, cidr }
+        }
+        _ => return Err("Unknown mechanism"),
+    };
+
+    Ok(Directive { qualifier, mechanism })
+}
+
+// Split a modifier into its name and value.
+fn modifier(term: &str) -> Option<(&str, &str)> {
+    let end = term.find(|c: char| !(c.is_ascii_alphanumeric() || "-_.".contains(c)))?;
+
+    if end > 0 && term.as_bytes()[0].is_ascii_alphabetic() && term.as_bytes()[end] == b'=' {
+        Some((&term[..end], &term[end+1..]))
+    } else {
+        None
+    }
+}
+
+impl SPFRecord {
+    /// Build a record from its space separated terms.
+    ///
+    /// Fails if a term is invalid or if the `"redirect="` or `"exp="`
+    /// modifiers are repeated.
+    pub fn from_terms(terms: &[&str]) -> Result<Self, &'static str> {
+        let mut record = SPFRecord { directives: Vec::new(), redirect: None, explanation: None, modifiers: Vec::new() };
+
+        for term in terms {
+            match modifier(term) {
+                Some((name, value)) => {
+                    let value = parse_macro_string(value)?;
+                    let slot = match &name.to_ascii_lowercase()[..] {
+                        "redirect" => &mut record.redirect,
+                        "exp" => &mut record.explanation,
+                        other => {
+                            record.modifiers.push((other.into(), value));
+                            continue;
+                        }
+                    };
+                    if slot.replace(value).is_some() {
+                        return Err("Duplicate modifier");
+                    }
+                }
+                None => record.directives.push(directive(term)?),
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+/// Parse an SPF record, starting with `"v=spf1"`.
+/// # Examples
+/// ```
+/// use rustyknife::rfc7208::{spf_record, Mechanism, Qualifier};
+///
+/// let (_, record) = spf_record(b"v=spf1 mx a:mail.example.org/24 ip6:2001:db8::/32 -all").unwrap();
+/// assert_eq!(record.directives.len(), 4);
+/// assert_eq!(record.directives[1].mechanism, Mechanism::A {
+///     domain: Some("mail.example.org".parse().unwrap()),
+///     ip4_cidr: Some(24),
+///     ip6_cidr: None,
+/// });
+/// assert_eq!(record.directives[3].qualifier, Qualifier::Fail);
+/// ```
+pub fn spf_record(input: &[u8]) -> NomResult<'_, SPFRecord> {
+    map_res(preceded(tag_no_case("v=spf1"),
+                     pair(many0(preceded(many1(tag(" ")), map_res(take_while1(|c| c != b' '), str::from_utf8))),
+                          many0(tag(" ")))),
+            |(terms, _)| SPFRecord::from_terms(&terms))(input)
+}
+
+/// The content of a `"Received-SPF:"` header.
+///
+/// Values of the known keys that cannot be parsed are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceivedSPF {
+    /// The SPF result.
+    pub result: AuthResult,
+    /// The human readable comment following the result.
+    pub comment: Option<Comment>,
+    /// The `"client-ip="` address of the SMTP client.
+    pub client_ip: Option<IpAddr>,
+    /// The `"envelope-from="` address. An empty value is returned as
+    /// [`ReversePath::Null`].
+    pub envelope_from: Option<ReversePath>,
+    /// The `"helo="` domain.
+    pub helo: Option<String>,
+    /// The `"problem="` description of an error.
+    pub problem: Option<String>,
+    /// The `"receiver="` host that did the evaluation.
+    pub receiver: Option<String>,
+    /// The `"identity="` that was checked such as `"mailfrom"` or
+    /// `"helo"`.
+    pub identity: Option<String>,
+    /// The `"mechanism="` that matched.
+    pub mechanism: Option<String>,
+    /// Any other key and value pairs, with lowercase keys.
+    pub extensions: Vec<(String, String)>,
+}
+
+impl fmt::Display for ReceivedSPF {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", headerwriter::received_spf_words::<Intl>(self).join(" "))
+    }
+}
+
+fn envelope_from(value: &str) -> Option<ReversePath> {
+    let value = value.strip_prefix('<').and_then(|v| v.strip_suffix('>')).unwrap_or(value);
+    if value.is_empty() {
+        return Some(ReversePath::Null);
+    }
+    exact!(value.as_bytes(), mailbox::<Intl>).ok().map(|(_, m)| ReversePath::Path(Path(m, Vec::new())))
+}
+
+impl ReceivedSPF {
+    fn from_pairs(result: AuthResult, comment: Option<Comment>, pairs: Vec<(String, String)>) -> Self {
+        let mut out = ReceivedSPF {
+            result,
+            comment,
+            client_ip: None,
+            envelope_from: None,
+            helo: None,
+            problem: None,
+            receiver: None,
+            identity: None,
+            mechanism: None,
+            extensions: Vec::new(),
+        };
+
+        for (key, value) in pairs {
+            match &key[..] {
+                "client-ip" => out.client_ip = exact!(value.as_bytes(), ip_address).ok().map(|(_, ip)| ip),
+                "envelope-from" => out.envelope_from = envelope_from(&value),
+                "helo" => out.helo = Some(value),
+                "problem" => out.problem = Some(value),
+                "receiver" => out.receiver = Some(value),
+                "identity" => out.identity = Some(value),
+                "mechanism" => out.mechanism = Some(value),
+                _ => out.extensions.push((key, value)),
+            }
+        }
+
+        out
+    }
+}
+
+fn _received_spf_key(input: &[u8]) -> NomResult<'_, String> {
+    map(take_while1(|c: u8| c.is_ascii_alphanumeric() || b"-_.".contains(&c)),
+        |k| str::from_utf8(k).unwrap().to_ascii_lowercase())(input)
+}
+
+// A quoted string, or the raw text up to the next separator since
+// unquoted values such as IPv6 addresses are common.
+fn _received_spf_value<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, String> {
+    alt((map(quoted_string::<P>, String::from),
+         map(map_res(take_while1(|c: u8| !b" \t\r\n;()\"".contains(&c)), str::from_utf8), String::from)))(input)
+}
+
+fn _received_spf_pair<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, (String, String)> {
+    map(tuple((_received_spf_key, opt(cfws::<P>), tag("="), opt(cfws::<P>), _received_spf_value::<P>)),
+        |(key, _, _, _, value)| (key, value))(input)
+}
+
+/// Parse the content of a `"Received-SPF:"` header.
+///
+/// A trailing semicolon after the last pair is tolerated.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::Intl;
+/// use rustyknife::rfc7208::received_spf;
+/// use rustyknife::rfc8601::AuthResult;
+///
+/// let (_, parsed) = received_spf::<Intl>(b"pass (mybox.example.org: domain of\r\n myname@example.com designates 192.0.2.1 as permitted sender)\r\n receiver=mybox.example.org; client-ip=192.0.2.1;\r\n envelope-from=\"myname@example.com\"; helo=foo.example.com;\r\n").unwrap();
+/// assert_eq!(parsed.result, AuthResult::Pass);
+/// assert_eq!(parsed.client_ip, Some("192.0.2.1".parse().unwrap()));
+/// assert_eq!(parsed.envelope_from, Some("<myname@example.com>".parse().unwrap()));
+/// assert_eq!(parsed.helo.as_deref(), Some("foo.example.com"));
+/// ```
+pub fn received_spf<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, ReceivedSPF> {
+    map(terminated(tuple((opt(cfws::<P>),
+                          map(take_while1(|c: u8| c.is_ascii_alphabetic()), |r| AuthResult::from(str::from_utf8(r).unwrap())),
+                          opt(preceded(ofws, flat_comment::<P>)),
+                          opt(cfws::<P>),
+                          separated_list0(tuple((opt(cfws::<P>), tag(";"), opt(cfws::<P>))), _received_spf_pair::<P>))),
+                   tuple((opt(cfws::<P>), opt(tag(";")), opt(cfws::<P>), opt(crlf)))),
+        |(_, result, comment, _, pairs)| ReceivedSPF::from_pairs(result, comment, pairs))(input)
+}
+
+impl str::FromStr for MacroString {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_macro_string(s)
+    }
+}
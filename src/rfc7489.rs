@@ -0,0 +1,200 @@
+//! [Domain-based Message Authentication, Reporting, and Conformance (DMARC)]
+//!
+//! Parser for DMARC policy records. Evaluation is done by the
+//! [crate::dmarc] module.
+//!
+//! Invalid values of optional tags are replaced by their defaults and
+//! unknown tags are ignored.
+//!
+//! [Domain-based Message Authentication, Reporting, and Conformance (DMARC)]: https://tools.ietf.org/html/rfc7489
+
+use nom::combinator::map_res;
+
+use crate::rfc6376::{get_tag, tag_list};
+use crate::util::*;
+
+/// The requested handling of messages that fail DMARC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Policy {
+    /// `"none"`
+    None,
+    /// `"quarantine"`
+    Quarantine,
+    /// `"reject"`
+    Reject,
+}
+
+impl Policy {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match &value.to_ascii_lowercase()[..] {
+            "none" => Some(Policy::None),
+            "quarantine" => Some(Policy::Quarantine),
+            "reject" => Some(Policy::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// The identifier alignment mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Alignment {
+    /// `"r"`, the organizational domains must match. The default.
+    Relaxed,
+    /// `"s"`, the domains must match exactly.
+    Strict,
+}
+
+impl Alignment {
+    pub(crate) fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some(v) if v.eq_ignore_ascii_case("s") => Alignment::Strict,
+            _ => Alignment::Relaxed,
+        }
+    }
+}
+
+/// A failure reporting option from the `"fo="` tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailureOption {
+    /// `"0"`, report if all mechanisms fail to produce an aligned pass.
+    All,
+    /// `"1"`, report if any mechanism fails to produce an aligned pass.
+    Any,
+    /// `"d"`, report DKIM signatures that fail evaluation.
+    DKIM,
+    /// `"s"`, report SPF evaluations that fail.
+    SPF,
+}
+
+/// A reporting address from the `"rua="` or `"ruf="` tags.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportURI {
+    /// The URI such as `"mailto:reports@example.org"`.
+    pub uri: String,
+    /// The maximum report size in bytes.
+    pub max_size: Option<u64>,
+}
+
+/// A parsed DMARC record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DMARCRecord {
+    /// The policy from `"p="`.
+    pub policy: Policy,
+    /// The policy for subdomains from `"sp="`.
+    pub subdomain_policy: Option<Policy>,
+    /// The percentage of failing messages to apply the policy to,
+    /// from `"pct="`. Defaults to 100.
+    pub percent: u8,
+    /// The DKIM alignment mode from `"adkim="`.
+    pub dkim_alignment: Alignment,
+    /// The SPF alignment mode from `"aspf="`.
+    pub spf_alignment: Alignment,
+    /// The aggregate report addresses from `"rua="`.
+    pub aggregate_reports: Vec<ReportURI>,
+    /// The failure report addresses from `"ruf="`.
+    pub failure_reports: Vec<ReportURI>,
+    /// The failure reporting options from `"fo="`. Defaults to
+    /// [`FailureOption::All`].
+    pub failure_options: Vec<FailureOption>,
+    /// The failure report formats from `"rf="`. Defaults to `"afrf"`.
+    pub report_formats: Vec<String>,
+    /// The aggregate report interval in seconds from `"ri="`.
+    /// Defaults to 86400.
+    pub report_interval: u32,
+}
+
+fn report_uri(value: &str) -> Option<ReportURI> {
+    let value = value.trim_matches(|c| c == ' ' || c == '\t');
+    let (uri, size) = match value.rfind('!') {
+        Some(pos) => (&value[..pos], Some(&value[pos+1..])),
+        None => (value, None),
+    };
+    if !uri.contains(':') {
+        return None;
+    }
+    let max_size = match size {
+        Some(size) => {
+            let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+                Some(pos) => size.split_at(pos),
+                None => (size, ""),
+            };
+            let multiplier = match &unit.to_ascii_lowercase()[..] {
+                "" => 1,
+                "k" => 1 << 10,
+                "m" => 1 << 20,
+                "g" => 1 << 30,
+                "t" => 1 << 40,
+                _ => return None,
+            };
+            Some(number.parse::<u64>().ok()?.checked_mul(multiplier)?)
+        }
+        None => None,
+    };
+
+    Some(ReportURI { uri: uri.into(), max_size })
+}
+
+fn report_uris(value: Option<&str>) -> Vec<ReportURI> {
+    value.map(|v| v.split(',').filter_map(report_uri).collect()).unwrap_or_default()
+}
+
+impl DMARCRecord {
+    /// Build a record from a parsed tag list.
+    ///
+    /// Fails if the first tag is not `"v=DMARC1"` or if the policy is
+    /// missing or invalid. A record with an invalid policy but a
+    /// valid aggregate report address is treated as `"p=none"`.
+    pub fn from_tags(tags: &[(String, String)]) -> Result<Self, &'static str> {
+        match tags.first() {
+            Some((name, value)) if name == "v" && value == "DMARC1" => (),
+            _ => return Err("Not a DMARC record"),
+        }
+        let get = |name: &str| get_tag(tags, name);
+
+        let aggregate_reports = report_uris(get("rua"));
+        let policy = match get("p").and_then(Policy::parse) {
+            Some(policy) => policy,
+            None if !aggregate_reports.is_empty() => Policy::None,
+            None => return Err("Missing or invalid p="),
+        };
+        let failure_options: Vec<_> = get("fo").unwrap_or("0").split(':').filter_map(|o| {
+            match &o.trim_matches(|c| c == ' ' || c == '\t').to_ascii_lowercase()[..] {
+                "0" => Some(FailureOption::All),
+                "1" => Some(FailureOption::Any),
+                "d" => Some(FailureOption::DKIM),
+                "s" => Some(FailureOption::SPF),
+                _ => None,
+            }
+        }).collect();
+
+        Ok(DMARCRecord {
+            policy,
+            subdomain_policy: get("sp").and_then(Policy::parse),
+            percent: get("pct").and_then(|p| p.parse().ok()).filter(|p| *p <= 100).unwrap_or(100),
+            dkim_alignment: Alignment::parse(get("adkim")),
+            spf_alignment: Alignment::parse(get("aspf")),
+            aggregate_reports,
+            failure_reports: report_uris(get("ruf")),
+            failure_options: if failure_options.is_empty() { vec![FailureOption::All] } else { failure_options },
+            report_formats: get("rf").unwrap_or("afrf").split(':').map(|f| f.trim().to_ascii_lowercase()).collect(),
+            report_interval: get("ri").and_then(|i| i.parse().ok()).unwrap_or(86400),
+        })
+    }
+}
+
+/// Parse a DMARC record, starting with `"v=DMARC1"`.
+/// # Examples
+/// ```
+/// use rustyknife::rfc7489::{dmarc_record, Alignment, Policy};
+///
+/// let (_, record) = dmarc_record(b"v=DMARC1; p=quarantine; pct=50; adkim=s; rua=mailto:dmarc@example.org!10m").unwrap();
+/// assert_eq!(record.policy, Policy::Quarantine);
+/// assert_eq!(record.percent, 50);
+/// assert_eq!(record.dkim_alignment, Alignment::Strict);
+/// assert_eq!(record.spf_alignment, Alignment::Relaxed);
+/// assert_eq!(record.aggregate_reports[0].uri, "mailto:dmarc@example.org");
+/// assert_eq!(record.aggregate_reports[0].max_size, Some(10 * 1024 * 1024));
+/// ```
+pub fn dmarc_record(input: &[u8]) -> NomResult<'_, DMARCRecord> {
+    map_res(tag_list, |tags| DMARCRecord::from_tags(&tags))(input)
+}
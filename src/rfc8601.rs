@@ -0,0 +1,221 @@
+//! [Message header field for indicating message authentication status]
+//!
+//! Parser for the content of the `"Authentication-Results:"` header.
+//! Serialization is done by
+//! [`headerwriter::authentication_results`](crate::headerwriter::authentication_results).
+//!
+//! Keywords such as method names, results and property types are
+//! case insensitive and are returned in lowercase.
+//!
+//! [Message header field for indicating message authentication status]: https://tools.ietf.org/html/rfc8601
+
+use std::fmt;
+use std::str;
+
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while1};
+use nom::combinator::{map, map_res, opt};
+use nom::multi::{many0, many1};
+use nom::sequence::{pair, preceded, terminated, tuple};
+
+use crate::behaviour::Intl;
+use crate::headerwriter;
+use crate::rfc2231::value;
+use crate::rfc5234::crlf;
+use crate::rfc5322::{cfws, commented, quoted_string, Comment, UTF8Policy};
+use crate::util::*;
+
+/// The result of an authentication method.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AuthResult {
+    /// `"none"`
+    None,
+    /// `"pass"`
+    Pass,
+    /// `"fail"`
+    Fail,
+    /// `"softfail"`
+    SoftFail,
+    /// `"neutral"`
+    Neutral,
+    /// `"temperror"`
+    TempError,
+    /// `"permerror"`
+    PermError,
+    /// `"policy"`
+    Policy,
+    /// Any other result, in lowercase.
+    Other(String),
+}
+
+impl From<&str> for AuthResult {
+    fn from(value: &str) -> Self {
+        match &value.to_ascii_lowercase()[..] {
+            "none" => AuthResult::None,
+            "pass" => AuthResult::Pass,
+            "fail" => AuthResult::Fail,
+            "softfail" => AuthResult::SoftFail,
+            "neutral" => AuthResult::Neutral,
+            "temperror" => AuthResult::TempError,
+            "permerror" => AuthResult::PermError,
+            "policy" => AuthResult::Policy,
+            other => AuthResult::Other(other.into()),
+        }
+    }
+}
+
+impl fmt::Display for AuthResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            AuthResult::None => "none",
+            AuthResult::Pass => "pass",
+            AuthResult::Fail => "fail",
+            AuthResult::SoftFail => "softfail",
+            AuthResult::Neutral => "neutral",
+            AuthResult::TempError => "temperror",
+            AuthResult::PermError => "permerror",
+            AuthResult::Policy => "policy",
+            AuthResult::Other(other) => other,
+        })
+    }
+}
+
+/// A property of the message that was evaluated, such as
+/// `"smtp.mailfrom=example.org"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Property {
+    /// The property type such as `"smtp"` or `"header"`.
+    pub ptype: String,
+    /// The property name such as `"mailfrom"` or `"d"`.
+    pub property: String,
+    /// The property value.
+    pub value: String,
+}
+
+/// The result of a single authentication method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResInfo {
+    /// The method name such as `"spf"` or `"dkim"`.
+    pub method: String,
+    /// The method version, if specified.
+    pub method_version: Option<u32>,
+    /// The result of the method.
+    pub result: AuthResult,
+    /// The human readable reason for the result.
+    pub reason: Option<String>,
+    /// The properties that were evaluated.
+    pub properties: Vec<Property>,
+    /// The comments attached to this result.
+    ///
+    /// Always empty unless parsed with the
+    /// [`Commented`](crate::behaviour::Commented) behaviour.
+    pub comments: Vec<Comment>,
+}
+
+/// The content of an `"Authentication-Results:"` header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthenticationResults {
+    /// The identifier of the service that did the authentication.
+    pub authserv_id: String,
+    /// The header version, if specified.
+    pub version: Option<u32>,
+    /// The results. Empty when no authentication was done.
+    pub results: Vec<ResInfo>,
+    /// The comments attached to the authserv-id.
+    ///
+    /// Always empty unless parsed with the
+    /// [`Commented`](crate::behaviour::Commented) behaviour.
+    pub comments: Vec<Comment>,
+}
+
+impl fmt::Display for AuthenticationResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", headerwriter::authentication_results_words::<Intl>(self).join(" "))
+    }
+}
+
+fn keyword(input: &[u8]) -> NomResult<'_, String> {
+    map(take_while1(|c: u8| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'),
+        |k| str::from_utf8(k).unwrap().to_ascii_lowercase())(input)
+}
+
+fn number(input: &[u8]) -> NomResult<'_, u32> {
+    map_res(take_while1(|c: u8| c.is_ascii_digit()),
+            |n| str::from_utf8(n).unwrap().parse())(input)
+}
+
+fn _equals<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, ()> {
+    map(tuple((opt(cfws::<P>), tag("="), opt(cfws::<P>))), |_| ())(input)
+}
+
+fn method<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, (String, Option<u32>)> {
+    pair(keyword,
+         opt(preceded(tuple((opt(cfws::<P>), tag("/"), opt(cfws::<P>))), number)))(input)
+}
+
+fn reasonspec<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, String> {
+    map(preceded(tuple((opt(cfws::<P>), tag_no_case("reason"), _equals::<P>)), value),
+        String::from)(input)
+}
+
+// A token extended with "@" to allow addresses.
+fn _address_token(input: &[u8]) -> NomResult<'_, &str> {
+    map(take_while1(|c: u8| (33..=126).contains(&c) && !b"()<>,;:\\\"/[]?=".contains(&c)),
+        |v| str::from_utf8(v).unwrap())(input)
+}
+
+// Addresses with a quoted local part keep their quotes.
+pub(crate) fn pvalue(input: &[u8]) -> NomResult<'_, String> {
+    alt((map(pair(quoted_string::<Intl>, preceded(tag("@"), _address_token)),
+             |(local, domain)| format!("{}@{}", local.quoted(), domain)),
+         map(_address_token, String::from),
+         map(value, String::from)))(input)
+}
+
+fn propspec<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, Property> {
+    map(tuple((opt(cfws::<P>), keyword,
+               opt(cfws::<P>), tag("."), opt(cfws::<P>), keyword,
+               _equals::<P>, pvalue)),
+        |(_, ptype, _, _, _, property, _, value)| Property { ptype, property, value })(input)
+}
+
+fn resinfo<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, ResInfo> {
+    map(commented::<P, _, _>(tuple((opt(cfws::<P>), tag(";"), opt(cfws::<P>),
+                                    method::<P>, _equals::<P>, keyword,
+                                    opt(reasonspec::<P>), many0(propspec::<P>), opt(cfws::<P>)))),
+        |((_, _, _, (method, method_version), _, result, reason, properties, _), comments)| {
+            ResInfo { method, method_version, result: AuthResult::from(&result[..]), reason, properties, comments }
+        })(input)
+}
+
+fn no_result<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, Vec<ResInfo>> {
+    map(tuple((opt(cfws::<P>), tag(";"), opt(cfws::<P>), tag_no_case("none"), opt(cfws::<P>))),
+        |_| Vec::new())(input)
+}
+
+/// Parse the content of an `"Authentication-Results:"` header.
+///
+/// A trailing semicolon after the last result is tolerated.
+/// # Examples
+/// ```
+/// use rustyknife::behaviour::Intl;
+/// use rustyknife::rfc8601::{authentication_results, AuthResult};
+///
+/// let (_, parsed) = authentication_results::<Intl>(b"mx.example.org 1;\r\n spf=pass smtp.mailfrom=example.net;\r\n dkim=fail (bad signature) reason=\"body hash\" header.d=example.net\r\n").unwrap();
+/// assert_eq!(parsed.authserv_id, "mx.example.org");
+/// assert_eq!(parsed.version, Some(1));
+/// assert_eq!(parsed.results[0].method, "spf");
+/// assert_eq!(parsed.results[0].result, AuthResult::Pass);
+/// assert_eq!(parsed.results[0].properties[0].value, "example.net");
+/// assert_eq!(parsed.results[1].reason, Some("body hash".into()));
+/// ```
+pub fn authentication_results<P: UTF8Policy>(input: &[u8]) -> NomResult<'_, AuthenticationResults> {
+    map(terminated(pair(commented::<P, _, _>(tuple((opt(cfws::<P>), value,
+                                                     opt(preceded(opt(cfws::<P>), number)),
+                                                     opt(cfws::<P>)))),
+                        alt((many1(resinfo::<P>), no_result::<P>))),
+                   tuple((opt(tag(";")), opt(cfws::<P>), opt(crlf)))),
+        |(((_, authserv_id, version, _), comments), results)| {
+            AuthenticationResults { authserv_id: authserv_id.into(), version, results, comments }
+        })(input)
+}
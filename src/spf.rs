@@ -0,0 +1,423 @@
+//! [SPF] evaluation
+//!
+//! Implements the `check_host()` function including macro expansion
+//! and the DNS lookup limits. Records are parsed by the
+//! [crate::rfc7208] module and DNS lookups go through a
+//! [`Resolver`].
+//!
+//! [SPF]: https://tools.ietf.org/html/rfc7208
+
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dns::{LookupError, Resolver};
+use crate::rfc5321::ReversePath;
+use crate::rfc7208::{explain_string, spf_record, MacroString, MacroToken, Mechanism, Qualifier, SPFRecord};
+use crate::rfc8601::AuthResult;
+use crate::types::{Domain, DomainPart, LocalPart, Mailbox};
+
+/// The maximum number of mechanisms and modifiers that cause DNS
+/// lookups.
+pub const MAX_LOOKUPS: u32 = 10;
+
+/// The maximum number of lookups returning no records.
+pub const MAX_VOID_LOOKUPS: u32 = 2;
+
+// The maximum number of names looked up for the mx and ptr
+// mechanisms.
+const MAX_NAMES: usize = 10;
+
+/// The result of an SPF evaluation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SPFResult {
+    /// One of [`AuthResult::None`], [`AuthResult::Neutral`],
+    /// [`AuthResult::Pass`], [`AuthResult::Fail`],
+    /// [`AuthResult::SoftFail`], [`AuthResult::TempError`] or
+    /// [`AuthResult::PermError`].
+    pub result: AuthResult,
+    /// The reason for an error result.
+    pub reason: Option<&'static str>,
+    /// The explanation from the `"exp="` modifier for a fail result.
+    pub explanation: Option<String>,
+}
+
+type Failure = (AuthResult, &'static str);
+
+fn perm(reason: &'static str) -> Failure {
+    (AuthResult::PermError, reason)
+}
+
+fn qualifier_result(qualifier: Qualifier) -> AuthResult {
+    match qualifier {
+        Qualifier::Pass => AuthResult::Pass,
+        Qualifier::Fail => AuthResult::Fail,
+        Qualifier::SoftFail => AuthResult::SoftFail,
+        Qualifier::Neutral => AuthResult::Neutral,
+    }
+}
+
+fn is_spf(record: &str) -> bool {
+    let record = record.as_bytes();
+    record.len() >= 6 && record[..6].eq_ignore_ascii_case(b"v=spf1") && (record.len() == 6 || record[6] == b' ')
+}
+
+// Check that a domain is a fully qualified name with valid label lengths.
+fn valid_domain(domain: &str) -> bool {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    domain.len() <= 253 && domain.contains('.') && domain.split('.').all(|l| !l.is_empty() && l.len() <= 63)
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, cidr: Option<u8>) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let bits = u32::from(cidr.unwrap_or(32));
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let bits = u32::from(cidr.unwrap_or(128));
+            let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+fn url_escape(value: &str) -> String {
+    value.bytes().map(|c| match c {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (c as char).to_string(),
+        _ => format!("%{:02X}", c),
+    }).collect()
+}
+
+// Keep the rightmost labels so that the name fits in 253 octets.
+fn truncate_domain(mut domain: &str) -> &str {
+    while domain.len() > 253 {
+        domain = match domain.find('.') {
+            Some(pos) => &domain[pos+1..],
+            None => "",
+        };
+    }
+    domain
+}
+
+fn same_or_subdomain(name: &str, domain: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    let domain = domain.trim_end_matches('.');
+
+    name.eq_ignore_ascii_case(domain) ||
+        (name.len() > domain.len() && name.as_bytes()[name.len()-domain.len()-1] == b'.' &&
+         name[name.len()-domain.len()..].eq_ignore_ascii_case(domain))
+}
+
+struct Evaluator<'a, R> {
+    resolver: &'a R,
+    ip: IpAddr,
+    sender: &'a Mailbox,
+    helo: &'a str,
+    lookups: u32,
+    void_lookups: u32,
+}
+
+// The result of evaluating a record along with the explanation
+// string and the domain it applies to.
+type Evaluation = (AuthResult, Option<(MacroString, String)>);
+
+impl<'a, R: Resolver> Evaluator<'a, R> {
+    fn count_lookup(&mut self) -> Result<(), Failure> {
+        self.lookups += 1;
+        if self.lookups > MAX_LOOKUPS {
+            return Err(perm("Too many DNS lookups"));
+        }
+        Ok(())
+    }
+
+    // Handle the result of a lookup done by a mechanism.
+    fn checked<T>(&mut self, result: Result<Vec<T>, LookupError>) -> Result<Vec<T>, Failure> {
+        match result {
+            Ok(ref records) if !records.is_empty() => Ok(result.unwrap()),
+            Ok(_) | Err(LookupError::NotFound) => {
+                self.void_lookups += 1;
+                if self.void_lookups > MAX_VOID_LOOKUPS {
+                    return Err(perm("Too many void lookups"));
+                }
+                Ok(Vec::new())
+            }
+            Err(LookupError::TempFail) => Err((AuthResult::TempError, "DNS lookup failed")),
+        }
+    }
+
+    fn addresses(&self, name: &str) -> Result<Vec<IpAddr>, LookupError> {
+        match self.ip {
+            IpAddr::V4(_) => self.resolver.lookup_a(name).map(|a| a.into_iter().map(IpAddr::V4).collect()),
+            IpAddr::V6(_) => self.resolver.lookup_aaaa(name).map(|a| a.into_iter().map(IpAddr::V6).collect()),
+        }
+    }
+
+    // The names of the client IP that map back to it.
+    fn validated_names(&self) -> Vec<String> {
+        self.resolver.lookup_ptr(self.ip).unwrap_or_default().into_iter()
+            .take(MAX_NAMES)
+            .filter(|name| self.addresses(name).map(|a| a.contains(&self.ip)).unwrap_or(false))
+            .collect()
+    }
+
+    fn ip_macro(&self) -> String {
+        match self.ip {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => ip.octets().iter()
+                .flat_map(|o| vec![format!("{:x}", o >> 4), format!("{:x}", o & 0xf)])
+                .collect::<Vec<_>>().join("."),
+        }
+    }
+
+    fn expand(&self, spec: &MacroString, domain: &str, explanation: bool) -> Result<String, Failure> {
+        let mut out = String::new();
+
+        for token in &spec.0 {
+            let m = match token {
+                MacroToken::Literal(l) => {
+                    out.push_str(l);
+                    continue;
+                }
+                MacroToken::Macro(m) => m,
+            };
+            let value = match m.letter {
+                's' => self.sender.to_string(),
+                'l' => self.sender.local_part().to_string(),
+                'o' => self.sender.domain_part().to_string(),
+                'd' => domain.to_string(),
+                'i' => self.ip_macro(),
+                'p' => {
+                    let names = self.validated_names();
+                    names.iter().find(|n| same_or_subdomain(n, domain)).or_else(|| names.first())
+                        .map(|n| n.trim_end_matches('.').to_string())
+                        .unwrap_or_else(|| "unknown".into())
+                }
+                'v' => if self.ip.is_ipv4() { "in-addr" } else { "ip6" }.into(),
+                'h' => self.helo.to_string(),
+                'c' if explanation => self.ip.to_string(),
+                'r' if explanation => "unknown".into(),
+                't' if explanation => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0).to_string(),
+                _ => return Err(perm("Invalid macro")),
+            };
+
+            let delimiters = if m.delimiters.is_empty() { "." } else { &m.delimiters };
+            let mut parts: Vec<_> = value.split(|c| delimiters.contains(c)).collect();
+            if m.reverse {
+                parts.reverse();
+            }
+            if let Some(digits) = m.digits {
+                parts = parts.split_off(parts.len().saturating_sub(digits as usize));
+            }
+            let value = parts.join(".");
+
+            out.push_str(&if m.url_escape { url_escape(&value) } else { value });
+        }
+
+        if explanation {
+            Ok(out)
+        } else {
+            Ok(truncate_domain(&out).into())
+        }
+    }
+
+    fn target(&self, spec: &Option<MacroString>, domain: &str) -> Result<String, Failure> {
+        match spec {
+            Some(spec) => self.expand(spec, domain, false),
+            None => Ok(domain.into()),
+        }
+    }
+
+    fn record(&self, domain: &str) -> Result<Option<SPFRecord>, Failure> {
+        let records = match self.resolver.lookup_txt(domain) {
+            Ok(records) => records,
+            Err(LookupError::NotFound) => return Ok(None),
+            Err(LookupError::TempFail) => return Err((AuthResult::TempError, "DNS lookup failed")),
+        };
+        let mut records = records.into_iter().filter(|r| is_spf(r));
+
+        match (records.next(), records.next()) {
+            (None, _) => Ok(None),
+            (Some(record), None) => exact!(record.as_bytes(), spf_record)
+                .map(|(_, r)| Some(r))
+                .map_err(|_| perm("Invalid SPF record")),
+            _ => Err(perm("Multiple SPF records")),
+        }
+    }
+
+    fn matches(&mut self, mechanism: &Mechanism, domain: &str) -> Result<bool, Failure> {
+        match mechanism {
+            Mechanism::All => Ok(true),
+            Mechanism::Include(spec) => {
+                self.count_lookup()?;
+                let target = self.expand(spec, domain, false)?;
+                match self.check_host(&target)?.0 {
+                    AuthResult::Pass => Ok(true),
+                    AuthResult::None => Err(perm("Included domain has no SPF record")),
+                    _ => Ok(false),
+                }
+            }
+            Mechanism::A { domain: spec, ip4_cidr, ip6_cidr } => {
+                self.count_lookup()?;
+                let target = self.target(spec, domain)?;
+                let cidr = if self.ip.is_ipv4() { *ip4_cidr } else { *ip6_cidr };
+                let addresses = self.addresses(&target);
+                Ok(self.checked(addresses)?.into_iter().any(|a| in_network(self.ip, a, cidr)))
+            }
+            Mechanism::MX { domain: spec, ip4_cidr, ip6_cidr } => {
+                self.count_lookup()?;
+                let target = self.target(spec, domain)?;
+                let cidr = if self.ip.is_ipv4() { *ip4_cidr } else { *ip6_cidr };
+                let names = self.resolver.lookup_mx(&target);
+                let names = self.checked(names)?;
+                if names.len() > MAX_NAMES {
+                    return Err(perm("Too many MX records"));
+                }
+                for name in names {
+                    match self.addresses(&name) {
+                        Ok(addresses) if addresses.iter().any(|a| in_network(self.ip, *a, cidr)) => return Ok(true),
+                        Err(LookupError::TempFail) => return Err((AuthResult::TempError, "DNS lookup failed")),
+                        _ => (),
+                    }
+                }
+                Ok(false)
+            }
+            Mechanism::PTR(spec) => {
+                self.count_lookup()?;
+                let target = self.target(spec, domain)?;
+                Ok(self.validated_names().iter().any(|n| same_or_subdomain(n, &target)))
+            }
+            Mechanism::IP4 { address, cidr } => Ok(in_network(self.ip, IpAddr::V4(*address), *cidr)),
+            Mechanism::IP6 { address, cidr } => Ok(in_network(self.ip, IpAddr::V6(*address), *cidr)),
+            Mechanism::Exists(spec) => {
+                self.count_lookup()?;
+                let target = self.expand(spec, domain, false)?;
+                let addresses = self.resolver.lookup_a(&target);
+                Ok(!self.checked(addresses)?.is_empty())
+            }
+        }
+    }
+
+    fn check_host(&mut self, domain: &str) -> Result<Evaluation, Failure> {
+        if !valid_domain(domain) {
+            return Ok((AuthResult::None, None));
+        }
+        let record = match self.record(domain)? {
+            Some(record) => record,
+            None => return Ok((AuthResult::None, None)),
+        };
+
+        for directive in &record.directives {
+            if self.matches(&directive.mechanism, domain)? {
+                let explanation = record.explanation.clone().map(|e| (e, domain.into()));
+                return Ok((qualifier_result(directive.qualifier), explanation));
+            }
+        }
+
+        match &record.redirect {
+            Some(spec) => {
+                self.count_lookup()?;
+                let target = self.expand(spec, domain, false)?;
+                match self.check_host(&target)? {
+                    (AuthResult::None, _) => Err(perm("Redirect domain has no SPF record")),
+                    result => Ok(result),
+                }
+            }
+            None => Ok((AuthResult::Neutral, None)),
+        }
+    }
+
+    // Errors while building the explanation are ignored.
+    fn explain(&self, spec: &MacroString, domain: &str) -> Option<String> {
+        let target = self.expand(spec, domain, false).ok()?;
+        let records = self.resolver.lookup_txt(&target).ok()?;
+        if records.len() != 1 {
+            return None;
+        }
+        let (_, text) = exact!(records[0].as_bytes(), explain_string).ok()?;
+
+        self.expand(&text, domain, true).ok()
+    }
+}
+
+/// Evaluate the SPF policy of `domain` for a message sent by
+/// `sender` from `ip`.
+///
+/// `helo` is the name given by the client, used for macro expansion.
+pub fn check_host<R: Resolver>(resolver: &R, ip: IpAddr, domain: &Domain, sender: &Mailbox, helo: &str) -> SPFResult {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        _ => ip,
+    };
+    let mut evaluator = Evaluator { resolver, ip, sender, helo, lookups: 0, void_lookups: 0 };
+
+    match evaluator.check_host(&domain.to_string()) {
+        Ok((AuthResult::Fail, Some((spec, domain)))) => {
+            SPFResult { result: AuthResult::Fail, reason: None, explanation: evaluator.explain(&spec, &domain) }
+        }
+        Ok((result, _)) => SPFResult { result, reason: None, explanation: None },
+        Err((result, reason)) => SPFResult { result, reason: Some(reason), explanation: None },
+    }
+}
+
+fn postmaster(domain: &DomainPart) -> Mailbox {
+    Mailbox::from_parts(LocalPart::from_smtp(b"postmaster").unwrap(), domain.clone())
+}
+
+fn no_policy() -> SPFResult {
+    SPFResult { result: AuthResult::None, reason: None, explanation: None }
+}
+
+/// Evaluate the SPF policy for the `"MAIL FROM"` identity.
+///
+/// The null reverse path is checked as `"postmaster@<helo>"`. Address
+/// literals give a none result.
+/// # Examples
+/// ```
+/// # use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+/// # use rustyknife::dns::{LookupError, Resolver};
+/// # struct Dns;
+/// # impl Resolver for Dns {
+/// #     fn lookup_txt(&self, name: &str) -> Result<Vec<String>, LookupError> {
+/// #         Ok(vec!["v=spf1 ip4:192.0.2.0/24 -all".into()])
+/// #     }
+/// #     fn lookup_a(&self, _: &str) -> Result<Vec<Ipv4Addr>, LookupError> { Err(LookupError::NotFound) }
+/// #     fn lookup_aaaa(&self, _: &str) -> Result<Vec<Ipv6Addr>, LookupError> { Err(LookupError::NotFound) }
+/// #     fn lookup_mx(&self, _: &str) -> Result<Vec<String>, LookupError> { Err(LookupError::NotFound) }
+/// #     fn lookup_ptr(&self, _: IpAddr) -> Result<Vec<String>, LookupError> { Err(LookupError::NotFound) }
+/// # }
+/// use rustyknife::behaviour::Intl;
+/// use rustyknife::rfc5321::{ehlo_command, mail_command};
+/// use rustyknife::rfc8601::AuthResult;
+/// use rustyknife::spf::check_mail_from;
+///
+/// let (_, helo) = ehlo_command::<Intl>(b"EHLO mail.example.org\r\n").unwrap();
+/// let (_, (path, _)) = mail_command::<Intl>(b"MAIL FROM:<bob@example.org>\r\n").unwrap();
+///
+/// let res = check_mail_from(&Dns, "192.0.2.25".parse().unwrap(), &path, &helo);
+/// assert_eq!(res.result, AuthResult::Pass);
+/// let res = check_mail_from(&Dns, "198.51.100.1".parse().unwrap(), &path, &helo);
+/// assert_eq!(res.result, AuthResult::Fail);
+/// ```
+pub fn check_mail_from<R: Resolver>(resolver: &R, ip: IpAddr, path: &ReversePath, helo: &DomainPart) -> SPFResult {
+    let sender = match path {
+        ReversePath::Path(path) => path.0.clone(),
+        ReversePath::Null => postmaster(helo),
+    };
+
+    match sender.domain_part() {
+        DomainPart::Domain(domain) => check_host(resolver, ip, domain, &sender, &helo.to_string()),
+        DomainPart::Address(_) => no_policy(),
+    }
+}
+
+/// Evaluate the SPF policy for the `"HELO"` identity.
+///
+/// Address literals give a none result.
+pub fn check_helo<R: Resolver>(resolver: &R, ip: IpAddr, helo: &DomainPart) -> SPFResult {
+    match helo {
+        DomainPart::Domain(domain) => check_host(resolver, ip, domain, &postmaster(helo), &helo.to_string()),
+        DomainPart::Address(_) => no_policy(),
+    }
+}
@@ -1,4 +1,84 @@
+#[cfg(feature = "dkim")]
+mod test_arc;
+mod test_attachment;
+#[cfg(feature = "dkim")]
+mod test_dkim;
+mod test_dmarc;
+#[cfg(feature = "dmarc-report")]
+mod test_dmarc_report;
 mod test_headersection;
+mod test_headerwriter;
+mod test_mime;
+mod test_rfc2045;
+mod test_rfc2047;
 mod test_rfc2231;
+mod test_rfc3464;
 mod test_rfc5321;
 mod test_rfc5322;
+mod test_rfc6376;
+mod test_rfc7208;
+mod test_rfc7489;
+mod test_rfc8601;
+mod test_spf;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::dns::{LookupError, Resolver};
+
+// An in-memory DNS zone. Names are matched case-insensitively and
+// lookups of names with a "temp" label fail temporarily.
+#[derive(Default)]
+struct Zone {
+    txt: HashMap<String, Vec<String>>,
+    a: HashMap<String, Vec<Ipv4Addr>>,
+    aaaa: HashMap<String, Vec<Ipv6Addr>>,
+    mx: HashMap<String, Vec<String>>,
+    ptr: HashMap<IpAddr, Vec<String>>,
+}
+
+fn get<T: Clone>(map: &HashMap<String, Vec<T>>, name: &str) -> Result<Vec<T>, LookupError> {
+    let name = name.to_ascii_lowercase();
+    if name.split('.').any(|label| label == "temp") {
+        return Err(LookupError::TempFail);
+    }
+    map.get(&name).cloned().ok_or(LookupError::NotFound)
+}
+
+impl Resolver for Zone {
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, LookupError> {
+        get(&self.txt, name)
+    }
+
+    fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, LookupError> {
+        get(&self.a, name)
+    }
+
+    fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, LookupError> {
+        get(&self.aaaa, name)
+    }
+
+    fn lookup_mx(&self, name: &str) -> Result<Vec<String>, LookupError> {
+        get(&self.mx, name)
+    }
+
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, LookupError> {
+        self.ptr.get(&ip).cloned().ok_or(LookupError::NotFound)
+    }
+}
+
+impl Zone {
+    fn txt(&mut self, name: &str, record: &str) {
+        self.txt.entry(name.to_ascii_lowercase()).or_default().push(record.into());
+    }
+
+    // Add an A or AAAA record along with the matching PTR record.
+    fn a(&mut self, name: &str, ip: &str) {
+        let name = name.to_ascii_lowercase();
+        match ip.parse().unwrap() {
+            IpAddr::V4(ip) => self.a.entry(name.clone()).or_default().push(ip),
+            IpAddr::V6(ip) => self.aaaa.entry(name.clone()).or_default().push(ip),
+        }
+        self.ptr.entry(ip.parse().unwrap()).or_default().push(name);
+    }
+}
@@ -0,0 +1,185 @@
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+use crate::arc::*;
+use crate::dkim::{canonicalize_body, canonicalize_header};
+use crate::headersection::split_message;
+use crate::rfc6376::{Canonicalization::Relaxed, SigningAlgorithm};
+use crate::rfc8601::AuthResult;
+
+use super::Zone;
+
+const MESSAGE: &[u8] = b"From: Joe SixPack <joe@football.example.com>\r
+To: Suzie Q <suzie@shopping.example.net>\r
+Subject: Is dinner ready?\r
+\r
+Hi.\r
+\r
+We lost the game.  Are you hungry yet?\r
+";
+
+fn key() -> (Ed25519KeyPair, Zone) {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let mut zone = Zone::default();
+    zone.txt("arc._domainkey.example.org", &format!("v=DKIM1; k=ed25519; p={}", base64::encode(key.public_key().as_ref())));
+    (key, zone)
+}
+
+fn sign(key: &Ed25519KeyPair, data: &[u8]) -> String {
+    base64::encode(key.sign(digest::digest(&digest::SHA256, data).as_ref()).as_ref())
+}
+
+// The canonicalized header without the trailing CRLF.
+fn unsigned(name: &str, value: &str) -> Vec<u8> {
+    let mut out = canonicalize_header(Relaxed, name.as_bytes(), value.as_bytes());
+    out.truncate(out.len() - 2);
+    out
+}
+
+// Add an ARC set on top of `message`. `chain` holds the headers of
+// the previous sets in instance order and is extended.
+fn seal(message: &[u8], key: &Ed25519KeyPair, chain: &mut Vec<(&'static str, String)>, cv: &str) -> Vec<u8> {
+    let instance = chain.len() / 3 + 1;
+    let (fields, body) = split_message(message).unwrap();
+
+    let aar = format!(" i={}; mx{}.example.org; spf=pass smtp.mailfrom=example.com", instance, instance);
+
+    let bh = digest::digest(&digest::SHA256, &canonicalize_body(Relaxed, body));
+    let mut ams = format!(" i={}; a=ed25519-sha256; c=relaxed/relaxed; d=example.org; s=arc; x=2000;\r\n h=from:to:subject; bh={}; b=",
+                          instance, base64::encode(bh.as_ref()));
+    let mut data = Vec::new();
+    for name in &["from", "to", "subject"] {
+        let (n, v) = fields.iter().rev().find(|(n, _)| n.eq_ignore_ascii_case(name.as_bytes())).unwrap();
+        data.extend(canonicalize_header(Relaxed, n, v));
+    }
+    data.extend(unsigned("ARC-Message-Signature", &ams));
+    ams += &sign(key, &data);
+
+    let mut seal = format!(" i={}; a=ed25519-sha256; cv={}; d=example.org; s=arc; t=1000; b=", instance, cv);
+    chain.push(("ARC-Authentication-Results", aar.clone()));
+    chain.push(("ARC-Message-Signature", ams.clone()));
+    let mut data = Vec::new();
+    for (name, value) in chain.iter() {
+        data.extend(canonicalize_header(Relaxed, name.as_bytes(), value.as_bytes()));
+    }
+    data.extend(unsigned("ARC-Seal", &seal));
+    seal += &sign(key, &data);
+    chain.push(("ARC-Seal", seal.clone()));
+
+    let mut out = format!("ARC-Seal:{}\r\nARC-Message-Signature:{}\r\nARC-Authentication-Results:{}\r\n", seal, ams, aar).into_bytes();
+    out.extend_from_slice(message);
+    out
+}
+
+fn chain(key: &Ed25519KeyPair, cvs: &[&str]) -> Vec<u8> {
+    let mut headers = Vec::new();
+    let mut message = MESSAGE.to_vec();
+    for cv in cvs {
+        message = seal(&message, key, &mut headers, cv);
+    }
+    message
+}
+
+fn check(message: &[u8], resolver: &Zone) -> (AuthResult, Option<&'static str>) {
+    let res = validate(message, resolver, 1500);
+    (res.result, res.reason)
+}
+
+fn replace(message: &[u8], from: &str, to: &str) -> Vec<u8> {
+    String::from_utf8(message.to_vec()).unwrap().replacen(from, to, 1).into_bytes()
+}
+
+#[test]
+fn parse() {
+    let (_, seal) = arc_seal(b" i=2; a=rsa-sha256; cv=Pass; d=example.org; s=arc; t=12345; b=AAEC\r\n").unwrap();
+    assert_eq!(seal.instance, 2);
+    assert_eq!(seal.algorithm, SigningAlgorithm::RsaSha256);
+    assert_eq!(seal.chain_validation, ChainValidation::Pass);
+    assert_eq!(seal.signature, [0, 1, 2]);
+    assert_eq!(seal.timestamp, Some(12345));
+
+    let (_, ams) = arc_message_signature(b" i=1; a=rsa-sha256; d=example.org; s=arc; h=subject; bh=AAEC; b=AAEC").unwrap();
+    assert_eq!(ams.instance, 1);
+    assert_eq!(ams.signature.signed_headers, ["subject"]);
+    assert_eq!(ams.signature.identity.local_part, None);
+
+    assert!(arc_seal(b"i=1; a=rsa-sha256; cv=none; d=example.org; s=arc; h=from; b=AAEC").is_err());
+    assert!(arc_seal(b"i=1; a=rsa-sha256; cv=maybe; d=example.org; s=arc; b=AAEC").is_err());
+    assert!(arc_seal(b"i=0; a=rsa-sha256; cv=none; d=example.org; s=arc; b=AAEC").is_err());
+    assert!(arc_seal(b"i=51; a=rsa-sha256; cv=pass; d=example.org; s=arc; b=AAEC").is_err());
+    assert!(arc_authentication_results(b"mx.example.org; spf=pass").is_err());
+}
+
+#[test]
+fn sets() {
+    let (key, _) = key();
+    let message = chain(&key, &["none", "pass"]);
+
+    let sets = arc_sets(&message).unwrap();
+    assert_eq!(sets.iter().map(|s| s.instance).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(sets[1].authentication_results.results.authserv_id, "mx2.example.org");
+    assert_eq!(arc_sets(MESSAGE).unwrap(), []);
+
+    let missing = replace(&message, "ARC-Message-Signature: i=2", "X-Removed: i=2");
+    assert_eq!(arc_sets(&missing), Err("Incomplete ARC set"));
+    let gap = replace(&message, "ARC-Seal: i=1", "ARC-Seal: i=3");
+    assert_eq!(arc_sets(&gap), Err("Incomplete ARC set"));
+    let duplicate = replace(&message, "ARC-Authentication-Results: i=1", "ARC-Authentication-Results: i=2");
+    assert_eq!(arc_sets(&duplicate), Err("Duplicate ARC header"));
+}
+
+#[test]
+fn pass() {
+    let (key, resolver) = key();
+
+    assert_eq!(check(MESSAGE, &resolver), (AuthResult::None, None));
+    assert_eq!(check(&chain(&key, &["none"]), &resolver), (AuthResult::Pass, None));
+
+    let res = validate(&chain(&key, &["none", "pass", "pass"]), &resolver, 1500);
+    assert_eq!(res.result, AuthResult::Pass);
+    assert_eq!(res.sets.len(), 3);
+    assert_eq!(res.resinfo().method, "arc");
+}
+
+#[test]
+fn chain_validation() {
+    let (key, resolver) = key();
+
+    assert_eq!(check(&chain(&key, &["none", "fail"]), &resolver), (AuthResult::Fail, Some("Chain already failed")));
+    assert_eq!(check(&chain(&key, &["pass"]), &resolver), (AuthResult::Fail, Some("Invalid cv=")));
+    assert_eq!(check(&chain(&key, &["none", "none"]), &resolver), (AuthResult::Fail, Some("Invalid cv=")));
+    assert_eq!(check(&chain(&key, &["none", "fail", "pass"]), &resolver), (AuthResult::Fail, Some("Invalid cv=")));
+}
+
+#[test]
+fn tampered() {
+    let (key, resolver) = key();
+    let message = chain(&key, &["none", "pass"]);
+
+    let body = replace(&message, "hungry", "angry");
+    assert_eq!(check(&body, &resolver), (AuthResult::Fail, Some("Body hash mismatch")));
+
+    let results = replace(&message, "mx1.example.org", "mx9.example.org");
+    assert_eq!(check(&results, &resolver), (AuthResult::Fail, Some("Signature mismatch")));
+
+    let missing = replace(&message, "ARC-Seal: i=1", "X-Removed: i=1");
+    assert_eq!(check(&missing, &resolver), (AuthResult::Fail, Some("Incomplete ARC set")));
+
+    let (other, _) = self::key();
+    assert_eq!(check(&message, &Zone::default()), (AuthResult::Fail, Some("No key record")));
+    assert_eq!(check(&chain(&other, &["none"]), &resolver).0, AuthResult::Fail);
+
+    let temp = replace(&message, "s=arc; t=1000", "s=temp; t=1000");
+    assert_eq!(check(&temp, &resolver).0, AuthResult::TempError);
+}
+
+#[test]
+fn expired() {
+    let (key, resolver) = key();
+    let message = chain(&key, &["none", "pass"]);
+
+    let res = validate(&message, &resolver, 2001);
+    assert_eq!((res.result, res.reason), (AuthResult::Fail, Some("Signature expired")));
+}
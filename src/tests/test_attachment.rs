@@ -0,0 +1,109 @@
+use crate::attachment::*;
+use crate::mime::parse;
+
+const MESSAGE: &[u8] = b"Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+--outer\r\n\
+Content-Type: multipart/alternative; boundary=inner\r\n\r\n\
+--inner\r\n\
+Content-Type: text/plain\r\n\r\nbody\r\n\
+--inner\r\n\
+Content-Type: text/html\r\n\r\n<p>body</p>\r\n\
+--inner--\r\n\
+--outer\r\n\
+Content-Type: text/plain; name=notes.txt\r\n\r\nnotes\r\n\
+--outer\r\n\
+Content-Type: application/octet-stream; name=\"ignored.bin\"\r\n\
+Content-Disposition: attachment;\r\n filename*0*=iso-8859-1''%E9t%E9;\r\n filename*1=\".bin\"\r\n\
+Content-Transfer-Encoding: base64\r\n\r\nAAEC\r\n\
+--outer\r\n\
+Content-Type: image/png\r\n\
+Content-Disposition: inline; filename=\"=?utf-8?Q?caf=C3=A9?= =?utf-8?Q?.png?=\"\r\n\r\npng\r\n\
+--outer\r\n\
+Content-Type: text/csv\r\nContent-Disposition: attachment\r\n\r\na,b\r\n\
+--outer\r\n\
+Content-Type: application/x-evil; name=\"..\\\\..\\\\AUX.exe\"\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\r\nbad=ZZ\r\n\
+--outer--\r\n";
+
+#[test]
+fn extract() {
+    let root = parse(MESSAGE);
+    let found: Vec<_> = attachments(&root, MESSAGE).collect();
+
+    let names: Vec<_> = found.iter().map(|a| (a.raw_filename.as_deref(), a.filename.as_deref())).collect();
+    assert_eq!(names, [(Some("notes.txt"), Some("notes.txt")),
+                       (Some("été.bin"), Some("été.bin")),
+                       (Some("café.png"), Some("café.png")),
+                       (None, None),
+                       (Some("..\\..\\AUX.exe"), Some("_AUX.exe"))]);
+
+    assert_eq!(found[0].data, b"notes");
+    assert_eq!(found[1].data, [0, 1, 2]);
+    assert_eq!(found[2].content_type.essence(), "image/png");
+    assert_eq!(found[3].content_type.essence(), "text/csv");
+    assert_eq!(found[4].data, b"bad=ZZ");
+    assert_eq!(found[4].transfer_error, Some("Invalid quoted-printable escape"));
+}
+
+#[test]
+fn forwarded() {
+    let message = b"Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+--outer\r\n\r\nSee the forwarded message.\r\n\
+--outer\r\n\
+Content-Type: message/rfc822\r\n\r\n\
+Subject: Report\r\n\
+Content-Type: multipart/mixed; boundary=inner\r\n\r\n\
+--inner\r\n\r\nThe report.\r\n\
+--inner\r\n\
+Content-Type: application/pdf; name=report.pdf\r\n\r\n%PDF\r\n\
+--inner--\r\n\
+--outer\r\n\
+Content-Type: text/plain; name=after.txt\r\n\r\nafter\r\n\
+--outer--\r\n";
+
+    let root = parse(message);
+    let found: Vec<_> = attachments(&root, message).collect();
+    let types: Vec<_> = found.iter().map(|a| (a.content_type.essence(), a.filename.as_deref())).collect();
+    assert_eq!(types, [("message/rfc822".to_string(), None),
+                       ("application/pdf".to_string(), Some("report.pdf")),
+                       ("text/plain".to_string(), Some("after.txt"))]);
+    assert_eq!(found[1].data, b"%PDF");
+}
+
+#[test]
+fn single_part() {
+    let message = b"Content-Type: application/pdf\r\n\r\n%PDF";
+    let root = parse(message);
+    let found: Vec<_> = attachments(&root, message).collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].filename, None);
+
+    let message = b"Subject: text only\r\n\r\nhello";
+    let root = parse(message);
+    assert_eq!(attachments(&root, message).count(), 0);
+}
+
+#[test]
+fn sanitize() {
+    assert_eq!(sanitize_filename("a/b\\c.txt"), Some("c.txt".into()));
+    assert_eq!(sanitize_filename("tab\there\u{7}.txt"), Some("tab_here_.txt".into()));
+    assert_eq!(sanitize_filename("..."), None);
+    assert_eq!(sanitize_filename("dir/"), None);
+    assert_eq!(sanitize_filename(" trailing dot. "), Some("trailing dot".into()));
+    assert_eq!(sanitize_filename("com1"), Some("_com1".into()));
+    assert_eq!(sanitize_filename("Com9.tar.gz"), Some("_Com9.tar.gz".into()));
+    assert_eq!(sanitize_filename("command.com"), Some("command.com".into()));
+    assert_eq!(sanitize_filename("lpt10"), Some("lpt10".into()));
+
+    let long = format!("{}.pdf", "é".repeat(200));
+    let out = sanitize_filename(&long).unwrap();
+    assert!(out.len() <= MAX_FILENAME_LEN);
+    assert!(out.ends_with("é.pdf"));
+}
+
+#[test]
+fn unsplit_multipart() {
+    let message = b"Content-Type: multipart/mixed\r\n\r\n--b\r\n\r\nbody\r\n--b--\r\n";
+    let root = parse(message);
+    assert_eq!(attachments(&root, message).count(), 0);
+}
@@ -0,0 +1,171 @@
+use ring::rand::SystemRandom;
+use ring::signature::Ed25519KeyPair;
+
+use crate::dkim::*;
+use crate::rfc6376::Canonicalization::{self, Relaxed, Simple};
+use crate::rfc8601::AuthResult;
+use crate::types::Domain;
+
+use super::Zone;
+
+const RSA_KEY: &str = concat!(
+    "MIIEpAIBAAKCAQEAtkp3SI2AKW26fC8ubeZneQN5jFy1o7ybmaTO+qhWe4NwiODo9EB6LgYjNtD9",
+    "rTxMJBEM6yPVUo5MVwAbx/qfSReuAXWBeDzJOnMHE3nluhY9DYgZ/AOGBOqE55nSnzoPeuWEuQZb",
+    "l4L+Pl0ASrqF2wGRlK/Lr5q2ewaKtJ5BhHbZgdhrbf9SXbNUJeJPAfbuh4pHzLsihKiQVW/+4nrN",
+    "bFmW6Sp2DYHLnuICjrLwZt1Xy5CbTejRk0SKCanCo/0Zux/9wwdwKJUHklFbokbX/gwhYEWEcyv/",
+    "PSHWoYVkiw8v4P5WkG5sDe4S+BmwxZaI+6mPuQJJB4Fp0Y/ytiN3VwIDAQABAoIBAFUe551a6cYu",
+    "pV54v6L07EtPSpI29mBrYXM4yKe2NI//W+ymrVDkzMix9/6QT2x11A4hYhloxaODq/USKWbTyk5c",
+    "vtWSk67cH5DqBptbF+Exojev3oZV9N5VzlLBet78Qx89jqDBbcKNmf/v+INsnrWaAWtfPWsWAPDo",
+    "qJtXrLtX4/S+0c9fZwrEOgFVvvLtAACFPwIXjJgHdUTkoeJa6OhTJwkE3T/rvxgUR60p06plStIF",
+    "k5ne9a84ReAx1Xs8/do5C+NYRoLytVhvPUTLkztLnzzxC0MjlrXAeE87bocssh6Ly/304md3mq3+",
+    "tuLtlOYeXDm4Ur1KwsYXMuiukcUCgYEA5Uze4NYrS+Kg/ZY3NNVap/iexpLXoobM1vZS9yhTQt6t",
+    "210Gjs/WV/t4R5hDApXCRChUyv8SEKvfkGc5TOEPV4w7fzKF8VY/sAwVR2dbLy5pK8CTDGvweSdM",
+    "csTp9JWNQjN9l+SJ0TW1xpYiWRQfF06fUnf86n6WFOsHBd+60qUCgYEAy4RPKx4y4FZt4tJ52Vdt",
+    "xwywyuA3TnSnZpGNj/141EERqlJf0meXtZvY/cKhdiYferAAACVCLSuweK/7I5NeLe9orrH/zxOj",
+    "wHl+WxezGvpeLfBNmAUZwRMiXPeO6Yox+W3TpAaYWQGriKMnZe8bVbi1HF1yKjeUN/7QwhvB7UsC",
+    "gYAPN1uu9ednoMFoMpi1Q9dBQElhCguPOHzNYwk8Z4um61oELZcq0PX7Xq+rqJoByOhcV4TQ974+",
+    "Jvmt/nuZ83FuMNsd5L9otX2CdDrRQzWU2vKVubDIaT1K/OWg1BpmZao7E2mpM7xFitfmNr5OxPwP",
+    "MuN1uIqrEP1qo/ISXLdsFQKBgQCu4QuSdomrQjl8jfYMb9cyEtKcEh0UOqNaPLqTP3CjmyJN5CjY",
+    "yRGRCdFlBOjveITWTQRDG8EIxaEHo9i+0jQ8bpvaebPiQfMVQpD0P6I1DWz+Np7GgaOGyCVgE43n",
+    "WkvlFJ2nNc+VtBys9d219sfbWjOuoxGl+0pO75R9J5L1jwKBgQDAIWK51plMEYXxrmxMTzkA/6KJ",
+    "6sr+7lQCu9gojkuRNjxiBGmjDL4Mhyu0rXFqJgBGgoC9j00PZGdbIFGM+BydBc6jwbbeBjaDp0aE",
+    "DgVA7g0avgcKCGKEbAr1HXWrvnCjQ7DxQFNoJJTnE7X5tj40pQNjEivkz7TMRDj9+mjvkg==",
+);
+
+const MESSAGE: &[u8] = b"From: Joe SixPack <joe@football.example.com>\r
+To: Suzie Q <suzie@shopping.example.net>\r
+Subject: Is dinner ready?\r
+Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r
+\r
+Hi.\r
+\r
+We lost the game.  Are you hungry yet?\r
+\r
+Joe.\r
+\r
+\r
+";
+
+fn resolver(key: &SigningKey, record: &str) -> Zone {
+    let mut zone = Zone::default();
+    zone.txt("test._domainkey.example.com", &format!("{}p={}", record, key.public_key()));
+    zone
+}
+
+fn options(header: Canonicalization, body: Canonicalization) -> SignOptions {
+    SignOptions {
+        domain: Domain::from_smtp(b"example.com").unwrap(),
+        selector: "test".into(),
+        headers: vec!["from".into(), "to".into(), "subject".into(), "date".into(), "from".into()],
+        header_canonicalization: header,
+        body_canonicalization: body,
+        timestamp: Some(1000),
+        expiration: Some(2000),
+    }
+}
+
+fn signed(key: &SigningKey, header: Canonicalization, body: Canonicalization) -> Vec<u8> {
+    let line = sign(MESSAGE, key, &options(header, body)).unwrap();
+    assert!(line.split("\r\n").all(|l| l.len() <= 78));
+    let mut out = line.into_bytes();
+    out.extend_from_slice(MESSAGE);
+    out
+}
+
+fn rsa_key() -> SigningKey {
+    SigningKey::rsa_from_der(&base64::decode(RSA_KEY).unwrap()).unwrap()
+}
+
+fn ed25519_key() -> SigningKey {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    SigningKey::ed25519_from_pkcs8(pkcs8.as_ref()).unwrap()
+}
+
+fn check(message: &[u8], resolver: &Zone, now: u64) -> (AuthResult, Option<&'static str>) {
+    let results = verify(message, resolver, now);
+    assert_eq!(results.len(), 1);
+    (results[0].result.clone(), results[0].reason)
+}
+
+#[test]
+fn canonicalization() {
+    // Example from RFC 6376 section 3.4.5
+    assert_eq!(canonicalize_header(Relaxed, b"A", b" X"), b"a:X\r\n");
+    assert_eq!(canonicalize_header(Relaxed, b"B ", b" Y\t\r\n\tZ  "), b"b:Y Z\r\n");
+    assert_eq!(canonicalize_header(Simple, b"B ", b" Y\t\r\n\tZ  "), b"B : Y\t\r\n\tZ  \r\n");
+
+    let body = b" C \r\nD \t E\r\n\r\n\r\n";
+    assert_eq!(canonicalize_body(Relaxed, body), b" C\r\nD E\r\n");
+    assert_eq!(canonicalize_body(Simple, body), b" C \r\nD \t E\r\n");
+
+    assert_eq!(canonicalize_body(Simple, b""), b"\r\n");
+    assert_eq!(canonicalize_body(Relaxed, b"\r\n \r\n"), b"");
+    assert_eq!(canonicalize_body(Relaxed, b"no newline"), b"no newline\r\n");
+}
+
+#[test]
+fn roundtrip() {
+    for (key, record) in &[(rsa_key(), "v=DKIM1; "), (ed25519_key(), "v=DKIM1; k=ed25519; ")] {
+        let resolver = resolver(key, record);
+        for &(header, body) in &[(Simple, Simple), (Relaxed, Relaxed), (Relaxed, Simple)] {
+            let message = signed(key, header, body);
+            assert_eq!(check(&message, &resolver, 1500), (AuthResult::Pass, None));
+        }
+    }
+
+    let key = ed25519_key();
+    let results = verify(&signed(&key, Relaxed, Relaxed), &resolver(&key, "k=ed25519; t=y; "), 1500);
+    assert!(results[0].testing);
+    let resinfo = results[0].resinfo();
+    assert_eq!((&resinfo.method[..], &resinfo.result), ("dkim", &AuthResult::Pass));
+    let props: Vec<_> = resinfo.properties.iter().map(|p| format!("{}.{}={}", p.ptype, p.property, p.value)).collect();
+    assert_eq!(props, ["header.d=example.com", "header.i=@example.com", "header.s=test"]);
+}
+
+#[test]
+fn tampered() {
+    let key = rsa_key();
+    let resolver = resolver(&key, "");
+
+    let message = signed(&key, Relaxed, Relaxed);
+    let relaxed_ok = String::from_utf8(message.clone()).unwrap()
+        .replace("Subject: Is dinner ready?", "subject:   Is dinner\r\n ready?")
+        .replace("We lost the game.  Are", "We lost the game. Are  ");
+    assert_eq!(check(relaxed_ok.as_bytes(), &resolver, 1500).0, AuthResult::Pass);
+
+    let message = String::from_utf8(signed(&key, Simple, Simple)).unwrap();
+    let header = message.replace("Subject: Is dinner ready?", "Subject: Is dinner  ready?");
+    assert_eq!(check(header.as_bytes(), &resolver, 1500), (AuthResult::Fail, Some("Signature mismatch")));
+    let body = message.replace("hungry", "Hungry");
+    assert_eq!(check(body.as_bytes(), &resolver, 1500), (AuthResult::Fail, Some("Body hash mismatch")));
+
+    // The second From was signed as missing.
+    let added = message.replace("From: Joe", "From: Evil <evil@example.org>\r\nFrom: Joe");
+    assert_eq!(check(added.as_bytes(), &resolver, 1500).0, AuthResult::Fail);
+}
+
+#[test]
+fn errors() {
+    let key = rsa_key();
+    let message = signed(&key, Relaxed, Relaxed);
+
+    assert_eq!(check(&message, &resolver(&key, ""), 2001), (AuthResult::PermError, Some("Signature expired")));
+    assert_eq!(check(&message, &Zone::default(), 1500), (AuthResult::PermError, Some("No key record")));
+    assert_eq!(check(&message, &resolver(&key, "k=ed25519; "), 1500).0, AuthResult::PermError);
+    assert_eq!(check(&message, &resolver(&key, "h=sha1; "), 1500).0, AuthResult::PermError);
+
+    let mut revoked = Zone::default();
+    revoked.txt("test._domainkey.example.com", "v=DKIM1; p=");
+    assert_eq!(check(&message, &revoked, 1500), (AuthResult::PermError, Some("Key revoked")));
+
+    let temp = String::from_utf8(message).unwrap().replace("s=test;", "s=temp;");
+    assert_eq!(check(temp.as_bytes(), &resolver(&key, ""), 1500).0, AuthResult::TempError);
+
+    let results = verify(b"DKIM-Signature: v=1; a=rsa-sha256\r\nFrom: a@example.com\r\n\r\n", &resolver(&key, ""), 0);
+    assert_eq!(results[0].signature, None);
+    assert_eq!(results[0].result, AuthResult::PermError);
+
+    let mut opts = options(Simple, Simple);
+    opts.headers = vec!["to".into()];
+    assert!(sign(MESSAGE, &key, &opts).is_err());
+}
@@ -0,0 +1,119 @@
+use crate::behaviour::Intl;
+use crate::dmarc::*;
+use crate::rfc5322::from;
+use crate::rfc7489::{Alignment, Policy};
+use crate::rfc8601::AuthResult;
+use crate::types::Domain;
+
+use super::Zone;
+
+fn zone(records: &[(&str, &str)]) -> Zone {
+    let mut zone = Zone::default();
+    for (name, record) in records {
+        zone.txt(&format!("_dmarc.{}", name), record);
+    }
+    zone
+}
+
+fn domain(d: &str) -> Domain {
+    Domain::from_smtp(d.as_bytes()).unwrap()
+}
+
+fn id(d: &str, result: AuthResult) -> Identifier {
+    Identifier { domain: domain(d), result }
+}
+
+#[test]
+fn organizational() {
+    assert_eq!(organizational_domain("example.com"), "example.com");
+    assert_eq!(organizational_domain("Mail.Example.COM."), "example.com");
+    assert_eq!(organizational_domain("com"), "com");
+    assert_eq!(organizational_domain("a.b.c.example.invalidtld"), "example.invalidtld");
+    // Wildcard and exception rules.
+    assert_eq!(organizational_domain("a.b.example.ck"), "b.example.ck");
+    assert_eq!(organizational_domain("a.www.ck"), "www.ck");
+    // Internationalized domains.
+    assert_eq!(organizational_domain("mail.example.公司.cn"), "example.xn--55qx5d.cn");
+    assert_eq!(organizational_domain("mail.example.xn--55qx5d.cn"), "example.xn--55qx5d.cn");
+
+    assert!(is_aligned("mail.example.com", "news.example.com", Alignment::Relaxed));
+    assert!(!is_aligned("mail.example.com", "example.com", Alignment::Strict));
+    assert!(is_aligned("EXAMPLE.com", "example.com", Alignment::Strict));
+    assert!(!is_aligned("example.co.uk", "other.co.uk", Alignment::Relaxed));
+}
+
+#[test]
+fn author() {
+    let parse = |header: &[u8]| author_domain(&from::<Intl>(header).unwrap().1);
+
+    assert_eq!(parse(b"Joe <joe@example.com>\r\n"), Ok(domain("example.com")));
+    assert_eq!(parse(b"joe@example.com, jane@EXAMPLE.com\r\n"), Ok(domain("example.com")));
+    assert_eq!(parse(b"joe@example.com, jane@example.org\r\n"), Err("Multiple author domains"));
+    assert_eq!(parse(b"joe@[192.0.2.1]\r\n"), Err("Author address has no domain"));
+    assert_eq!(parse(b"undisclosed:;\r\n"), Err("No author address"));
+}
+
+#[test]
+fn alignment() {
+    let zone = zone(&[("example.com", "v=DMARC1; p=reject; aspf=s")]);
+    let from = domain("news.example.com");
+
+    let res = evaluate(&zone, &from, Some(&id("bounce.example.com", AuthResult::Pass)), &[], 0);
+    assert_eq!((res.result, res.spf_aligned, res.disposition), (AuthResult::Fail, false, Policy::Reject));
+    assert_eq!(res.policy_domain.as_deref(), Some("example.com"));
+
+    let res = evaluate(&zone, &from, Some(&id("news.example.com", AuthResult::Pass)), &[], 0);
+    assert_eq!((res.result, res.spf_aligned, res.disposition), (AuthResult::Pass, true, Policy::None));
+
+    let dkim = [id("other.example", AuthResult::Pass), id("example.com", AuthResult::Fail), id("mail.example.com", AuthResult::Pass)];
+    let res = evaluate(&zone, &from, Some(&id("news.example.com", AuthResult::Fail)), &dkim, 0);
+    assert_eq!((res.result.clone(), res.spf_aligned, res.dkim_aligned), (AuthResult::Pass, false, true));
+    assert_eq!(res.resinfo().properties[0].value, "news.example.com");
+}
+
+#[test]
+fn disposition() {
+    let zone = zone(&[
+        ("example.com", "v=DMARC1; p=reject; sp=quarantine; pct=50"),
+        ("example.org", "v=DMARC1; p=quarantine"),
+    ]);
+    let check = |from: &str, sample| evaluate(&zone, &domain(from), None, &[], sample);
+
+    assert_eq!(check("example.com", 10).disposition, Policy::Reject);
+    assert_eq!(check("example.com", 50).disposition, Policy::Quarantine);
+    assert_eq!(check("mail.example.com", 10).disposition, Policy::Quarantine);
+    assert_eq!(check("mail.example.com", 99).disposition, Policy::None);
+    assert_eq!(check("mail.example.org", 99).disposition, Policy::Quarantine);
+    assert_eq!(check("mail.example.org", 99).policy, Some(Policy::Quarantine));
+}
+
+#[test]
+fn discovery() {
+    let zone = zone(&[
+        ("example.com", "v=DMARC1; p=reject"),
+        ("mail.example.com", "v=DMARC1; p=none"),
+        ("multiple.example.com", "v=DMARC1; p=none"),
+        ("multiple.example.com", "v=DMARC1; p=reject"),
+        ("broken.example.com", "v=DMARC1; p=block"),
+        ("other.example.com", "some other record"),
+        ("other.example.com", " v=DMARC1 ; p=quarantine"),
+    ]);
+    let check = |from: &str| {
+        let res = evaluate(&zone, &domain(from), None, &[], 0);
+        (res.result, res.policy, res.policy_domain)
+    };
+
+    assert_eq!(check("mail.example.com"), (AuthResult::Fail, Some(Policy::None), Some("mail.example.com".into())));
+    assert_eq!(check("x.mail.example.com"), (AuthResult::Fail, Some(Policy::Reject), Some("example.com".into())));
+    assert_eq!(check("other.example.com"), (AuthResult::Fail, Some(Policy::Quarantine), Some("other.example.com".into())));
+    // Invalid or multiple records stop discovery.
+    let reason = |from: &str| evaluate(&zone, &domain(from), None, &[], 0).reason;
+    assert_eq!(check("multiple.example.com").0, AuthResult::None);
+    assert_eq!(reason("multiple.example.com"), Some("Multiple DMARC records"));
+    assert_eq!(check("broken.example.com").0, AuthResult::None);
+    assert_eq!(reason("broken.example.com"), Some("Invalid DMARC record"));
+    assert_eq!(check("example.org"), (AuthResult::None, None, None));
+
+    let res = evaluate(&zone, &domain("temp.example.net"), None, &[], 0);
+    assert_eq!((res.result, res.reason), (AuthResult::TempError, Some("DNS lookup failed")));
+}
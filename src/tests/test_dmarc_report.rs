@@ -0,0 +1,201 @@
+use std::io::Write;
+use std::net::IpAddr;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::attachment::attachments;
+use crate::dmarc_report::*;
+use crate::mime::parse;
+use crate::rfc7489::{Alignment, Policy};
+use crate::rfc8601::AuthResult;
+
+const REPORT: &str = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<feedback xmlns="urn:ietf:params:xml:ns:dmarc-2.0">
+  <version>1.0</version>
+  <report_metadata>
+    <org_name>example.net</org_name>
+    <email>noreply-dmarc-support@example.net</email>
+    <extra_contact_info>https://example.net/dmarc</extra_contact_info>
+    <report_id>9391651994964116463</report_id>
+    <date_range>
+      <begin>1600000000</begin>
+      <end>1600086399</end>
+    </date_range>
+    <error>Report truncated</error>
+  </report_metadata>
+  <policy_published>
+    <domain>example.com</domain>
+    <adkim>s</adkim>
+    <aspf>r</aspf>
+    <p>quarantine</p>
+    <sp>none</sp>
+    <pct>50</pct>
+    <fo>1</fo>
+  </policy_published>
+  <record>
+    <row>
+      <source_ip>192.0.2.10</source_ip>
+      <count>12</count>
+      <policy_evaluated>
+        <disposition>none</disposition>
+        <dkim>pass</dkim>
+        <spf>fail</spf>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <envelope_to>example.org</envelope_to>
+      <envelope_from>bounce.example.com</envelope_from>
+      <header_from>example.com</header_from>
+    </identifiers>
+    <auth_results>
+      <dkim>
+        <domain>example.com</domain>
+        <selector>sel1</selector>
+        <result>pass</result>
+        <human_result>good signature</human_result>
+      </dkim>
+      <dkim>
+        <domain>esp.example</domain>
+        <result>fail</result>
+      </dkim>
+      <spf>
+        <domain>bounce.example.com</domain>
+        <scope>mfrom</scope>
+        <result>softfail</result>
+      </spf>
+    </auth_results>
+  </record>
+  <record>
+    <row>
+      <source_ip>2001:db8::1</source_ip>
+      <count>1</count>
+      <policy_evaluated>
+        <disposition>quarantine</disposition>
+        <dkim>fail</dkim>
+        <spf>fail</spf>
+        <reason><type>mailing_list</type><comment>list.example.org</comment></reason>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <header_from>example.com</header_from>
+    </identifiers>
+    <auth_results>
+      <spf>
+        <domain>list.example.org</domain>
+        <result>none</result>
+      </spf>
+    </auth_results>
+  </record>
+</feedback>
+"#;
+
+#[test]
+fn full() {
+    let report = parse_report(REPORT.as_bytes()).unwrap();
+
+    assert_eq!(report.version.as_deref(), Some("1.0"));
+    assert_eq!(report.metadata, ReportMetadata {
+        org_name: "example.net".into(),
+        email: "noreply-dmarc-support@example.net".into(),
+        extra_contact_info: Some("https://example.net/dmarc".into()),
+        report_id: "9391651994964116463".into(),
+        begin: 1600000000,
+        end: 1600086399,
+        errors: vec!["Report truncated".into()],
+    });
+    assert_eq!(report.policy_published, PolicyPublished {
+        domain: "example.com".into(),
+        dkim_alignment: Alignment::Strict,
+        spf_alignment: Alignment::Relaxed,
+        policy: Policy::Quarantine,
+        subdomain_policy: Some(Policy::None),
+        percent: 50,
+        failure_options: Some("1".into()),
+    });
+
+    let first = &report.records[0];
+    assert_eq!(first.source_ip, "192.0.2.10".parse::<IpAddr>().unwrap());
+    assert_eq!((first.count, first.disposition), (12, Policy::None));
+    assert_eq!((first.dkim.clone(), first.spf.clone()), (AuthResult::Pass, AuthResult::Fail));
+    assert_eq!(first.envelope_from.as_deref(), Some("bounce.example.com"));
+    assert_eq!(first.envelope_to.as_deref(), Some("example.org"));
+    assert_eq!(first.dkim_results, [
+        DKIMAuthResult { domain: "example.com".into(), selector: Some("sel1".into()), result: AuthResult::Pass, human_result: Some("good signature".into()) },
+        DKIMAuthResult { domain: "esp.example".into(), selector: None, result: AuthResult::Fail, human_result: None },
+    ]);
+    assert_eq!(first.spf_results, [
+        SPFAuthResult { domain: "bounce.example.com".into(), scope: Some("mfrom".into()), result: AuthResult::SoftFail },
+    ]);
+
+    let second = &report.records[1];
+    assert_eq!(second.source_ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+    assert_eq!(second.disposition, Policy::Quarantine);
+    assert_eq!(second.reasons, [PolicyOverride { kind: "mailing_list".into(), comment: Some("list.example.org".into()) }]);
+    assert_eq!(second.envelope_from, None);
+    assert!(second.dkim_results.is_empty());
+}
+
+#[test]
+fn compressed() {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(REPORT.as_bytes()).unwrap();
+    let gz = gz.finish().unwrap();
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("README.txt", options).unwrap();
+    zip.write_all(b"not a report").unwrap();
+    zip.start_file("example.net!example.com!1600000000!1600086399.xml", options).unwrap();
+    zip.write_all(REPORT.as_bytes()).unwrap();
+    let zip = zip.finish().unwrap().into_inner();
+
+    let expected = parse_report(REPORT.as_bytes()).unwrap();
+    assert_eq!(parse_report_data(REPORT.as_bytes()).unwrap(), expected);
+    assert_eq!(parse_report_data(&gz).unwrap(), expected);
+    assert_eq!(parse_report_data(&zip).unwrap(), expected);
+
+    assert_eq!(parse_report_data(&gz[..gz.len() / 2]), Err("Invalid compressed report"));
+    assert_eq!(parse_report_data(b"PK\x03\x04garbage"), Err("Invalid compressed report"));
+}
+
+#[test]
+fn from_message() {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(REPORT.as_bytes()).unwrap();
+    let gz = gz.finish().unwrap();
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    zip.start_file("report.xml", zip::write::SimpleFileOptions::default()).unwrap();
+    zip.write_all(REPORT.as_bytes()).unwrap();
+    let zip = zip.finish().unwrap().into_inner();
+
+    let part = |headers: &str, data: &[u8]| {
+        format!("--b\r\n{}Content-Transfer-Encoding: base64\r\n\r\n{}\r\n", headers, base64::encode(data))
+    };
+    let message = [
+        "Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\n\r\nReports attached.\r\n".to_string(),
+        part("Content-Type: application/gzip; name=report.xml.gz\r\n", &gz),
+        part("Content-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=report.zip\r\n", &zip),
+        part("Content-Type: image/png; name=logo.png\r\n", b"png"),
+        part("Content-Type: application/zip\r\n", b"PK\x03\x04garbage"),
+        "--b--\r\n".to_string(),
+    ].concat().into_bytes();
+
+    let root = parse(&message);
+    let expected = parse_report(REPORT.as_bytes()).unwrap();
+    assert_eq!(message_reports(&root, &message), [Ok(expected.clone()), Ok(expected), Err("Invalid compressed report")]);
+
+    let logo = attachments(&root, &message).find(|a| a.content_type.essence() == "image/png").unwrap();
+    assert_eq!(parse_report_attachment(&logo), Err("Not a report attachment"));
+}
+
+#[test]
+fn invalid() {
+    assert_eq!(parse_report(b"<feedback>"), Err("Invalid XML"));
+    assert_eq!(parse_report(b"<html></html>"), Err("Not an aggregate report"));
+    assert_eq!(parse_report(b"<feedback></feedback>"), Err("Missing report_metadata"));
+    assert_eq!(parse_report(&REPORT.replace("<p>quarantine</p>", "<p>block</p>").into_bytes()), Err("Invalid published policy"));
+    assert_eq!(parse_report(&REPORT.replace("192.0.2.10", "192.0.2").into_bytes()), Err("Invalid source_ip"));
+    assert_eq!(parse_report(&REPORT.replace("<count>12</count>", "").into_bytes()), Err("Invalid count"));
+}
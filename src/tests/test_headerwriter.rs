@@ -0,0 +1,174 @@
+use crate::behaviour::{Intl, Legacy};
+use crate::headerwriter::*;
+use crate::headersection::header_section;
+use crate::rfc2047::decode_embedded;
+use crate::rfc5322::{self, Address, Group, Mailbox};
+use crate::types;
+
+fn mbox(dname: Option<&str>, address: &str) -> Mailbox {
+    Mailbox::new(dname.map(String::from), types::Mailbox::from_imf(address.as_bytes()).unwrap())
+}
+
+// Check line lengths and return the header value.
+fn check_lines(line: &str, max: usize) -> Vec<u8> {
+    for l in line.split("\r\n") {
+        assert!(l.len() <= max, "line too long: {:?}", l);
+    }
+    let section = format!("{}\r\n", line);
+    let (rem, parsed) = header_section(section.as_bytes()).unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed.len(), 1);
+    parsed[0].unwrap().1.to_vec()
+}
+
+fn roundtrip_list<P>(addresses: &[Address]) -> String
+    where P: WriterPolicy + rfc5322::UTF8Policy
+{
+    let line = address_list::<P>("To", addresses).unwrap();
+    let value = check_lines(&line, 78);
+    let (rem, parsed) = rfc5322::from::<P>(&value).unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed, addresses);
+    line
+}
+
+#[test]
+fn simple_mailbox() {
+    let addr = [Address::Mailbox(mbox(Some("John Doe"), "jdoe@example.org")),
+                Address::Mailbox(mbox(None, "mary@example.org"))];
+    assert_eq!(roundtrip_list::<Legacy>(&addr), "To: John Doe <jdoe@example.org>, mary@example.org\r\n");
+    assert_eq!(addr[0].to_string(), "John Doe <jdoe@example.org>");
+}
+
+#[test]
+fn quoted_dname() {
+    let addr = [Address::Mailbox(mbox(Some("Smith, \"Mary\""), "mary@example.org")),
+                Address::Mailbox(mbox(Some("two  spaces"), "\"quoted local\"@example.org"))];
+    assert_eq!(roundtrip_list::<Legacy>(&addr),
+               "To: \"Smith, \\\"Mary\\\"\" <mary@example.org>, \"two  spaces\"\r\n <\"quoted local\"@example.org>\r\n");
+}
+
+#[test]
+fn intl_dname() {
+    let addr = [Address::Mailbox(mbox(Some("Frédéric Chopin"), "chopin@example.org"))];
+    assert_eq!(roundtrip_list::<Legacy>(&addr), "To: =?utf-8?B?RnLDqWTDqXJpYyBDaG9waW4=?= <chopin@example.org>\r\n");
+    assert_eq!(roundtrip_list::<Intl>(&addr), "To: Frédéric Chopin <chopin@example.org>\r\n");
+
+    let addr = [Address::Mailbox(mbox(Some("=?not encoded?="), "a@example.org"))];
+    roundtrip_list::<Intl>(&addr);
+}
+
+#[test]
+fn intl_address() {
+    let addr = [Address::Mailbox(mbox(None, "jøhn@exämple.org"))];
+    assert!(address_list::<Legacy>("To", &addr).is_err());
+    roundtrip_list::<Intl>(&addr);
+
+    let addr = [Address::Mailbox(mbox(None, "john@exämple.org"))];
+    assert_eq!(address_list::<Legacy>("To", &addr).unwrap(), "To: john@xn--exmple-cua.org\r\n");
+}
+
+#[test]
+fn group() {
+    let members = (0..10).map(|i| mbox(Some(&format!("Member {}", i)), &format!("member{}@example.org", i))).collect();
+    let addr = [Address::Group(Group::new("A long group".into(), members)),
+                Address::Group(Group::new("Empty".into(), vec![]))];
+    let line = roundtrip_list::<Legacy>(&addr);
+    assert!(line.ends_with(" Empty:;\r\n"));
+}
+
+#[test]
+fn comments() {
+    let (_, parsed) = rfc5322::from::<crate::behaviour::Commented<Intl>>(b"a@example.org (some \\(nested\\) comment), G: b@example.org; (end)").unwrap();
+    let line = address_list::<Intl>("Cc", &parsed).unwrap();
+    assert_eq!(line, "Cc: a@example.org (some \\(nested\\) comment), G: b@example.org; (end)\r\n");
+    let (_, reparsed) = rfc5322::from::<crate::behaviour::Commented<Intl>>(&check_lines(&line, 78)).unwrap();
+    assert_eq!(reparsed, parsed);
+}
+
+#[test]
+fn long_phrase() {
+    let long = "x".repeat(1200);
+    let addr = [Address::Mailbox(mbox(Some(&long), "a@example.org")),
+                Address::Mailbox(mbox(Some(&format!("a {} b", long)), "b@example.org"))];
+
+    for line in &[address_list::<Legacy>("To", &addr).unwrap(), address_list::<Intl>("To", &addr).unwrap()] {
+        let value = check_lines(line, 998);
+        let (rem, parsed) = rfc5322::from::<Intl>(&value).unwrap();
+        assert_eq!(rem.len(), 0);
+        assert_eq!(parsed, addr);
+    }
+
+    let input = format!("a@example.org ({})", long);
+    let (_, parsed) = rfc5322::from::<crate::behaviour::Commented<Intl>>(input.as_bytes()).unwrap();
+    let line = address_list::<Intl>("Cc", &parsed).unwrap();
+    let value = check_lines(&line, 998);
+    let (_, reparsed) = rfc5322::from::<crate::behaviour::Commented<Intl>>(&value).unwrap();
+    match &reparsed[0] {
+        Address::Mailbox(m) => assert_eq!(decode_embedded(&m.comments[0]), long),
+        _ => unreachable!(),
+    }
+
+    let addr = Address::Mailbox(mbox(None, &format!("{}@example.org", long)));
+    assert_eq!(address::<Intl>("Sender", &addr), Err("Address too long"));
+}
+
+fn roundtrip_unstructured<P>(text: &str) -> String
+    where P: WriterPolicy + rfc5322::UTF8Policy
+{
+    let line = unstructured::<P>("Subject", text);
+    let value = check_lines(&line, 998);
+    let (rem, parsed) = rfc5322::unstructured::<P>(&value).unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(&parsed[1..], text);
+    line
+}
+
+#[test]
+fn unstructured_ascii() {
+    assert_eq!(roundtrip_unstructured::<Legacy>("Hello  world\t!"), "Subject: Hello  world\t!\r\n");
+    let long = "word ".repeat(40);
+    let line = roundtrip_unstructured::<Legacy>(long.trim_end());
+    assert!(line.split("\r\n").all(|l| l.len() <= 78));
+}
+
+#[test]
+fn unstructured_encoded() {
+    assert_eq!(roundtrip_unstructured::<Legacy>("Déjà vu, or déjà entendu?"),
+               "Subject: =?utf-8?B?RMOpasOg?= vu, or =?utf-8?B?ZMOpasOg?= entendu?\r\n");
+    roundtrip_unstructured::<Legacy>(&"日本語のテキスト".repeat(20));
+    roundtrip_unstructured::<Legacy>("an =?encoded?= lookalike");
+    roundtrip_unstructured::<Intl>("an =?encoded?= lookalike");
+    assert_eq!(roundtrip_unstructured::<Intl>("Déjà vu"), "Subject: Déjà vu\r\n");
+}
+
+#[test]
+fn unstructured_long_word() {
+    roundtrip_unstructured::<Legacy>(&"x".repeat(2000));
+}
+
+#[test]
+fn content_disposition_long_filename() {
+    use crate::rfc2231::{self, ContentDisposition};
+
+    let name = "Ein sehr langer Dateiname mit Umlauten äöü und noch mehr Text, Übersicht 2024.pdf";
+    let params = [("filename".to_string(), name.to_string()), ("size".to_string(), "1234".to_string())];
+
+    for fallback in &[false, true] {
+        let line = content_disposition::<Legacy>(&ContentDisposition::Attachment, &params, *fallback).unwrap();
+        assert!(line.is_ascii());
+        let value = check_lines(&line, if *fallback { 998 } else { 78 });
+        let (rem, (disp, parsed)) = rfc2231::content_disposition(&value).unwrap();
+        assert_eq!(rem.len(), 0);
+        assert_eq!(disp, ContentDisposition::Attachment);
+        assert_eq!(parsed, params);
+        assert_eq!(line.contains("=?utf-8?"), *fallback);
+    }
+
+    let line = content_disposition::<Intl>(&ContentDisposition::Inline, &params, true).unwrap();
+    assert!(line.contains("filename*0*=utf-8"));
+    assert!(line.contains(&format!("filename=\"{}\";", name)));
+
+    assert_eq!(content_disposition::<Legacy>(&ContentDisposition::Inline, &[("a b".into(), "c".into())], false),
+               Err("Invalid parameter name"));
+}
@@ -0,0 +1,313 @@
+use std::ops::Range;
+
+use crate::mime::*;
+
+fn text(message: &[u8], range: Range<usize>) -> &str {
+    std::str::from_utf8(&message[range]).unwrap()
+}
+
+const NESTED: &[u8] = b"From: joe@example.com\r\n\
+Content-Type: multipart/mixed;\r\n\tboundary=\"outer\"\r\n\
+\r\n\
+preamble\r\n\
+--outer\r\n\
+Content-Type: multipart/alternative; boundary=outer-inner\r\n\
+\r\n\
+--outer-inner\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+plain\r\n\
+--outer-inner\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>html</p>\r\n\
+--outer-inner--\r\n\
+inner epilogue\r\n\
+--outer\r\n\
+\r\n\
+no headers\r\n\
+--outer--\r\n\
+epilogue\r\n";
+
+#[test]
+fn nested() {
+    let root = parse(NESTED);
+    assert_eq!(root.content_type.essence(), "multipart/mixed");
+    assert_eq!(root.parameter("boundary"), Some("outer"));
+    assert_eq!(root.header("from"), Some(b" joe@example.com".as_ref()));
+
+    let outer = root.multipart.unwrap();
+    assert!(outer.closed);
+    assert_eq!(text(NESTED, outer.preamble), "preamble");
+    assert_eq!(text(NESTED, outer.epilogue), "epilogue\r\n");
+    assert_eq!(outer.parts.len(), 2);
+
+    let alternative = &outer.parts[0];
+    assert_eq!(alternative.content_type.essence(), "multipart/alternative");
+    let inner = alternative.multipart.as_ref().unwrap();
+    assert_eq!(text(NESTED, inner.preamble.clone()), "");
+    assert_eq!(text(NESTED, inner.epilogue.clone()), "inner epilogue");
+    assert_eq!(inner.parts.iter().map(|p| p.content_type.essence()).collect::<Vec<_>>(), ["text/plain", "text/html"]);
+    assert_eq!(inner.parts[0].parameter("charset"), Some("utf-8"));
+    assert_eq!(text(NESTED, inner.parts[0].body.clone()), "plain");
+    assert_eq!(text(NESTED, inner.parts[1].range.clone()), "Content-Type: text/html\r\n\r\n<p>html</p>");
+
+    let plain = &outer.parts[1];
+    assert!(plain.headers.is_empty());
+    assert_eq!((&plain.content_type.essence()[..], plain.content_type.charset()), ("text/plain", Some("us-ascii")));
+    assert_eq!(text(NESTED, plain.body.clone()), "no headers");
+}
+
+#[test]
+fn missing_close_delimiter() {
+    let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\none\r\n--b\r\n\r\ntwo\r\n";
+    let multipart = parse(message).multipart.unwrap();
+    assert!(!multipart.closed);
+    assert_eq!(multipart.parts.len(), 2);
+    assert_eq!(text(message, multipart.parts[1].body.clone()), "two\r\n");
+    assert_eq!(multipart.epilogue, message.len()..message.len());
+}
+
+#[test]
+fn lookalike_boundaries() {
+    // Lines that only start with the boundary are content.
+    let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                    --b\r\n\
+                    Content-Transfer-Encoding: base64\r\n\r\n\
+                    --bQUJD\r\n\
+                    --b-- trailing\r\n\
+                    x--b\r\n\
+                    --b  \t\r\n\
+                    \r\n\
+                    second\r\n\
+                    --b--";
+    let multipart = parse(message).multipart.unwrap();
+    assert!(multipart.closed);
+    assert_eq!(multipart.parts.len(), 2);
+    assert_eq!(text(message, multipart.parts[0].body.clone()), "--bQUJD\r\n--b-- trailing\r\nx--b");
+    assert_eq!(text(message, multipart.parts[1].body.clone()), "second");
+}
+
+#[test]
+fn bare_lf_delimiters() {
+    let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\n\r\none\n--b--\n";
+    let multipart = parse(message).multipart.unwrap();
+    assert!(multipart.closed);
+    assert_eq!(text(message, multipart.parts[0].body.clone()), "one");
+}
+
+#[test]
+fn headers_without_body() {
+    let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/html\r\n--b--\r\n";
+    let multipart = parse(message).multipart.unwrap();
+    let part = &multipart.parts[0];
+    assert_eq!(part.content_type.essence(), "text/html");
+    assert_eq!(part.body.len(), 0);
+}
+
+#[test]
+fn digest_default() {
+    let message = b"Content-Type: multipart/digest; boundary=b\r\n\r\n--b\r\n\r\nSubject: one\r\n\r\nbody\r\n--b--\r\n";
+    let multipart = parse(message).multipart.unwrap();
+    assert_eq!(multipart.parts[0].content_type.essence(), "message/rfc822");
+    assert_eq!(multipart.parts[0].content_type.parameters, []);
+}
+
+#[test]
+fn not_split() {
+    let message = b"Content-Type: multipart/mixed\r\n\r\n--b\r\nbody\r\n--b--\r\n";
+    let root = parse(message);
+    assert_eq!(root.multipart, None);
+    assert_eq!(root.body.start, 33);
+
+    let root = parse(b"Subject: no type\r\n\r\nbody");
+    assert_eq!(root.content_type.essence(), "text/plain");
+    assert_eq!(root.multipart, None);
+
+    let root = parse(b"Content-Type: multipart/mixed; boundary=b\r\n\r\nno delimiter");
+    let multipart = root.multipart.unwrap();
+    assert!(multipart.parts.is_empty() && !multipart.closed);
+    assert_eq!(multipart.preamble, root.body);
+}
+
+#[test]
+fn max_depth() {
+    let mut message = Vec::new();
+    for i in 0..MAX_DEPTH + 5 {
+        message.extend_from_slice(format!("Content-Type: multipart/mixed; boundary=b{0}\r\n\r\n--b{0}\r\n", i).as_bytes());
+    }
+
+    let mut depth = 0;
+    let root = parse(&message);
+    let mut entity = &root;
+    while let Some(multipart) = &entity.multipart {
+        entity = &multipart.parts[0];
+        depth += 1;
+    }
+    assert_eq!(depth, MAX_DEPTH);
+}
+
+#[test]
+fn decode_body() {
+    let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                    --b\r\nContent-Transfer-Encoding: Base64\r\n\r\nSGVs\r\nbG8=\r\n\
+                    --b\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\ncaf=C3=A9=\r\n!\r\n\
+                    --b\r\nContent-Transfer-Encoding: unknown junk\r\n\r\n=41\r\n\
+                    --b--\r\n";
+    let multipart = parse(message).multipart.unwrap();
+    let decoded: Vec<_> = multipart.parts.iter().map(|p| p.decode_body(message)).collect();
+
+    assert_eq!(decoded, [(b"Hello".to_vec(), None), ("café!".as_bytes().to_vec(), None), (b"=41".to_vec(), None)]);
+}
+
+#[test]
+fn decode_text() {
+    use encoding::all::WINDOWS_1252;
+
+    let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                    --b\r\nContent-Type: text/plain; charset=ISO-8859-1\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\ncaf=E9\r\n\
+                    --b\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nbad \xff\xfe utf-8\r\n\
+                    --b\r\nContent-Type: text/html; charset=x-made-up\r\n\r\n\xe0 la\r\n\
+                    --b\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n//5oAGkA\r\n\
+                    --b\r\n\r\nundeclared \xe9\r\n\
+                    --b\r\nContent-Type: image/png\r\n\r\nnot text\r\n\
+                    --b--\r\n";
+    let multipart = parse(message).multipart.unwrap();
+    let decoded: Vec<_> = multipart.parts.iter().map(|p| p.decode_text(message, WINDOWS_1252)).collect();
+
+    let latin1 = decoded[0].as_ref().unwrap();
+    assert_eq!((&latin1.text[..], &latin1.charset[..]), ("café", "windows-1252"));
+
+    let invalid = decoded[1].as_ref().unwrap();
+    assert_eq!(invalid.text, "bad \u{fffd}\u{fffd} utf-8");
+    assert_eq!(invalid.invalid_sequences, 2);
+
+    let unknown = decoded[2].as_ref().unwrap();
+    assert_eq!(unknown.text, "à la");
+    assert!(unknown.unknown_charset);
+
+    let bom = decoded[3].as_ref().unwrap();
+    assert_eq!((&bom.text[..], &bom.charset[..], bom.bom), ("hi", "utf-16le", true));
+
+    let undeclared = decoded[4].as_ref().unwrap();
+    assert_eq!((&undeclared.text[..], &undeclared.charset[..]), ("undeclared é", "windows-1252"));
+    assert_eq!(undeclared.transfer_error, None);
+
+    assert_eq!(decoded[5], None);
+}
+
+#[test]
+fn decode_text_truncated() {
+    use encoding::all::WINDOWS_1252;
+
+    let text = crate::mime::decode_text(b"ends in \xc3", Some("utf-8"), WINDOWS_1252);
+    assert_eq!((&text.text[..], text.invalid_sequences), ("ends in \u{fffd}", 1));
+
+    let text = crate::mime::decode_text(b"plain", None, WINDOWS_1252);
+    assert_eq!((&text.text[..], &text.charset[..], text.invalid_sequences), ("plain", "ascii", 0));
+}
+
+const FORWARDED: &[u8] = b"Subject: Fwd: hello\r\n\
+Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+--b\r\n\r\nSee below.\r\n\
+--b\r\n\
+Content-Type: message/rfc822\r\n\r\n\
+Subject: hello\r\n\
+Content-Type: multipart/alternative; boundary=c\r\n\r\n\
+--c\r\nContent-Type: text/plain\r\n\r\nhi\r\n--c--\r\n\
+--b\r\n\
+Content-Type: message/global\r\nContent-Transfer-Encoding: 8bit\r\n\r\n\
+Subject: h\xc3\xa9\r\n\r\nbody\r\n\
+--b\r\n\
+Content-Type: message/rfc822\r\nContent-Transfer-Encoding: base64\r\n\r\n\
+U3ViamVjdDogaGkNCg0KYm9keQ==\r\n\
+--b--\r\n";
+
+#[test]
+fn nested_message() {
+    let root = parse(FORWARDED);
+    let parts = root.multipart.unwrap().parts;
+
+    let inner = parts[1].message.as_ref().unwrap();
+    assert_eq!(inner.header("Subject"), Some(b" hello".as_ref()));
+    let alternative = inner.multipart.as_ref().unwrap();
+    assert_eq!(text(FORWARDED, alternative.parts[0].body.clone()), "hi");
+
+    let global = parts[2].message.as_ref().unwrap();
+    assert_eq!(global.header("Subject"), Some(" hé".as_bytes()));
+    assert_eq!(text(FORWARDED, global.body.clone()), "body");
+
+    // Encoded messages are not parsed in place.
+    assert_eq!(parts[3].message, None);
+    assert_eq!(parts[0].message, None);
+}
+
+#[test]
+fn message_depth() {
+    let mut message = Vec::new();
+    for _ in 0..100 {
+        message.extend_from_slice(b"Content-Type: message/rfc822\r\n\r\n");
+    }
+
+    let mut depth = 0;
+    let root = parse_with_depth(&message, 10);
+    let mut entity = &root;
+    while let Some(inner) = &entity.message {
+        entity = inner;
+        depth += 1;
+    }
+    assert_eq!(depth, 10);
+    assert_eq!(parse_with_depth(FORWARDED, 1).multipart.unwrap().parts[1].message, None);
+}
+
+#[test]
+fn external_body() {
+    let message = b"Content-Type: message/external-body; access-type=URL;\r\n\
+                    \tURL=\"http://example.com/\r\n big.tar\"; size=1000000; expiration=\"Fri, 1 Jan 2100 00:00:00 +0000\"\r\n\r\n\
+                    Content-Type: application/x-tar\r\n\
+                    Content-ID: <id1@example.com>\r\n\r\n";
+    let root = parse(message);
+    let external = root.external_body().unwrap();
+    assert_eq!(external.access_type, "url");
+    assert_eq!(external.url.as_deref(), Some("http://example.com/big.tar"));
+    assert_eq!(external.size, Some(1_000_000));
+    assert_eq!(external.expiration.as_deref(), Some("Fri, 1 Jan 2100 00:00:00 +0000"));
+    assert_eq!(external.permission, None);
+
+    let headers = external.headers.unwrap();
+    assert_eq!(headers.content_type.essence(), "application/x-tar");
+    assert_eq!(headers.header("Content-ID"), Some(b" <id1@example.com>".as_ref()));
+
+    assert_eq!(parse(b"Content-Type: message/external-body\r\n\r\n").external_body(), None);
+    assert_eq!(parse(FORWARDED).external_body(), None);
+}
+
+fn fragment(number: u32, total: Option<u32>, id: &str, body: &str) -> Vec<u8> {
+    let total = total.map(|t| format!("; total={}", t)).unwrap_or_default();
+    format!("From: joe@example.com\r\nSubject: part {}\r\nMessage-ID: <frag{}@example.com>\r\n\
+             Content-Type: message/partial; id=\"{}\"; number={}{}\r\n\r\n{}", number, number, id, number, total, body)
+        .into_bytes()
+}
+
+#[test]
+fn partial() {
+    let one = fragment(1, None, "x@y", "Received: dropped\r\nSubject: Original\r\nMessage-ID: <orig@example.com>\r\n\
+                                       MIME-Version: 1.0\r\nContent-Type: text/plain\r\n\r\none ");
+    let two = fragment(2, None, "x@y", "two ");
+    let three = fragment(3, Some(3), "x@y", "three");
+
+    assert_eq!(parse(&three).partial(), Some(Partial { id: "x@y".into(), number: 3, total: Some(3) }));
+    assert_eq!(parse(b"Content-Type: message/partial; id=a\r\n\r\n").partial(), None);
+
+    let joined = reassemble(&[&three, &one, &two]).unwrap();
+    assert_eq!(String::from_utf8(joined).unwrap(),
+               "From: joe@example.com\r\nSubject: Original\r\nMessage-ID: <orig@example.com>\r\n\
+                MIME-Version: 1.0\r\nContent-Type: text/plain\r\n\r\none two three");
+
+    assert_eq!(reassemble(&[&one, &three]), Err("Missing fragment"));
+    assert_eq!(reassemble(&[&one, &two]), Err("Unknown fragment total"));
+    assert_eq!(reassemble(&[&one, &two, &two, &three]), Err("Duplicate fragment"));
+    assert_eq!(reassemble(&[&one, &two, &fragment(3, Some(3), "other", "")]), Err("Mismatched partial id"));
+    assert_eq!(reassemble(&[&one, b"Subject: not partial\r\n\r\n"]), Err("Not a partial message"));
+    assert_eq!(reassemble(&[]), Err("No fragments"));
+}
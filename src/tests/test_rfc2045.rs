@@ -0,0 +1,156 @@
+use crate::rfc2045::*;
+use crate::rfc2231::ContentTransferEncoding as CTE;
+
+fn b64(input: &[u8]) -> (Vec<u8>, Option<&'static str>) {
+    decode(&CTE::Base64, input)
+}
+
+fn qp(input: &[u8]) -> (Vec<u8>, Option<&'static str>) {
+    decode(&CTE::QuotedPrintable, input)
+}
+
+// Decode with the input split in two at every position.
+fn check_splits(encoding: &CTE, input: &[u8]) {
+    let expected = decode(encoding, input);
+
+    for i in 0..=input.len() {
+        let mut decoder = Decoder::new(encoding);
+        let mut out = Vec::new();
+        decoder.update(&input[..i], &mut out);
+        decoder.update(&input[i..], &mut out);
+        let error = decoder.finish(&mut out);
+        assert_eq!((out, error), expected, "split at {}", i);
+    }
+}
+
+#[test]
+fn base64() {
+    assert_eq!(b64(b"SGVsbG8sIHdvcmxkIQ=="), (b"Hello, world!".to_vec(), None));
+    assert_eq!(b64(b"SGVs\r\nbG8s\r\n IHdv\tcmxk\r\nIQ==\r\n"), (b"Hello, world!".to_vec(), None));
+    assert_eq!(b64(b"SGV*sbG8!~"), (b"Hello".to_vec(), Some("Missing base64 padding")));
+    assert_eq!(b64(b""), (vec![], None));
+}
+
+#[test]
+fn base64_padding() {
+    assert_eq!(b64(b"SGk="), (b"Hi".to_vec(), None));
+    assert_eq!(b64(b"SQ=="), (b"I".to_vec(), None));
+    assert_eq!(b64(b"SQ="), (b"I".to_vec(), Some("Missing base64 padding")));
+    assert_eq!(b64(b"SQ=SGk="), (b"IHi".to_vec(), Some("Missing base64 padding")));
+    assert_eq!(b64(b"SGk=SGk="), (b"HiHi".to_vec(), Some("Data after base64 padding")));
+    assert_eq!(b64(b"S==="), (vec![], Some("Invalid base64 padding")));
+    assert_eq!(b64(b"SGk=="), (b"Hi".to_vec(), Some("Invalid base64 padding")));
+    assert_eq!(b64(b"SGVsb"), (b"Hel".to_vec(), Some("Truncated base64")));
+}
+
+#[test]
+fn quoted_printable() {
+    assert_eq!(qp(b"caf=C3=A9 caf=c3=a9"), ("café café".as_bytes().to_vec(), None));
+    assert_eq!(qp(b"soft =\r\nbreak=  \r\nand=\nlf"), (b"soft breakandlf".to_vec(), None));
+    assert_eq!(qp(b"trailing  \r\nspace\t\r\nend  "), (b"trailing\r\nspace\r\nend".to_vec(), None));
+    assert_eq!(qp(b"a\r\n\r\nb\n"), (b"a\r\n\r\nb\n".to_vec(), None));
+    assert_eq!(qp(b"ends with ="), (b"ends with ".to_vec(), None));
+}
+
+#[test]
+fn quoted_printable_invalid() {
+    assert_eq!(qp(b"1+1=2"), (b"1+1=2".to_vec(), Some("Invalid quoted-printable escape")));
+    assert_eq!(qp(b"=G1=4"), (b"=G1=4".to_vec(), Some("Invalid quoted-printable escape")));
+    assert_eq!(qp(b"=\r"), (b"=\r".to_vec(), Some("Invalid quoted-printable escape")));
+}
+
+#[test]
+fn identity() {
+    for encoding in &[CTE::SevenBit, CTE::EightBit, CTE::Binary, CTE::Extended("uuencode".into()), CTE::Token("other".into())] {
+        assert_eq!(decode(encoding, b"=41 \r\n"), (b"=41 \r\n".to_vec(), None));
+    }
+}
+
+#[test]
+fn streaming() {
+    check_splits(&CTE::Base64, b"SGVs\r\nbG8s\r\nIHdvcmxkIQ=\r\n=\r\n");
+    check_splits(&CTE::Base64, b"SQ=SGVsbG");
+    check_splits(&CTE::QuotedPrintable, b"caf=C3=A9  \r\nsoft=  \r\nbreak=\nlf=4=\r\n\tend  ");
+    check_splits(&CTE::QuotedPrintable, b"1+1=2 =\r");
+}
+
+fn check_lines(encoded: &[u8]) {
+    for line in encoded.split(|c| *c == b'\n') {
+        assert!(line.len() <= 77, "line too long: {:?}", String::from_utf8_lossy(line));
+        assert!(line.iter().all(|c| (32..=126).contains(c) || *c == b'\r' || *c == b'\t'));
+    }
+}
+
+fn roundtrip(encoding: &CTE, input: &[u8]) -> Vec<u8> {
+    let encoded = encode(encoding, input);
+    check_lines(&encoded);
+    assert_eq!(decode(encoding, &encoded), (input.to_vec(), None));
+
+    // Encoding in chunks gives the same output.
+    for size in &[1, 2, 3, 56, 57, 58] {
+        let mut encoder = Encoder::new(encoding);
+        let mut out = Vec::new();
+        for chunk in input.chunks(*size) {
+            encoder.update(chunk, &mut out);
+        }
+        encoder.finish(&mut out);
+        assert_eq!(out, encoded, "chunk size {}", size);
+    }
+
+    encoded
+}
+
+#[test]
+fn encode_base64() {
+    assert_eq!(roundtrip(&CTE::Base64, b""), b"");
+    assert_eq!(roundtrip(&CTE::Base64, b"Hi"), b"SGk=\r\n");
+
+    let data: Vec<u8> = (0..=255).collect();
+    let encoded = roundtrip(&CTE::Base64, &data);
+    let lines: Vec<_> = encoded.split(|c| *c == b'\n').collect();
+    assert_eq!(lines.iter().map(|l| l.len()).collect::<Vec<_>>(), [77, 77, 77, 77, 41, 0]);
+}
+
+#[test]
+fn encode_quoted_printable() {
+    assert_eq!(roundtrip(&CTE::QuotedPrintable, b"a = b\tc \t\r\nd \r\n"), b"a =3D b\tc =09\r\nd=20\r\n");
+    assert_eq!(roundtrip(&CTE::QuotedPrintable, b"bare\rcr\nlf\0 end "), b"bare=0Dcr=0Alf=00 end=20");
+
+    let long = "x".repeat(200);
+    let encoded = roundtrip(&CTE::QuotedPrintable, long.as_bytes());
+    assert_eq!(&encoded[..78], format!("{}=\r\n", "x".repeat(75)).as_bytes());
+
+    // A line of exactly 76 characters needs no soft line break.
+    assert_eq!(roundtrip(&CTE::QuotedPrintable, "y".repeat(76).as_bytes()), "y".repeat(76).as_bytes());
+    // Escapes are never split.
+    let encoded = roundtrip(&CTE::QuotedPrintable, "é".repeat(40).as_bytes());
+    assert!(encoded.starts_with(format!("{}=\r\n", "=C3=A9".repeat(12) + "=C3").as_bytes()));
+
+    let data: Vec<u8> = (0..=255).cycle().take(2000).collect();
+    roundtrip(&CTE::QuotedPrintable, &data);
+}
+
+#[test]
+fn encode_identity() {
+    assert_eq!(encode(&CTE::EightBit, b"caf\xc3\xa9 \r\n"), b"caf\xc3\xa9 \r\n");
+}
+
+#[test]
+fn choose() {
+    use BodyType::*;
+
+    assert_eq!(choose_encoding(b"", SevenBit), CTE::SevenBit);
+    assert_eq!(choose_encoding(b"plain text\r\nlines\r\n", SevenBit), CTE::SevenBit);
+    assert_eq!(choose_encoding("mostly ascii, très bien\r\n".as_bytes(), SevenBit), CTE::QuotedPrintable);
+    assert_eq!(choose_encoding("mostly ascii, très bien\r\n".as_bytes(), BinaryMIME), CTE::EightBit);
+    assert_eq!(choose_encoding("ελληνικά".as_bytes(), SevenBit), CTE::Base64);
+
+    // Bare line breaks and long lines cannot be sent as 7bit or 8bit.
+    assert_eq!(choose_encoding(b"unix\nline", SevenBit), CTE::QuotedPrintable);
+    assert_eq!(choose_encoding(b"unix\nline", EightBitMIME), CTE::QuotedPrintable);
+    assert_eq!(choose_encoding(b"unix\nline", BinaryMIME), CTE::Binary);
+    assert_eq!(choose_encoding("a".repeat(999).as_bytes(), SevenBit), CTE::QuotedPrintable);
+    assert_eq!(choose_encoding(format!("{}\r\n", "a".repeat(998)).as_bytes(), SevenBit), CTE::SevenBit);
+    assert_eq!(choose_encoding(&[0xff; 10], EightBitMIME), CTE::EightBit);
+    assert_eq!(choose_encoding(&[0; 10], EightBitMIME), CTE::Base64);
+}
@@ -0,0 +1,115 @@
+use encoding::all::{ISO_8859_1, UTF_8, WINDOWS_1252};
+use encoding::types::{Encoding, RawDecoder, RawEncoder};
+
+use crate::rfc2047::*;
+
+fn decode_all(words: &[String]) -> String {
+    words.iter().map(|w| {
+        let (rem, decoded) = encoded_word(w.as_bytes()).unwrap();
+        assert_eq!(rem.len(), 0);
+        decoded
+    }).collect()
+}
+
+#[test]
+fn split_long() {
+    let text = "Voix ambiguë d'un cœur qui au zéphyr préfère les jattes de kiwis. ".repeat(5);
+    let words = encode(&text, Context::Text);
+    assert!(words.len() > 1);
+    assert!(words.iter().all(|w| w.len() <= 75));
+    assert_eq!(decode_all(&words), text);
+}
+
+#[test]
+fn multibyte_boundaries() {
+    for text in &["日本語".repeat(30), "😀".repeat(50), "aé".repeat(60)] {
+        let words = encode(text, Context::Phrase);
+        assert!(words.iter().all(|w| w.len() <= 75));
+        assert_eq!(decode_all(&words), *text);
+    }
+}
+
+#[test]
+fn context_chars() {
+    assert_eq!(encode("Question? \"yes_no\" (maybe)", Context::Text), ["=?utf-8?Q?Question=3F_\"yes=5Fno\"_(maybe)?="]);
+    assert_eq!(encode("Question? yes_no (maybe)", Context::Comment), ["=?utf-8?Q?Question=3F_yes=5Fno_=28maybe=29?="]);
+    assert_eq!(encode("Question? yes.no, maybe", Context::Phrase), ["=?utf-8?Q?Question=3F_yes=2Eno=2C_maybe?="]);
+}
+
+#[test]
+fn b_or_q() {
+    assert_eq!(encode("Hello, wörld", Context::Text), ["=?utf-8?Q?Hello,_w=C3=B6rld?="]);
+    assert_eq!(encode("Ωμέγα", Context::Text), ["=?utf-8?B?zqnOvM6tzrPOsQ==?="]);
+}
+
+#[test]
+fn charset() {
+    let words = encode_charset("Fiche d’information", WINDOWS_1252, Context::Phrase).unwrap();
+    assert_eq!(words, ["=?windows-1252?Q?Fiche_d=92information?="]);
+    assert_eq!(decode_all(&words), "Fiche d’information");
+
+    assert_eq!(encode_charset("日本語", ISO_8859_1, Context::Text), Err("Character not representable in charset"));
+}
+
+struct Named(&'static str);
+
+impl Encoding for Named {
+    fn name(&self) -> &'static str { self.0 }
+    fn raw_encoder(&self) -> Box<dyn RawEncoder> { UTF_8.raw_encoder() }
+    fn raw_decoder(&self) -> Box<dyn RawDecoder> { UTF_8.raw_decoder() }
+}
+
+#[test]
+fn charset_name_too_long() {
+    static TIGHT: Named = Named("x-a-charset-name-that-leaves-room-for-only-a-single-encoded-octet-1");
+    static LONG: Named = Named("x-a-charset-name-that-is-much-too-long-to-fit-in-an-encoded-word-even-without-text");
+
+    assert_eq!(encode_charset("ab", &TIGHT, Context::Text).unwrap().len(), 2);
+    assert_eq!(encode_charset("é", &TIGHT, Context::Text), Err("Charset name too long"));
+    assert_eq!(encode_charset("a", &LONG, Context::Text), Err("Charset name too long"));
+}
+
+#[test]
+fn report_lang() {
+    let (_, report) = encoded_word_report(b"=?iso-8859-1*en-US?q?caf=E9?=").unwrap();
+    assert_eq!(report.decoded, "café");
+    assert_eq!(report.charset, "iso-8859-1");
+    assert_eq!(report.language.as_deref(), Some("en-US"));
+    assert_eq!(report.encoding, WordEncoding::Q);
+    assert!(!report.unknown_charset);
+    assert_eq!(encoded_word(b"=?iso-8859-1*en-US?q?caf=E9?=").unwrap().1, "café");
+}
+
+#[test]
+fn report_failures() {
+    let (_, report) = encoded_word_report(b"=?utf-8?B?not-base64!?=").unwrap();
+    assert!(report.decoding_failed);
+    assert_eq!(report.decoded, "not-base64!");
+    assert!(encoded_word_strict(b"=?utf-8?B?not-base64!?=").is_err());
+
+    let (_, report) = encoded_word_report(b"=?x-unknown?B?aGVsbG8=?=").unwrap();
+    assert!(report.unknown_charset);
+    assert_eq!(report.decoded, "hello");
+    assert!(encoded_word_strict(b"=?x-unknown?B?aGVsbG8=?=").is_err());
+
+    let (_, report) = encoded_word_report(b"=?utf-8?X?hello?=").unwrap();
+    assert_eq!(report.encoding, WordEncoding::Unknown("X".into()));
+    assert!(report.decoding_failed);
+
+    assert_eq!(encoded_word_strict(b"=?utf-8?b?aGVsbG8=?=").unwrap().1, "hello");
+}
+
+#[test]
+fn scan_positions() {
+    let found = scan_encoded_words(b"a=?utf-8?Q?x?= =?utf-8?Q?y?=b =?utf-8?Q?z?=", false);
+    let forbidden: Vec<_> = found.iter().map(|(o, r)| (*o, r.forbidden_position)).collect();
+    assert_eq!(forbidden, [(1, true), (15, true), (30, false)]);
+
+    let found = scan_encoded_words(b"(=?utf-8?Q?ok?=) <=?utf-8?Q?x?=@example.org>, [=?utf-8?Q?y?=]", true);
+    let forbidden: Vec<_> = found.iter().map(|(_, r)| r.forbidden_position).collect();
+    assert_eq!(forbidden, [false, true, true]);
+
+    // Quotes don't matter in unstructured headers.
+    let found = scan_encoded_words(b"\" =?utf-8?Q?x?= \"", false);
+    assert!(!found[0].1.forbidden_position);
+}
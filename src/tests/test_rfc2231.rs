@@ -1,3 +1,6 @@
+// The deprecated content_type parser is still covered here.
+#![allow(deprecated)]
+
 use crate::rfc2231::*;
 use crate::rfc2231::{ContentTransferEncoding as CTE, ContentDisposition as CD};
 
@@ -174,3 +177,81 @@ fn attfnbrokentokenutf() {
     let (rem, _) = content_disposition(b"attachment; filename=foo-\xC3\xA4.html").unwrap();
     assert_eq!(rem.len(), 0);
 }
+
+#[test]
+fn media_type_accessors() {
+    let (rem, media) = media_type(b"Multipart/Mixed; BOUNDARY=\"=_abc\"; Charset=UTF-8\r\n").unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(media.essence(), "multipart/mixed");
+    assert_eq!((media.boundary(), media.charset(), media.name()), (Some("=_abc"), Some("UTF-8"), None));
+    assert_eq!(media.parameter("Boundary"), Some("=_abc"));
+    assert!(media.is_multipart() && !media.is_text());
+    assert_eq!(media.suffix(), None);
+
+    assert_eq!(MediaType::new("application/ld+json").suffix(), Some("json"));
+    assert_eq!(MediaType::new("text/x+").suffix(), None);
+    assert!(MediaType::new("TEXT/Plain").is_text());
+}
+
+#[test]
+fn media_type_display() {
+    let mut media = MediaType::new("text/plain");
+    media.set_parameter("charset", "utf-8");
+    media.set_parameter("Format", "flowed");
+    media.set_parameter("CHARSET", "us-ascii");
+    assert_eq!(media.to_string(), "text/plain; charset=us-ascii; format=flowed");
+
+    let mut media = MediaType::new("application/octet-stream");
+    media.set_parameter("name", "my \"file\".txt");
+    media.set_parameter("x-empty", "");
+    media.set_parameter("x-title", "naïve 100%");
+    assert_eq!(media.to_string(), "application/octet-stream; name=\"my \\\"file\\\".txt\"; x-empty=\"\"; x-title*=utf-8''na%C3%AFve%20100%25");
+
+    // The output parses back to the same value.
+    let (_, parsed) = media_type(media.to_string().as_bytes()).unwrap();
+    assert_eq!(parsed, media);
+}
+
+fn roundtrip_parameter(value: &str, charset: encoding::types::EncodingRef, max_len: usize) -> Vec<String> {
+    let sections = encode_parameter("filename", value, charset, Some("en"), max_len).unwrap();
+    for s in &sections {
+        assert!(s.len() <= max_len, "section too long: {}", s);
+    }
+
+    let header = format!("attachment; {}", sections.join(";\r\n "));
+    let (rem, (_, params)) = content_disposition(header.as_bytes()).unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(params, [("filename".into(), value.into())]);
+    sections
+}
+
+#[test]
+fn encode_parameter_sections() {
+    use encoding::all::{ISO_8859_1, UTF_8};
+
+    assert_eq!(roundtrip_parameter("a b \"c\".txt", UTF_8, 78), ["filename=\"a b \\\"c\\\".txt\""]);
+    assert_eq!(roundtrip_parameter("plain but long", UTF_8, 20), ["filename*0=\"plain b\"", "filename*1=\"ut long\""]);
+    assert_eq!(roundtrip_parameter("été", ISO_8859_1, 78), ["filename*=iso-8859-1'en'%E9t%E9"]);
+
+    let long = "Проектная документация, окончательная версия.docx";
+    let sections = roundtrip_parameter(long, UTF_8, 76);
+    assert_eq!(sections.len(), 5);
+    assert!(sections[0].starts_with("filename*0*=utf-8'en'%D0%9F"));
+    assert!(sections[1].starts_with("filename*1*=%"));
+
+    // Escapes are never split.
+    for max_len in 30..60 {
+        roundtrip_parameter("日本語のファイル名.txt", UTF_8, max_len);
+    }
+}
+
+#[test]
+fn encode_parameter_invalid() {
+    use encoding::all::{ISO_8859_1, UTF_8};
+
+    assert_eq!(encode_parameter("file name", "x", UTF_8, None, 78), Err("Invalid parameter name"));
+    assert_eq!(encode_parameter("name*", "x", UTF_8, None, 78), Err("Invalid parameter name"));
+    assert_eq!(encode_parameter("name", "é", UTF_8, Some("en'us"), 78), Err("Invalid language"));
+    assert_eq!(encode_parameter("name", "日本", ISO_8859_1, None, 78), Err("Value not representable in charset"));
+    assert_eq!(encode_parameter("filename", "été", UTF_8, None, 10), Err("Maximum length too short"));
+}
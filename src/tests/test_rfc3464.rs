@@ -0,0 +1,259 @@
+use crate::mime::parse;
+use crate::rfc3461::{dsn_notify, DSNMailParams, DSNRet};
+use crate::rfc3464::*;
+use crate::rfc5321::ReversePath;
+use crate::types::Mailbox;
+
+const BOUNCE: &[u8] = b"From: MAILER-DAEMON@mx.example.com\r\n\
+Subject: Undelivered Mail Returned to Sender\r\n\
+Content-Type: multipart/report; report-type=delivery-status;\r\n\tboundary=\"B\"\r\n\r\n\
+--B\r\n\
+Content-Description: Notification\r\n\
+Content-Type: text/plain; charset=us-ascii\r\n\r\n\
+I'm sorry to have to inform you that your message could not be delivered.\r\n\
+--B\r\n\
+Content-Description: Delivery report\r\n\
+Content-Type: message/delivery-status\r\n\r\n\
+Reporting-MTA: dns; mx.example.com\r\n\
+X-Postfix-Queue-ID: 4ABC123\r\n\
+Received-From-MTA: dns; client.example.net (192.0.2.1)\r\n\
+Arrival-Date: Mon,  1 Jun 2020 10:00:00 +0000 (UTC)\r\n\r\n\
+Original-Recipient: rfc822;bob+2Btag@example.org\r\n\
+Final-Recipient: RFC822; bob@example.org\r\n\
+Action: failed\r\n\
+Status: 5.1.1\r\n\
+Remote-MTA: dns; mail.example.org\r\n\
+Diagnostic-Code: smtp; 550 5.1.1 <bob@example.org>: Recipient address\r\n\
+\trejected: User unknown\r\n\r\n\
+Final-Recipient: rfc822; carol@example.org\r\n\
+Action: Delayed (will retry)\r\n\
+Status: 4.4.1 (no answer from host)\r\n\
+Will-Retry-Until: Fri,  5 Jun 2020 10:00:00 +0000\r\n\r\n\
+--B\r\n\
+Content-Description: Undelivered Message Headers\r\n\
+Content-Type: text/rfc822-headers\r\n\r\n\
+From: alice@example.net\r\n\
+Subject: Hello\r\n\
+--B--\r\n";
+
+#[test]
+fn bounce() {
+    let root = parse(BOUNCE);
+    let report = delivery_report(&root, BOUNCE).unwrap();
+    assert_eq!(report.description.unwrap().content_type.essence(), "text/plain");
+    assert_eq!(report.original.unwrap().content_type.essence(), "text/rfc822-headers");
+
+    let message = &report.status.message;
+    assert_eq!(message.reporting_mta, Some(("dns".into(), "mx.example.com".into())));
+    assert_eq!(message.received_from_mta, Some(("dns".into(), "client.example.net".into())));
+    assert_eq!(message.arrival_date.as_deref(), Some("Mon,  1 Jun 2020 10:00:00 +0000 (UTC)"));
+    assert_eq!(message.extensions, [("x-postfix-queue-id".into(), "4ABC123".into())]);
+
+    let recipients = &report.status.recipients;
+    assert_eq!(recipients.len(), 2);
+    assert_eq!(recipients[0].original_recipient, Some(("rfc822".into(), "bob+tag@example.org".into())));
+    assert_eq!(recipients[0].final_recipient, ("rfc822".into(), "bob@example.org".into()));
+    assert_eq!(recipients[0].status, Status { class: 5, subject: 1, detail: 1 });
+    assert_eq!(recipients[0].remote_mta, Some(("dns".into(), "mail.example.org".into())));
+    assert_eq!(recipients[0].diagnostic_code.as_ref().unwrap().1,
+               "550 5.1.1 <bob@example.org>: Recipient address rejected: User unknown");
+
+    assert_eq!(recipients[1].action, Action::Delayed);
+    assert_eq!(recipients[1].status.to_string(), "4.4.1");
+    assert_eq!(recipients[1].will_retry_until.as_deref(), Some("Fri,  5 Jun 2020 10:00:00 +0000"));
+    assert_eq!(recipients[1].original_recipient, None);
+}
+
+#[test]
+fn global_status() {
+    let message = "Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+                   --outer\r\n\
+                   Content-Type: multipart/report; report-type=global-delivery-status; boundary=B\r\n\r\n\
+                   --B\r\n\
+                   Content-Type: message/global-delivery-status\r\n\
+                   Content-Transfer-Encoding: 8bit\r\n\r\n\
+                   Reporting-MTA: dns; mx.example.com\r\n\r\n\
+                   Original-Recipient: utf-8; δοκιμή@παράδειγμα.δοκιμή\r\n\
+                   Final-Recipient: utf-8; δοκιμή@παράδειγμα.δοκιμή\r\n\
+                   Action: delivered\r\n\
+                   Status: 2.0.0\r\n\
+                   --B--\r\n\
+                   --outer--\r\n".as_bytes();
+    let root = parse(message);
+    let report = delivery_report(&root, message).unwrap();
+    assert_eq!(report.description, None);
+    assert_eq!(report.original, None);
+
+    let rcpt = &report.status.recipients[0];
+    assert_eq!(rcpt.action, Action::Delivered);
+    assert_eq!(rcpt.original_recipient, Some(("utf-8".into(), "δοκιμή@παράδειγμα.δοκιμή".into())));
+    assert_eq!(rcpt.final_recipient, ("utf-8".into(), "δοκιμή@παράδειγμα.δοκιμή".into()));
+}
+
+#[test]
+fn status_codes() {
+    assert_eq!(Status::parse("5.7.26"), Some(Status { class: 5, subject: 7, detail: 26 }));
+    assert_eq!(Status::parse("2.0.0(ok)"), Some(Status { class: 2, subject: 0, detail: 0 }));
+    assert_eq!(Status::parse("3.0.0"), None);
+    assert_eq!(Status::parse("5.1"), None);
+    assert_eq!(Status::parse("5.1.1.1"), None);
+    assert_eq!(Status::parse("5.1000.1"), None);
+}
+
+#[test]
+fn invalid() {
+    assert_eq!(delivery_status(b""), Err("Empty delivery status"));
+    assert_eq!(delivery_status(b"Reporting-MTA: dns; a\r\n"), Err("No recipients in delivery status"));
+    assert_eq!(delivery_status(b"Reporting-MTA: dns; a\r\n\r\nAction: failed\r\nStatus: 5.0.0\r\n"),
+               Err("Missing Final-Recipient"));
+    assert_eq!(delivery_status(b"Reporting-MTA: dns; a\r\n\r\nFinal-Recipient: rfc822; a@b\r\nAction: bounced\r\nStatus: 5.0.0\r\n"),
+               Err("Invalid Action"));
+    assert_eq!(delivery_status(b"Reporting-MTA: dns; a\r\n\r\nFinal-Recipient: rfc822; a@b\r\nAction: failed\r\n"), Err("Invalid Status"));
+
+    let message = b"Content-Type: multipart/report; report-type=disposition-notification; boundary=B\r\n\r\n--B--\r\n";
+    assert_eq!(delivery_report(&parse(message), message), Err("No delivery report"));
+    let message = b"Content-Type: multipart/report; report-type=delivery-status; boundary=B\r\n\r\n--B\r\n\r\ntext\r\n--B--\r\n";
+    assert_eq!(delivery_report(&parse(message), message), Err("Missing delivery status"));
+}
+
+const ORIGINAL: &[u8] = b"From: alice@example.com\r\nSubject: Report\r\n\r\nThe body.\r\n";
+
+fn outcome(address: &str, action: Action, status: &str, notify: Option<&str>) -> RecipientOutcome {
+    RecipientOutcome {
+        recipient: Mailbox::from_smtp(address.as_bytes()).unwrap(),
+        original_recipient: None,
+        notify: notify.map(|n| dsn_notify(n).unwrap().1),
+        action,
+        status: Status::parse(status).unwrap(),
+        remote_mta: None,
+        diagnostic_code: None,
+        last_attempt_date: None,
+        will_retry_until: None,
+    }
+}
+
+fn generate(params: &DSNMailParams, smtputf8: bool, recipients: &[RecipientOutcome]) -> Result<Option<Vec<u8>>, &'static str> {
+    generate_dsn(&DSNMessage {
+        reporting_mta: "relay.example.com",
+        from: &Mailbox::from_smtp(b"MAILER-DAEMON@relay.example.com").unwrap(),
+        sender: &"<alice@example.com>".parse::<ReversePath>().unwrap(),
+        date: "Mon, 1 Jun 2020 10:00:00 +0000",
+        arrival_date: Some("Mon, 1 Jun 2020 09:59:00 +0000"),
+        params,
+        smtputf8,
+        boundary: "=_report",
+        original: ORIGINAL,
+        recipients,
+    })
+}
+
+#[test]
+fn generate_roundtrip() {
+    let mut failed = outcome("bob@example.org", Action::Failed, "5.1.1", Some("FAILURE,DELAY"));
+    failed.original_recipient = Some(("rfc822".into(), "bob+x@example.org".into()));
+    failed.remote_mta = Some("mx.example.org".into());
+    failed.diagnostic_code = Some("550 5.1.1 No such user".into());
+    let mut delayed = outcome("carol@example.org", Action::Delayed, "4.4.1", Some("DELAY"));
+    delayed.will_retry_until = Some("Fri, 5 Jun 2020 10:00:00 +0000".into());
+    let recipients = [failed, delayed, outcome("dave@example.org", Action::Delivered, "2.0.0", None)];
+
+    let params = DSNMailParams { envid: Some("id with space".into()), ret: Some(DSNRet::Full) };
+    let message = generate(&params, false, &recipients).unwrap().unwrap();
+    assert!(message.is_ascii());
+
+    let root = parse(&message);
+    assert_eq!(root.header("Subject"), Some(b" Delivery Status Notification (Failure)".as_ref()));
+    assert_eq!(root.header("To"), Some(b" <alice@example.com>".as_ref()));
+    assert_eq!(root.header("Auto-Submitted"), Some(b" auto-replied".as_ref()));
+
+    let report = delivery_report(&root, &message).unwrap();
+    assert_eq!(report.description.unwrap().content_type.charset(), Some("us-ascii"));
+    let original = report.original.unwrap();
+    assert_eq!(original.content_type.essence(), "message/rfc822");
+    assert_eq!(&message[original.body.clone()], &ORIGINAL[..ORIGINAL.len()-2]);
+
+    let status = report.status;
+    assert_eq!(status.message.reporting_mta, Some(("dns".into(), "relay.example.com".into())));
+    assert_eq!(status.message.original_envelope_id.as_deref(), Some("id with space"));
+    assert_eq!(status.message.arrival_date.as_deref(), Some("Mon, 1 Jun 2020 09:59:00 +0000"));
+    assert_eq!(status.recipients.len(), 2);
+
+    let bob = &status.recipients[0];
+    assert_eq!(bob.original_recipient, Some(("rfc822".into(), "bob+x@example.org".into())));
+    assert_eq!(bob.final_recipient, ("rfc822".into(), "bob@example.org".into()));
+    assert_eq!((bob.action.clone(), bob.status.to_string()), (Action::Failed, "5.1.1".into()));
+    assert_eq!(bob.remote_mta, Some(("dns".into(), "mx.example.org".into())));
+    assert_eq!(bob.diagnostic_code, Some(("smtp".into(), "550 5.1.1 No such user".into())));
+
+    let carol = &status.recipients[1];
+    assert_eq!(carol.action, Action::Delayed);
+    assert_eq!(carol.will_retry_until.as_deref(), Some("Fri, 5 Jun 2020 10:00:00 +0000"));
+}
+
+#[test]
+fn generate_returned_content() {
+    let full = DSNMailParams { envid: None, ret: Some(DSNRet::Full) };
+    let delayed = [outcome("bob@example.org", Action::Delayed, "4.0.0", Some("DELAY"))];
+
+    // RET=FULL is ignored for delay reports.
+    let message = generate(&full, false, &delayed).unwrap().unwrap();
+    let root = parse(&message);
+    assert_eq!(root.header("Subject"), Some(b" Delivery Status Notification (Delay)".as_ref()));
+    let original = delivery_report(&root, &message).unwrap().original.unwrap();
+    assert_eq!(original.content_type.essence(), "text/rfc822-headers");
+    assert_eq!(&message[original.body.clone()], b"From: alice@example.com\r\nSubject: Report\r\n");
+
+    let hdrs = DSNMailParams { envid: None, ret: Some(DSNRet::Hdrs) };
+    let failed = [outcome("bob@example.org", Action::Failed, "5.0.0", None)];
+    let message = generate(&hdrs, false, &failed).unwrap().unwrap();
+    let root = parse(&message);
+    assert_eq!(delivery_report(&root, &message).unwrap().original.unwrap().content_type.essence(), "text/rfc822-headers");
+}
+
+#[test]
+fn generate_global() {
+    let params = DSNMailParams { envid: None, ret: Some(DSNRet::Full) };
+    let mut failed = outcome("δοκιμή@παράδειγμα.δοκιμή", Action::Failed, "5.1.1", Some("SUCCESS,FAILURE"));
+    failed.original_recipient = Some(("rfc822".into(), "info@example.org".into()));
+    let recipients = [failed];
+    assert_eq!(generate(&params, false, &recipients), Err("Non-ASCII recipient without SMTPUTF8"));
+
+    let message = generate(&params, true, &recipients).unwrap().unwrap();
+    let root = parse(&message);
+    assert_eq!(root.content_type.parameter("report-type"), Some("global-delivery-status"));
+
+    let report = delivery_report(&root, &message).unwrap();
+    let description = report.description.unwrap();
+    assert_eq!(description.content_type.charset(), Some("utf-8"));
+    assert_eq!(description.header("Content-Transfer-Encoding"), Some(b" 8bit".as_ref()));
+    assert_eq!(report.original.unwrap().content_type.essence(), "message/global");
+    assert_eq!(report.status.recipients[0].original_recipient, Some(("rfc822".into(), "info@example.org".into())));
+    assert_eq!(report.status.recipients[0].final_recipient, ("utf-8".into(), "δοκιμή@παράδειγμα.δοκιμή".into()));
+}
+
+#[test]
+fn generate_not_reported() {
+    let params = DSNMailParams { envid: None, ret: None };
+    let recipients = [outcome("bob@example.org", Action::Failed, "5.0.0", Some("NEVER")),
+                      outcome("carol@example.org", Action::Delivered, "2.0.0", Some("FAILURE")),
+                      outcome("dave@example.org", Action::Delayed, "4.0.0", None)];
+    assert_eq!(generate(&params, false, &recipients), Ok(None));
+
+    let recipients = [outcome("bob@example.org", Action::Failed, "5.0.0", None)];
+    let null = DSNMessage {
+        reporting_mta: "relay.example.com",
+        from: &Mailbox::from_smtp(b"MAILER-DAEMON@relay.example.com").unwrap(),
+        sender: &ReversePath::Null,
+        date: "Mon, 1 Jun 2020 10:00:00 +0000",
+        arrival_date: None,
+        params: &params,
+        smtputf8: false,
+        boundary: "Report",
+        original: b"Subject: x\r\n\r\n--Report\r\n",
+        recipients: &recipients,
+    };
+    assert_eq!(generate_dsn(&null), Ok(None));
+
+    let sender = "<alice@example.com>".parse().unwrap();
+    assert_eq!(generate_dsn(&DSNMessage { sender: &sender, ..null }), Err("Boundary found in original message"));
+}
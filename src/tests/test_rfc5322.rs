@@ -1,5 +1,5 @@
-use crate::behaviour::{Intl, Legacy};
-use crate::rfc5322::{Address, Group, Mailbox, from, reply_to, sender, unstructured};
+use crate::behaviour::{Commented, Intl, Legacy, Obsolete};
+use crate::rfc5322::{Address, Comment, Group, Mailbox, Skipped, from, recover_address_list, reply_to, sender, unstructured};
 use crate::types::{Mailbox as SMTPMailbox, *};
 
 fn dp<T: Into<String>>(value: T) -> DomainPart {
@@ -44,7 +44,7 @@ fn simple_from() {
 fn simple_sender() {
     let (rem, parsed) = sender::<Intl>(b"Michael Jones <mjones@machine.example>\r\n").unwrap();
     assert_eq!(rem.len(), 0);
-    if let Address::Mailbox(Mailbox{dname, address}) = parsed {
+    if let Address::Mailbox(Mailbox{dname, address, ..}) = parsed {
         assert_eq!(dname, Some("Michael Jones".into()));
         assert_eq!(address, SMTPMailbox(DotAtom("mjones".into()).into(), dp("machine.example")))
     } else {
@@ -63,30 +63,68 @@ fn simple_reply_to() {
 fn group_reply_to() {
     let (rem, parsed) = reply_to::<Intl>(b"  A Group(Some people)\r\n    :Chris Jones <c@(Chris's host.)public.example>,\r\n        joe@example.org,\r\n John <jdoe@one.test> (my dear friend); (the end of the group)\r\n").unwrap();
     assert_eq!(rem.len(), 0);
+    assert_eq!(parsed, [Address::Group(Group::new("A Group".into(), vec![
+        Mailbox::new(Some("Chris Jones".into()), SMTPMailbox(DotAtom("c".into()).into(), dp("public.example"))),
+        Mailbox::new(None, SMTPMailbox(DotAtom("joe".into()).into(), dp("example.org"))),
+        Mailbox::new(Some("John".into()), SMTPMailbox(DotAtom("jdoe".into()).into(), dp("one.test"))),
+    ]))]);
+}
+
+#[test]
+fn group_comments() {
+    let (rem, parsed) = reply_to::<Commented<Intl>>(b"  A Group(Some people)\r\n    :Chris Jones <c@(Chris's host.)public.example>,\r\n        joe@example.org,\r\n John <jdoe@one.test> (my (dear) \\) friend); (the end of the group)\r\n").unwrap();
+    assert_eq!(rem.len(), 0);
     assert_eq!(parsed, [Address::Group(Group{
         dname: "A Group".into(),
         members: vec![
             Mailbox { dname: Some("Chris Jones".into()),
-                      address: SMTPMailbox(DotAtom("c".into()).into(), dp("public.example"))},
+                      address: SMTPMailbox(DotAtom("c".into()).into(), dp("public.example")),
+                      comments: vec![Comment("Chris's host.".into())],
+                      obsolete: false},
             Mailbox { dname: None,
-                      address: SMTPMailbox(DotAtom("joe".into()).into(), dp("example.org"))},
+                      address: SMTPMailbox(DotAtom("joe".into()).into(), dp("example.org")),
+                      comments: vec![],
+                      obsolete: false},
             Mailbox { dname: Some("John".into()),
-                      address: SMTPMailbox(DotAtom("jdoe".into()).into(), dp("one.test"))},
-        ]
+                      address: SMTPMailbox(DotAtom("jdoe".into()).into(), dp("one.test")),
+                      comments: vec![Comment("my (dear) ) friend".into())],
+                      obsolete: false},
+        ],
+        comments: vec![Comment("Some people".into()), Comment("the end of the group".into())],
+        obsolete: false,
     })]);
 }
 
+#[test]
+fn empty_group_comments() {
+    let (rem, parsed) = reply_to::<Commented<Legacy>>(b"Undisclosed recipients: (none);\r\n").unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed, [Address::Group(Group{
+        dname: "Undisclosed recipients".into(),
+        members: vec![],
+        comments: vec![Comment("none".into())],
+        obsolete: false,
+    })]);
+}
+
+#[test]
+fn quoted_comment() {
+    let parsed = parse_single(from::<Commented<Intl>>, b"\"not (a comment)\" <\"(nor) this\"@[(1.2.3.4)]> (but this is)\r\n");
+    assert_eq!(parsed.dname, Some("not (a comment)".into()));
+    assert_eq!(parsed.comments, [Comment("but this is".into())]);
+
+    let parsed = parse_single(from::<Intl>, b"John <jdoe@one.test> (my dear friend)\r\n");
+    assert_eq!(parsed.comments, []);
+}
+
 #[test]
 fn multi_reply_to() {
     let (rem, parsed) = reply_to::<Intl>(b"Mary Smith <mary@x.test>, jdoe@example.org, Who? <one@y.test>\r\n").unwrap();
     assert_eq!(rem.len(), 0);
     assert_eq!(parsed, [
-        Address::Mailbox(Mailbox { dname: Some("Mary Smith".into()),
-                                   address: SMTPMailbox(DotAtom("mary".into()).into(), dp("x.test"))}),
-        Address::Mailbox(Mailbox { dname: None,
-                                   address: SMTPMailbox(DotAtom("jdoe".into()).into(), dp("example.org"))}),
-        Address::Mailbox(Mailbox { dname: Some("Who?".into()),
-                                   address: SMTPMailbox(DotAtom("one".into()).into(), dp("y.test"))}),
+        Address::Mailbox(Mailbox::new(Some("Mary Smith".into()), SMTPMailbox(DotAtom("mary".into()).into(), dp("x.test")))),
+        Address::Mailbox(Mailbox::new(None, SMTPMailbox(DotAtom("jdoe".into()).into(), dp("example.org")))),
+        Address::Mailbox(Mailbox::new(Some("Who?".into()), SMTPMailbox(DotAtom("one".into()).into(), dp("y.test")))),
     ]);
 }
 
@@ -104,6 +142,25 @@ fn intl_subject() {
     assert_eq!(parsed, "忍法写メ光飛ばし(笑)");
 }
 
+#[test]
+fn split_multibyte() {
+    let (rem, parsed) = unstructured::<Intl>(b"=?utf-8?Q?caf=C3?=\r\n =?UTF-8?Q?=A9?= =?x-sjis?B?lA==?= =?x-sjis?B?RQ==?=").unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed, "café忍");
+
+    let (_, parsed) = unstructured::<Intl>(b"=?utf-8?Q?caf=C3?= =?iso-8859-1?Q?=A9?=").unwrap();
+    assert_eq!(parsed, "caf\u{fffd}©");
+
+    let parsed = parse_single(from::<Intl>, b"=?utf-8?Q?caf=C3?= =?utf-8?Q?=A9?= <a@example.org>\r\n");
+    assert_eq!(parsed.dname, Some("café".into()));
+
+    #[cfg(feature = "quoted-string-rfc2047")]
+    {
+        let parsed = parse_single(from::<Intl>, b"\"=?utf-8?Q?caf=C3?= =?utf-8?Q?=A9?= au lait\" <a@example.org>\r\n");
+        assert_eq!(parsed.dname, Some("café au lait".into()));
+    }
+}
+
 #[test]
 fn direct_utf8() {
     let input = b"\xc3\xa9";
@@ -128,3 +185,113 @@ fn invalid_latin1() {
     assert_eq!(rem.len(), 0);
     assert_eq!(parsed, "\u{fffd}");
 }
+
+#[test]
+fn obs_local_part() {
+    let parsed = parse_single(from::<Obsolete<Intl>>, b"John . Doe @ example (the host) . org\r\n");
+    assert_eq!(parsed.address, SMTPMailbox(DotAtom("John.Doe".into()).into(), dp("example.org")));
+    assert!(parsed.obsolete);
+
+    let parsed = parse_single(from::<Obsolete<Intl>>, b"\"John Q\".Public@example.org\r\n");
+    assert_eq!(parsed.address, SMTPMailbox(QuotedString("John Q.Public".into()).into(), dp("example.org")));
+    assert!(parsed.obsolete);
+
+    assert!(from::<Intl>(b"John . Doe @ example . org\r\n").is_err());
+}
+
+#[test]
+fn obs_route() {
+    let parsed = parse_single(from::<Obsolete<Intl>>, b"Bob <@a.example,,@b.example:bob@c.example>\r\n");
+    assert_eq!(parsed.dname, Some("Bob".into()));
+    assert_eq!(parsed.address, SMTPMailbox(DotAtom("bob".into()).into(), dp("c.example")));
+    assert!(parsed.obsolete);
+
+    assert!(from::<Intl>(b"Bob <@a.example,@b.example:bob@c.example>\r\n").is_err());
+}
+
+#[test]
+fn obs_phrase() {
+    let parsed = parse_single(from::<Obsolete<Intl>>, b"John Q. Public <jqp@example.org>\r\n");
+    assert_eq!(parsed.dname, Some("John Q. Public".into()));
+    assert!(parsed.obsolete);
+
+    let parsed = parse_single(from::<Obsolete<Intl>>, b"J.R.R. Tolkien <jrrt@example.org>\r\n");
+    assert_eq!(parsed.dname, Some("J.R.R. Tolkien".into()));
+    assert!(parsed.obsolete);
+
+    let parsed = parse_single(from::<Obsolete<Intl>>, b"John Doe <jdoe@example.org>\r\n");
+    assert_eq!(parsed.dname, Some("John Doe".into()));
+    assert!(!parsed.obsolete);
+}
+
+#[test]
+fn obs_list() {
+    let (rem, parsed) = from::<Obsolete<Intl>>(b", a@example.org,, (nothing) ,b@example.org,\r\n").unwrap();
+    assert_eq!(rem.len(), 0);
+    let flags: Vec<_> = parsed.iter().map(|a| match a {
+        Address::Mailbox(m) => m.obsolete,
+        _ => unreachable!(),
+    }).collect();
+    assert_eq!(flags, [true, true]);
+
+    let (rem, parsed) = from::<Obsolete<Intl>>(b"a@example.org, b@example.org\r\n").unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed.len(), 2);
+    assert!(parsed.iter().all(|a| matches!(a, Address::Mailbox(Mailbox{obsolete: false, ..}))));
+}
+
+#[test]
+fn obs_group_list() {
+    let (rem, parsed) = reply_to::<Commented<Obsolete<Legacy>>>(b"Nobody: , (really) ,;\r\n").unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed, [Address::Group(Group{
+        dname: "Nobody".into(),
+        members: vec![],
+        comments: vec![Comment("really".into())],
+        obsolete: true,
+    })]);
+}
+
+#[test]
+fn recover_garbage() {
+    let input = b"Mary <mary@x.test>, a@b c@d (x, y), \"Who, me?\" <one@y.test>, <<>>, G: joe@example.org;, bad\r\n";
+    let (rem, parsed) = recover_address_list::<Intl>(input).unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed.addresses.len(), 3);
+    match &parsed.addresses[1] {
+        Address::Mailbox(m) => assert_eq!(m.dname, Some("Who, me?".into())),
+        _ => unreachable!(),
+    }
+    match &parsed.addresses[2] {
+        Address::Group(g) => assert_eq!(g.members.len(), 1),
+        _ => unreachable!(),
+    }
+    assert_eq!(parsed.skipped, [Skipped{offset: 20, content: b"a@b c@d (x, y)"},
+                                Skipped{offset: 61, content: b"<<>>"},
+                                Skipped{offset: 88, content: b"bad"}]);
+}
+
+#[test]
+fn recover_group() {
+    let input = b"a@example.org, Friends: good@example.org, broken@, \"x; y\" <z@example.org>;, c@example.org\r\n";
+    let (_, parsed) = recover_address_list::<Intl>(input).unwrap();
+    assert_eq!(parsed.addresses.len(), 2);
+    assert_eq!(parsed.skipped, [Skipped{offset: 15, content: b"Friends: good@example.org, broken@, \"x; y\" <z@example.org>;"}]);
+
+    // Without a closing ";" the group ends at its first comma.
+    let (_, parsed) = recover_address_list::<Intl>(b"G: broken@, b@example.org").unwrap();
+    assert_eq!(parsed.addresses.len(), 1);
+    assert_eq!(parsed.skipped, [Skipped{offset: 0, content: b"G: broken@"}]);
+}
+
+#[test]
+fn recover_unterminated() {
+    let (rem, parsed) = recover_address_list::<Intl>(b"a@example.org, \"unterminated, b@example.org").unwrap();
+    assert_eq!(rem.len(), 0);
+    assert_eq!(parsed.addresses.len(), 1);
+    assert_eq!(parsed.skipped, [Skipped{offset: 15, content: b"\"unterminated, b@example.org"}]);
+
+    let (_, parsed) = recover_address_list::<Intl>(b"").unwrap();
+    assert_eq!(parsed.addresses, []);
+    assert_eq!(parsed.skipped, []);
+}
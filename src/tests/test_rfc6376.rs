@@ -0,0 +1,63 @@
+use crate::rfc6376::*;
+
+const SIG: &str = "v=1; a=rsa-sha256; d=example.net; s=brisbane; c=relaxed/relaxed;\r
+ q=dns/txt; i=joe=40x@mail.example.net; t=1117574938; x=1118006938; l=200;\r
+ h=From:To:Subject:Date; bh=MTIzNDU2Nzg5MDEyMzQ1Njc4OTAxMjM0NTY3ODkwMTI=;\r
+ z=From:foo@eng.example.net|To:joe@example.com|Subject:demo=20run;\r
+ b=dzdVyOfAKCdLXdJOc9G2q8LoXSlEniSbav+yuU4zGeeruD00lszZVoG4ZHRNiYzR";
+
+fn parse(input: &str) -> Result<DKIMSignature, ()> {
+    dkim_signature(input.as_bytes()).map(|(rem, sig)| { assert_eq!(rem.len(), 0); sig }).map_err(|_| ())
+}
+
+fn tags(input: &str) -> Vec<(String, String)> {
+    let (rem, tags) = tag_list(input.as_bytes()).unwrap();
+    assert_eq!(rem.len(), 0);
+    tags
+}
+
+#[test]
+fn full() {
+    let sig = parse(SIG).unwrap();
+    assert_eq!(sig.body_canonicalization, Canonicalization::Relaxed);
+    assert_eq!(sig.identity.local_part, Some("joe@x".into()));
+    assert_eq!(sig.identity.domain.to_string(), "mail.example.net");
+    assert_eq!((sig.timestamp, sig.expiration, sig.body_length), (Some(1117574938), Some(1118006938), Some(200)));
+    assert_eq!(sig.query_methods, ["dns/txt"]);
+    assert_eq!(sig.body_hash, b"12345678901234567890123456789012");
+    assert_eq!(sig.copied_headers[2], ("Subject".into(), "demo run".into()));
+    assert_eq!(sig.copied_headers.len(), 3);
+    assert_eq!(sig.selector, "brisbane");
+}
+
+#[test]
+fn defaults() {
+    let sig = parse("v=1; a=ed25519-sha256; d=example.com; s=s; h=from; bh=; b=").unwrap();
+    assert_eq!(sig.algorithm, SigningAlgorithm::Ed25519Sha256);
+    assert_eq!(sig.header_canonicalization, Canonicalization::Simple);
+    assert_eq!(sig.identity, Identity { local_part: None, domain: sig.domain.clone() });
+    assert_eq!(sig.query_methods, ["dns/txt"]);
+    assert!(sig.copied_headers.is_empty());
+}
+
+#[test]
+fn whitespace() {
+    assert_eq!(tags(" a = b c ;\r\n\td=e\r\n f ;"),
+               [("a".into(), "b c".into()), ("d".into(), "e f".into())]);
+    assert_eq!(tags("a="), [("a".into(), "".into())]);
+}
+
+#[test]
+fn invalid() {
+    let base = "v=1; a=rsa-sha256; d=example.com; s=s; bh=; b=";
+    assert!(parse(base).is_err());
+    assert!(parse(&format!("{}; h=to", base)).is_err());
+    assert!(parse(&format!("{}; h=from; h=to", base)).is_err());
+    assert!(parse(&format!("{}; h=from; i=@example.org", base)).is_err());
+    assert!(parse(&format!("{}; h=from; i=@notexample.com", base)).is_err());
+    assert!(parse(&format!("{}; h=from; c=strict", base)).is_err());
+    assert!(parse(&format!("{}; h=from; l=-1", base)).is_err());
+    assert!(parse(&format!("{}; h=from; x=1; bh=!!", base)).is_err());
+    assert!(parse(&format!("{}; h=from", base).replace("v=1", "v=2")).is_err());
+    assert!(parse(&format!("{}; h=from; unknown=x", base)).is_ok());
+}
@@ -0,0 +1,138 @@
+use crate::behaviour::{Intl, Legacy};
+use crate::headerwriter::received_spf as write;
+use crate::rfc5321::ReversePath;
+use crate::rfc5322::Comment;
+use crate::rfc7208::*;
+use crate::rfc8601::AuthResult;
+
+fn parse(input: &str) -> Result<SPFRecord, ()> {
+    spf_record(input.as_bytes()).map(|(rem, r)| { assert_eq!(rem.len(), 0); r }).map_err(|_| ())
+}
+
+fn mechanisms(input: &str) -> Vec<Mechanism> {
+    parse(input).unwrap().directives.into_iter().map(|d| d.mechanism).collect()
+}
+
+fn ms(input: &str) -> MacroString {
+    input.parse().unwrap()
+}
+
+#[test]
+fn mechanisms_and_qualifiers() {
+    let record = parse("V=SPF1 +all -Include:_spf.example.com ~exists:%{i}.x.example.com ?ptr  ").unwrap();
+    let qualifiers: Vec<_> = record.directives.iter().map(|d| d.qualifier).collect();
+    assert_eq!(qualifiers, [Qualifier::Pass, Qualifier::Fail, Qualifier::SoftFail, Qualifier::Neutral]);
+    assert_eq!(record.directives[1].mechanism, Mechanism::Include(ms("_spf.example.com")));
+    assert_eq!(record.directives[3].mechanism, Mechanism::PTR(None));
+
+    assert_eq!(mechanisms("v=spf1 ip4:192.0.2.1 ip4:192.0.2.0/24 ip6:2001:db8::/32"), [
+        Mechanism::IP4 { address: "192.0.2.1".parse().unwrap(), cidr: None },
+        Mechanism::IP4 { address: "192.0.2.0".parse().unwrap(), cidr: Some(24) },
+        Mechanism::IP6 { address: "2001:db8::".parse().unwrap(), cidr: Some(32) },
+    ]);
+    assert!(parse("v=spf1").unwrap().directives.is_empty());
+}
+
+#[test]
+fn dual_cidr() {
+    assert_eq!(mechanisms("v=spf1 a mx/24 a//64 mx:example.org/30//96 a:%{d}/24"), [
+        Mechanism::A { domain: None, ip4_cidr: None, ip6_cidr: None },
+        Mechanism::MX { domain: None, ip4_cidr: Some(24), ip6_cidr: None },
+        Mechanism::A { domain: None, ip4_cidr: None, ip6_cidr: Some(64) },
+        Mechanism::MX { domain: Some(ms("example.org")), ip4_cidr: Some(30), ip6_cidr: Some(96) },
+        Mechanism::A { domain: Some(ms("%{d}")), ip4_cidr: Some(24), ip6_cidr: None },
+    ]);
+}
+
+#[test]
+fn modifiers() {
+    let record = parse("v=spf1 -all redirect=_spf.example.com exp=explain.%{d} moo.cow-far_out=%{l}").unwrap();
+    assert_eq!(record.redirect, Some(ms("_spf.example.com")));
+    assert_eq!(record.explanation, Some(ms("explain.%{d}")));
+    assert_eq!(record.modifiers, [("moo.cow-far_out".into(), ms("%{l}"))]);
+}
+
+#[test]
+fn macros() {
+    let parsed = ms("%{L1r-}%%%_%-x");
+    assert_eq!(parsed.0, [
+        MacroToken::Macro(Macro { letter: 'l', url_escape: true, digits: Some(1), reverse: true, delimiters: "-".into() }),
+        MacroToken::Literal("%".into()),
+        MacroToken::Literal(" ".into()),
+        MacroToken::Literal("%20".into()),
+        MacroToken::Literal("x".into()),
+    ]);
+
+    assert!("%{x}".parse::<MacroString>().is_err());
+    assert!("%{d0}".parse::<MacroString>().is_err());
+    assert!("%{d".parse::<MacroString>().is_err());
+    assert!("50%".parse::<MacroString>().is_err());
+    assert!("a b".parse::<MacroString>().is_err());
+
+    let (_, parsed) = explain_string(b"See %{d}").unwrap();
+    assert_eq!(parsed.0.len(), 3);
+}
+
+#[test]
+fn invalid() {
+    assert!(parse("v=spf2 -all").is_err());
+    assert!(spf_record(b"v=spf10 -all").map(|(rem, _)| rem.is_empty()) != Ok(true));
+    assert!(parse("v=spf1 foo").is_err());
+    assert!(parse("v=spf1 all:example.com").is_err());
+    assert!(parse("v=spf1 include").is_err());
+    assert!(parse("v=spf1 include:").is_err());
+    assert!(parse("v=spf1 ip4:192.0.2.1/33").is_err());
+    assert!(parse("v=spf1 ip4:192.0.2.1/024").is_err());
+    assert!(parse("v=spf1 ip6:2001:db8::/129").is_err());
+    assert!(parse("v=spf1 ip4:2001:db8::").is_err());
+    assert!(parse("v=spf1 a/33").is_err());
+    assert!(parse("v=spf1 redirect=a.example redirect=b.example").is_err());
+    assert!(parse("v=spf1 exp=%{z}").is_err());
+    assert_eq!(SPFRecord::from_terms(&["-all", ""]), Err("Empty term"));
+}
+
+fn parse_received(input: &[u8]) -> ReceivedSPF {
+    let (rem, parsed) = received_spf::<Intl>(input).unwrap();
+    assert_eq!(rem.len(), 0);
+    parsed
+}
+
+#[test]
+fn received_spf_rfc_examples() {
+    let parsed = parse_received(b"pass (mybox.example.org: domain of\r\n myname@example.com designates 192.0.2.1 as permitted sender)\r\n receiver=mybox.example.org; client-ip=192.0.2.1;\r\n envelope-from=\"myname@example.com\"; helo=foo.example.com;\r\n");
+    assert_eq!(parsed.comment, Some(Comment("mybox.example.org: domain of myname@example.com designates 192.0.2.1 as permitted sender".into())));
+    assert_eq!(parsed.receiver.as_deref(), Some("mybox.example.org"));
+
+    let parsed = parse_received(b"fail (mybox.example.org: domain of\r\n myname@example.com does not designate\r\n 192.0.2.1 as permitted sender)\r\n identity=mailfrom; client-ip=192.0.2.1;\r\n envelope-from=\"<myname@example.com>\";\r\n");
+    assert_eq!(parsed.result, AuthResult::Fail);
+    assert_eq!(parsed.identity.as_deref(), Some("mailfrom"));
+    assert_eq!(parsed.envelope_from, Some("<myname@example.com>".parse().unwrap()));
+}
+
+#[test]
+fn received_spf_values() {
+    let parsed = parse_received(b"SoftFail client-ip=2001:db8::1; envelope-from=<>; problem=\"DNS timeout\"; x-extra = Value; mechanism=-all");
+    assert_eq!(parsed.result, AuthResult::SoftFail);
+    assert_eq!(parsed.comment, None);
+    assert_eq!(parsed.client_ip, Some("2001:db8::1".parse().unwrap()));
+    assert_eq!(parsed.envelope_from, Some(ReversePath::Null));
+    assert_eq!(parsed.problem.as_deref(), Some("DNS timeout"));
+    assert_eq!(parsed.mechanism.as_deref(), Some("-all"));
+    assert_eq!(parsed.extensions, [("x-extra".to_string(), "Value".to_string())]);
+
+    let parsed = parse_received(b"none client-ip=192.0.2; envelope-from=\"\"");
+    assert_eq!(parsed.client_ip, None);
+    assert_eq!(parsed.envelope_from, Some(ReversePath::Null));
+
+    assert_eq!(parse_received(b"neutral").result, AuthResult::Neutral);
+    assert!(received_spf::<Intl>(b"; client-ip=192.0.2.1").is_err());
+}
+
+#[test]
+fn received_spf_write() {
+    let parsed = parse_received(b"temperror (lookup failed) client-ip=2001:db8::1; envelope-from=<>; helo=[192.0.2.1]; x-extra=\"a b\"");
+    let written = write::<Legacy>(&parsed);
+    assert_eq!(written, "Received-SPF: temperror (lookup failed) client-ip=\"2001:db8::1\";\r\n envelope-from=\"\"; helo=\"[192.0.2.1]\"; x-extra=\"a b\"\r\n");
+    assert_eq!(parse_received(&written.as_bytes()["Received-SPF:".len()..]), parsed);
+    assert_eq!(parsed.to_string(), "temperror (lookup failed) client-ip=\"2001:db8::1\"; envelope-from=\"\"; helo=\"[192.0.2.1]\"; x-extra=\"a b\"");
+}
@@ -0,0 +1,58 @@
+use crate::rfc7489::*;
+
+fn parse(input: &str) -> Result<DMARCRecord, ()> {
+    dmarc_record(input.as_bytes()).map(|(rem, r)| { assert_eq!(rem.len(), 0); r }).map_err(|_| ())
+}
+
+#[test]
+fn full() {
+    let record = parse("v=DMARC1; p=reject; sp=none; pct=20; adkim=s; aspf=s;\
+                        rua=mailto:agg@example.com,mailto:agg@example.org!50k;\
+                        ruf=mailto:fail@example.com!2m; fo=1:d:s; rf=afrf; ri=3600;").unwrap();
+
+    assert_eq!(record.policy, Policy::Reject);
+    assert_eq!(record.subdomain_policy, Some(Policy::None));
+    assert_eq!(record.percent, 20);
+    assert_eq!((record.dkim_alignment, record.spf_alignment), (Alignment::Strict, Alignment::Strict));
+    assert_eq!(record.aggregate_reports, [
+        ReportURI { uri: "mailto:agg@example.com".into(), max_size: None },
+        ReportURI { uri: "mailto:agg@example.org".into(), max_size: Some(50 * 1024) },
+    ]);
+    assert_eq!(record.failure_reports[0].max_size, Some(2 * 1024 * 1024));
+    assert_eq!(record.failure_options, [FailureOption::Any, FailureOption::DKIM, FailureOption::SPF]);
+    assert_eq!(record.report_formats, ["afrf"]);
+    assert_eq!(record.report_interval, 3600);
+}
+
+#[test]
+fn defaults() {
+    let record = parse("v=DMARC1; p=none").unwrap();
+    assert_eq!(record.subdomain_policy, None);
+    assert_eq!(record.percent, 100);
+    assert_eq!((record.dkim_alignment, record.spf_alignment), (Alignment::Relaxed, Alignment::Relaxed));
+    assert!(record.aggregate_reports.is_empty());
+    assert_eq!(record.failure_options, [FailureOption::All]);
+    assert_eq!(record.report_interval, 86400);
+
+    let record = parse("v=DMARC1; p=Quarantine; pct=101; adkim=x; fo=z; ri=soon; future=tag").unwrap();
+    assert_eq!(record.policy, Policy::Quarantine);
+    assert_eq!(record.percent, 100);
+    assert_eq!(record.dkim_alignment, Alignment::Relaxed);
+    assert_eq!(record.failure_options, [FailureOption::All]);
+    assert_eq!(record.report_interval, 86400);
+}
+
+#[test]
+fn missing_policy() {
+    assert_eq!(parse("v=DMARC1; rua=mailto:agg@example.com").unwrap().policy, Policy::None);
+    assert_eq!(parse("v=DMARC1; p=block; rua=mailto:agg@example.com").unwrap().policy, Policy::None);
+    assert!(parse("v=DMARC1; p=block").is_err());
+    assert!(parse("v=DMARC1; rua=agg@example.com").is_err());
+}
+
+#[test]
+fn invalid() {
+    assert!(parse("p=none; v=DMARC1").is_err());
+    assert!(parse("v=DMARC2; p=none").is_err());
+    assert!(parse("v=spf1 -all").is_err());
+}
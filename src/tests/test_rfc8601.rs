@@ -0,0 +1,68 @@
+use crate::behaviour::{Commented, Intl, Legacy};
+use crate::headerwriter::authentication_results as write;
+use crate::rfc5322::Comment;
+use crate::rfc8601::*;
+
+fn parse(input: &[u8]) -> AuthenticationResults {
+    let (rem, parsed) = authentication_results::<Intl>(input).unwrap();
+    assert_eq!(rem.len(), 0);
+    parsed
+}
+
+fn prop(ptype: &str, property: &str, value: &str) -> Property {
+    Property { ptype: ptype.into(), property: property.into(), value: value.into() }
+}
+
+#[test]
+fn no_result() {
+    let parsed = parse(b"example.org 1; none\r\n");
+    assert_eq!(parsed.authserv_id, "example.org");
+    assert_eq!(parsed.version, Some(1));
+    assert!(parsed.results.is_empty());
+    assert_eq!(write::<Legacy>(&parsed), "Authentication-Results: example.org 1; none\r\n");
+}
+
+#[test]
+fn multiple() {
+    let parsed = parse(b"example.com;\r\n spf=pass smtp.mailfrom=example.net;\r\n dkim/1=PASS header.d=example.net header.b=\"abc/12+\";\r\n dmarc=fail (p=reject) reason = \"policy failed\" header.from=example.net;");
+    assert_eq!(parsed.results.len(), 3);
+    assert_eq!(parsed.results[0].properties, [prop("smtp", "mailfrom", "example.net")]);
+    assert_eq!(parsed.results[1].method, "dkim");
+    assert_eq!(parsed.results[1].method_version, Some(1));
+    assert_eq!(parsed.results[1].result, AuthResult::Pass);
+    assert_eq!(parsed.results[1].properties[1], prop("header", "b", "abc/12+"));
+    assert_eq!(parsed.results[2].result, AuthResult::Fail);
+    assert_eq!(parsed.results[2].reason, Some("policy failed".into()));
+    assert!(parsed.results[2].comments.is_empty());
+
+    let line = write::<Legacy>(&parsed);
+    assert!(line.split("\r\n").all(|l| l.len() <= 78));
+    assert_eq!(parse(&line.as_bytes()["Authentication-Results:".len()..]), parsed);
+}
+
+#[test]
+fn addresses() {
+    let parsed = parse(b"example.com; auth=pass smtp.auth=sender@example.com; spf=pass smtp.mailfrom=\"a b\"@example.com");
+    assert_eq!(parsed.results[0].properties[0].value, "sender@example.com");
+    assert_eq!(parsed.results[1].result, AuthResult::Pass);
+    assert_eq!(parsed.to_string(), "example.com; auth=pass smtp.auth=sender@example.com; spf=pass smtp.mailfrom=\"a b\"@example.com");
+}
+
+#[test]
+fn comments() {
+    let input = b"mx.example.org (my server); spf=softfail (sender not authorized) smtp.mailfrom=example.net (from)";
+    let (_, parsed) = authentication_results::<Commented<Intl>>(input).unwrap();
+    assert_eq!(parsed.comments, [Comment("my server".into())]);
+    assert_eq!(parsed.results[0].result, AuthResult::SoftFail);
+    assert_eq!(parsed.results[0].comments, [Comment("sender not authorized".into()), Comment("from".into())]);
+    assert_eq!(parsed.to_string(), "mx.example.org (my server); spf=softfail smtp.mailfrom=example.net (sender not authorized) (from)");
+
+    assert!(parse(input).results[0].comments.is_empty());
+}
+
+#[test]
+fn other_result() {
+    let parsed = parse(b"example.org; x-custom=Discard");
+    assert_eq!(parsed.results[0].result, AuthResult::Other("discard".into()));
+    assert!(authentication_results::<Intl>(b"example.org; spf").is_err());
+}
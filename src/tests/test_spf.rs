@@ -0,0 +1,185 @@
+use crate::rfc8601::AuthResult;
+use crate::spf::*;
+use crate::types::{Domain, DomainPart, Mailbox};
+
+use super::Zone;
+
+// The example zone from RFC 7208 appendix A.
+fn zone(record: &str) -> Zone {
+    let mut zone = Zone::default();
+
+    zone.txt("example.com", record);
+    zone.a("example.com", "192.0.2.10");
+    zone.a("example.com", "192.0.2.11");
+    zone.a("amy.example.com", "192.0.2.65");
+    zone.a("bob.example.com", "192.0.2.66");
+    zone.a("mail-a.example.com", "192.0.2.129");
+    zone.a("mail-b.example.com", "192.0.2.130");
+    zone.a("mail-c.example.org", "192.0.2.140");
+    zone.a("mail-c.example.org", "2001:db8::140");
+    zone.mx.insert("example.com".into(), vec!["mail-a.example.com".into(), "mail-b.example.com".into()]);
+    zone.mx.insert("example.org".into(), vec!["mail-c.example.org".into()]);
+    zone.ptr.insert("10.0.0.4".parse().unwrap(), vec!["bob.example.com".into()]);
+
+    zone
+}
+
+fn sender() -> Mailbox {
+    Mailbox::from_smtp(b"strong-bad@example.com").unwrap()
+}
+
+fn check(zone: &Zone, ip: &str) -> SPFResult {
+    check_host(zone, ip.parse().unwrap(), &Domain::from_smtp(b"example.com").unwrap(), &sender(), "mx.example.com")
+}
+
+fn results(record: &str, ips: &[&str]) -> Vec<AuthResult> {
+    let zone = zone(record);
+    ips.iter().map(|ip| check(&zone, ip).result).collect()
+}
+
+#[test]
+fn mechanisms() {
+    use AuthResult::*;
+    let ips = ["192.0.2.10", "192.0.2.65", "192.0.2.129", "192.0.2.140", "10.0.0.4", "2001:db8::140"];
+
+    assert_eq!(results("v=spf1 +all", &ips), [Pass, Pass, Pass, Pass, Pass, Pass]);
+    assert_eq!(results("v=spf1 a -all", &ips), [Pass, Fail, Fail, Fail, Fail, Fail]);
+    assert_eq!(results("v=spf1 a:example.org -all", &ips), [Fail, Fail, Fail, Fail, Fail, Fail]);
+    assert_eq!(results("v=spf1 mx -all", &ips), [Fail, Fail, Pass, Fail, Fail, Fail]);
+    assert_eq!(results("v=spf1 mx:example.org -all", &ips), [Fail, Fail, Fail, Pass, Fail, Pass]);
+    assert_eq!(results("v=spf1 mx mx:example.org -all", &ips), [Fail, Fail, Pass, Pass, Fail, Pass]);
+    assert_eq!(results("v=spf1 mx/30 mx:example.org/30//126 -all", &ips), [Fail, Fail, Pass, Pass, Fail, Pass]);
+    assert_eq!(results("v=spf1 mx:example.org/30//126 -all", &["192.0.2.143", "192.0.2.144", "2001:db8::143", "2001:db8::144"]),
+               [Pass, Fail, Pass, Fail]);
+    assert_eq!(results("v=spf1 ptr -all", &ips), [Pass, Pass, Pass, Fail, Fail, Fail]);
+    assert_eq!(results("v=spf1 ip4:192.0.2.128/28 -all", &ips), [Fail, Fail, Pass, Pass, Fail, Fail]);
+    assert_eq!(results("v=spf1 ip6:2001:db8::/32 ~all", &ips), [SoftFail, SoftFail, SoftFail, SoftFail, SoftFail, Pass]);
+    assert_eq!(results("v=spf1 -ip4:192.0.2.10 ?a", &ips), [Fail, Neutral, Neutral, Neutral, Neutral, Neutral]);
+
+    // IPv4-mapped IPv6 addresses are checked as IPv4.
+    assert_eq!(results("v=spf1 a -all", &["::ffff:192.0.2.11"]), [Pass]);
+}
+
+#[test]
+fn include_and_redirect() {
+    let mut zone = self::zone("v=spf1 include:_spf.example.org -all");
+    zone.txt("_spf.example.org", "v=spf1 ip4:192.0.2.65 ?ip4:192.0.2.66 -all");
+    assert_eq!(check(&zone, "192.0.2.65").result, AuthResult::Pass);
+    assert_eq!(check(&zone, "192.0.2.66").result, AuthResult::Fail);
+    assert_eq!(check(&zone, "192.0.2.10").result, AuthResult::Fail);
+
+    let mut zone = self::zone("v=spf1 a redirect=_spf.example.org");
+    zone.txt("_spf.example.org", "v=spf1 ip4:192.0.2.65 ?all");
+    assert_eq!(check(&zone, "192.0.2.10").result, AuthResult::Pass);
+    assert_eq!(check(&zone, "192.0.2.65").result, AuthResult::Pass);
+    assert_eq!(check(&zone, "192.0.2.66").result, AuthResult::Neutral);
+
+    let zone = self::zone("v=spf1 include:nowhere.example.org -all");
+    assert_eq!(check(&zone, "192.0.2.10").reason, Some("Included domain has no SPF record"));
+    let zone = self::zone("v=spf1 redirect=nowhere.example.org");
+    assert_eq!(check(&zone, "192.0.2.10").result, AuthResult::PermError);
+}
+
+#[test]
+fn no_record() {
+    let mut zone = Zone::default();
+    zone.txt("example.com", "v=spf10 -all");
+    zone.txt("example.com", "some verification token");
+    assert_eq!(check(&zone, "192.0.2.1").result, AuthResult::None);
+    assert_eq!(check(&Zone::default(), "192.0.2.1").result, AuthResult::None);
+
+    let sender = Mailbox::from_smtp(b"bob@localhost").unwrap();
+    let res = check_host(&zone, "192.0.2.1".parse().unwrap(), &Domain::from_smtp(b"localhost").unwrap(), &sender, "localhost");
+    assert_eq!(res.result, AuthResult::None);
+}
+
+#[test]
+fn errors() {
+    let mut zone = self::zone("v=spf1 -all");
+    zone.txt("example.com", "v=spf1 +all");
+    assert_eq!(check(&zone, "192.0.2.1").reason, Some("Multiple SPF records"));
+
+    assert_eq!(check(&self::zone("v=spf1 foo:bar -all"), "192.0.2.1").reason, Some("Invalid SPF record"));
+    assert_eq!(check(&self::zone("v=spf1 a:%{c} -all"), "192.0.2.1").reason, Some("Invalid macro"));
+
+    let res = check(&self::zone("v=spf1 include:temp.example.net -all"), "192.0.2.1");
+    assert_eq!((res.result, res.reason), (AuthResult::TempError, Some("DNS lookup failed")));
+}
+
+#[test]
+fn limits() {
+    let mut zone = self::zone("v=spf1 include:l1.example.org -all");
+    for i in 1..12 {
+        zone.txt(&format!("l{}.example.org", i), &format!("v=spf1 include:l{}.example.org", i + 1));
+    }
+    assert_eq!(check(&zone, "192.0.2.1").reason, Some("Too many DNS lookups"));
+
+    let zone = self::zone("v=spf1 a:a.example.org a:b.example.org a:c.example.org -all");
+    assert_eq!(check(&zone, "192.0.2.1").reason, Some("Too many void lookups"));
+    let zone = self::zone("v=spf1 a:a.example.org a:b.example.org -all");
+    assert_eq!(check(&zone, "192.0.2.1").result, AuthResult::Fail);
+
+    let mut zone = self::zone("v=spf1 mx -all");
+    zone.mx.insert("example.com".into(), (0..11).map(|i| format!("mx{}.example.com", i)).collect());
+    assert_eq!(check(&zone, "192.0.2.1").reason, Some("Too many MX records"));
+
+    let zone = self::zone("v=spf1 a a a a a a a a a a -all");
+    assert_eq!(check(&zone, "192.0.2.1").result, AuthResult::Fail);
+    let zone = self::zone("v=spf1 a a a a a a a a a a a -all");
+    assert_eq!(check(&zone, "192.0.2.1").reason, Some("Too many DNS lookups"));
+}
+
+// Macro examples from RFC 7208 section 7.4.
+#[test]
+fn macros() {
+    let mut zone = Zone::default();
+    zone.txt("email.example.com", "v=spf1 exists:%{ir}.%{v}._spf.%{d2} exists:%{lr-}.lp._spf.%{d2} -all");
+    zone.a("3.2.0.192.in-addr._spf.example.com", "127.0.0.2");
+    zone.a("bad.strong.lp._spf.example.com", "127.0.0.2");
+    zone.a("1.0.b.c.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6._spf.example.com", "127.0.0.2");
+
+    let domain = Domain::from_smtp(b"email.example.com").unwrap();
+    let check = |sender: &str, ip: &str| {
+        check_host(&zone, ip.parse().unwrap(), &domain, &Mailbox::from_smtp(sender.as_bytes()).unwrap(), "mx.example.org").result
+    };
+
+    assert_eq!(check("nobody@email.example.com", "192.0.2.3"), AuthResult::Pass);
+    assert_eq!(check("nobody@email.example.com", "2001:db8::cb01"), AuthResult::Pass);
+    assert_eq!(check("strong-bad@email.example.com", "192.0.2.4"), AuthResult::Pass);
+    assert_eq!(check("nobody@email.example.com", "192.0.2.4"), AuthResult::Fail);
+}
+
+#[test]
+fn explanation() {
+    let mut zone = self::zone("v=spf1 mx -all exp=explain._spf.%{d}");
+    zone.txt("explain._spf.example.com", "%{i} is not one of %{d}'s designated mail servers. See %{v}/%{L}");
+
+    assert_eq!(check(&zone, "192.0.2.1").explanation.as_deref(),
+               Some("192.0.2.1 is not one of example.com's designated mail servers. See in-addr/strong-bad"));
+    assert_eq!(check(&zone, "192.0.2.129").explanation, None);
+
+    let zone = self::zone("v=spf1 -all exp=missing.example.com");
+    let res = check(&zone, "192.0.2.1");
+    assert_eq!((res.result, res.explanation), (AuthResult::Fail, None));
+}
+
+#[test]
+fn envelope() {
+    use crate::behaviour::Intl;
+    use crate::rfc5321::{ehlo_command, mail_command};
+
+    let mut zone = self::zone("v=spf1 a -all");
+    zone.txt("mail-a.example.com", "v=spf1 a -all");
+
+    let (_, helo) = ehlo_command::<Intl>(b"EHLO mail-a.example.com\r\n").unwrap();
+    let (_, (path, _)) = mail_command::<Intl>(b"MAIL FROM:<joe@example.com>\r\n").unwrap();
+    let (_, (null, _)) = mail_command::<Intl>(b"MAIL FROM:<>\r\n").unwrap();
+    let ip = "192.0.2.129".parse().unwrap();
+
+    assert_eq!(check_mail_from(&zone, ip, &path, &helo).result, AuthResult::Fail);
+    assert_eq!(check_mail_from(&zone, ip, &null, &helo).result, AuthResult::Pass);
+    assert_eq!(check_helo(&zone, ip, &helo).result, AuthResult::Pass);
+
+    let literal = DomainPart::from_smtp(b"[192.0.2.129]").unwrap();
+    assert_eq!(check_helo(&zone, ip, &literal).result, AuthResult::None);
+}